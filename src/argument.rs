@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(Debug, Parser)]
 #[command(version, author, about)]
@@ -6,7 +6,10 @@ pub struct Arg {
     /// mode
     #[command(subcommand)]
     pub md : Mode,
-    /// kifu directory
+    /// number of engine processes running at once.
+    #[arg(long, short, global = true, default_value_t = 1)]
+    pub jobs : usize,
+    /// kifu directory, .tar or .tar.zst archive
     #[arg(long, global = true, value_delimiter=',')]
     pub kifudir : Vec<String>,
     /// show progressbar
@@ -21,9 +24,70 @@ pub struct Arg {
     /// output directory
     #[arg(short, long, global = true)]
     pub output : Option<String>,
-    /// ruversi config file
+    /// also output the positions along the principal variations (kifu, mate).
+    #[arg(long, global = true, default_value_t = false)]
+    pub pv_samples : bool,
+    /// also output the scores of all the moves for each position (kifu, mate).
+    #[arg(long, global = true, default_value_t = false)]
+    pub policy : bool,
+    /// add the softmax policy of this temperature to --policy output.
+    #[arg(long, global = true, value_parser = parse_temperature)]
+    pub policy_temperature : Option<f32>,
+    /// how many plies spread expands from the kifu positions.
+    #[arg(long, global = true, default_value_t = 1)]
+    pub spread_depth : usize,
+    /// number of playouts in generate mode.
+    #[arg(long, global = true, default_value_t = 1000)]
+    pub playouts : usize,
+    /// random seed of generate and rollout mode.
+    #[arg(long, global = true, default_value_t = 0)]
+    pub seed : u64,
+    /// probability of a random move in generate mode. the engine's best move otherwise.
+    /// all the moves are random if not given.
+    #[arg(long, global = true)]
+    pub epsilon : Option<f64>,
+    /// number of random games per position in rollout mode.
+    #[arg(long, global = true, default_value_t = 100, value_parser = parse_rollouts)]
+    pub rollouts : usize,
+    /// how rollout mode chooses moves.
+    #[arg(long, global = true, value_enum, default_value_t = RolloutPolicy::Random)]
+    pub rollout_policy : RolloutPolicy,
+    /// fewest empties of the positions midgame mode extracts.
+    #[arg(long, global = true)]
+    pub min_empties : Option<u32>,
+    /// most empties of the positions midgame mode extracts.
+    #[arg(long, global = true)]
+    pub max_empties : Option<u32>,
+    /// extract only the positions in this phase (0 ~ 2) in midgame mode.
+    #[arg(long, global = true)]
+    pub progress : Option<usize>,
+    /// multiply the game result by this for each ply to the end in midgame mode.
+    #[arg(long, global = true)]
+    pub discount : Option<f32>,
+    /// output format of positions.
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    pub format : Format,
+    /// also output the engine value as a third column.
+    #[arg(long, global = true, default_value_t = false)]
+    pub value_column : bool,
+    /// how to make an integer label from a fractional engine value.
+    #[arg(long, global = true, value_enum, default_value_t = Rounding::Truncate)]
+    pub rounding : Rounding,
+    /// engine config file
     #[arg(long, global = true)]
     pub ru_config : Option<String>,
+    /// give up an engine call after this many seconds. overrides the config.
+    #[arg(long, global = true)]
+    pub timeout : Option<f64>,
+    /// number of retries before a position is quarantined.
+    #[arg(long, global = true, default_value_t = 2)]
+    pub retries : usize,
+    /// profile in the engine config file.
+    #[arg(long, global = true)]
+    pub engine_profile : Option<String>,
+    /// use built-in endgame solver regardless of the engine in the config.
+    #[arg(long, global = true, default_value_t = false)]
+    pub solver : bool,
     /// show details
     #[arg(long, global = true, default_value_t=false)]
     pub verbose : bool,
@@ -43,4 +107,121 @@ pub enum Mode {
     Shorten,
     /// Validate rfen and score
     Validate,
+    /// Replay kifu files and report broken ones
+    Lint,
+    /// Check mateN scores against the best child in mate(N-1)
+    Consistency,
+    /// Play random playouts down to the given empties
+    Generate,
+    /// Add Monte-Carlo rollout statistics to rfen,score files
+    Rollout,
+    /// Extract kifu positions in an empties window labelled with the game results
+    Midgame,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Format {
+    /// "rfen,score" lines
+    Text,
+    /// fixed-size binary records
+    Binary,
+}
+
+impl Format {
+    /// 出力ファイルの拡張子
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Text => "txt",
+            Format::Binary => "bin",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Rounding {
+    /// toward zero. -1.62 -> -1, 0.9 -> 0
+    Truncate,
+    /// to the nearest. -1.62 -> -2, 0.4 -> 0
+    Round,
+    /// away from zero keeping the sign. -1.62 -> -2, 0.3 -> 1, 0 -> 0
+    FloorBySign,
+}
+
+impl Rounding {
+    /// 評価値を石の差にする。
+    pub fn apply(&self, value : f32) -> i8 {
+        let v = match self {
+            Rounding::Truncate => value.trunc(),
+            Rounding::Round => value.round(),
+            Rounding::FloorBySign => value.abs().ceil().copysign(value),
+        };
+        v.clamp(-64.0, 64.0) as i8
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum RolloutPolicy {
+    /// every legal move equally
+    Random,
+    /// moves leaving the opponent fewer moves more often
+    Mobility,
+}
+
+/// softmaxの温度。0で割らないように正の有限の値だけ受け付ける。
+fn parse_temperature(txt : &str) -> Result<f32, String> {
+    let t = txt.parse::<f32>().map_err(|e| e.to_string())?;
+    if t.is_finite() && t > 0.0 {
+        Ok(t)
+    } else {
+        Err(format!("{txt} is not a positive number"))
+    }
+}
+
+/// rolloutの対局数。0局では統計にならないので1以上だけ受け付ける。
+fn parse_rollouts(txt : &str) -> Result<usize, String> {
+    match txt.parse::<usize>().map_err(|e| e.to_string())? {
+        0 => Err(String::from("at least 1 game is needed")),
+        k => Ok(k),
+    }
+}
+
+#[test]
+fn test_rounding() {
+    let tbl = [
+        (-1.62, -1, -2, -2),
+        (0.9, 0, 1, 1),
+        (0.3, 0, 0, 1),
+        (-0.3, 0, 0, -1),
+        (0.0, 0, 0, 0),
+        (2.5, 2, 3, 3),
+        (-64.0, -64, -64, -64),
+        (100.0, 64, 64, 64),
+    ];
+    for (value, trunc, round, sign) in tbl {
+        assert_eq!(Rounding::Truncate.apply(value), trunc, "{value}");
+        assert_eq!(Rounding::Round.apply(value), round, "{value}");
+        assert_eq!(Rounding::FloorBySign.apply(value), sign, "{value}");
+    }
+}
+
+#[test]
+fn test_policy_temperature() {
+    assert_eq!(parse_temperature("0.5"), Ok(0.5));
+    for txt in ["0", "-1", "inf", "NaN", "abc"] {
+        assert!(parse_temperature(txt).is_err(), "{txt}");
+    }
+    let arg = Arg::try_parse_from(["incuversi", "kifu", "--policy-temperature", "2"]).unwrap();
+    assert_eq!(arg.policy_temperature, Some(2.0));
+    assert!(Arg::try_parse_from(["incuversi", "kifu", "--policy-temperature", "0"]).is_err());
+}
+
+#[test]
+fn test_rollouts() {
+    assert_eq!(parse_rollouts("20"), Ok(20));
+    for txt in ["0", "-1", "abc"] {
+        assert!(parse_rollouts(txt).is_err(), "{txt}");
+    }
+    let arg = Arg::try_parse_from(["incuversi", "rollout"]).unwrap();
+    assert_eq!(arg.rollouts, 100);
+    assert!(Arg::try_parse_from(["incuversi", "rollout", "--rollouts", "0"]).is_err());
 }
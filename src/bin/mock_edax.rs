@@ -0,0 +1,93 @@
+//! テスト用の偽Edax。
+//!
+//! `-solve <obf>`の局面を全部読んで、Edaxと同じ形式で1局面1行ずつ出力する。
+//! 石の差は組み込みの完全読みの結果で、空きマスが多すぎて読めない局面は石の差をそのまま使う。
+//!
+//! - `-solve <obf>` 局面を1行に1つ書いたファイル
+//! - 環境変数`MOCK_EDAX_LOG`があるとそのファイルに起動する毎に1行追記する
+//! - 環境変数`MOCK_EDAX_CRASH_AFTER=<n>`があるとn局面より多い時にn局面だけ出力して失敗する
+//! - 他の引数は無視する
+
+#[allow(dead_code)]
+#[path = "../bitboard.rs"]
+mod bitboard;
+#[allow(dead_code)]
+#[path = "../solver.rs"]
+mod solver;
+
+use bitboard::BitBoard;
+use std::io::Write;
+
+/// 黒から見た値
+fn value(ban : &BitBoard) -> i8 {
+    if ban.nblank() <= solver::MAX_EMPTIES {
+        solver::solve(ban)
+    } else {
+        ban.count()
+    }
+}
+
+fn pos(mv : u8) -> String {
+    if mv == bitboard::PASS {return String::from("ps");}
+
+    let (x, y) = bitboard::cell2xy(mv);
+    format!("{}{y}", "0abcdefgh".chars().nth(x as usize).unwrap())
+}
+
+/// (手番から見た石の差, 最善手)
+fn solve(ban : &BitBoard) -> (i8, String) {
+    let moves = ban.genmove().unwrap_or_default();
+    let best = moves.iter().map(|&mv| {
+        (value(&ban.r#move(mv).unwrap()) * ban.teban, mv)
+    }).max_by_key(|(v, _)| *v);
+    match best {
+        Some((v, mv)) => (v, pos(mv)),
+        None => (ban.count() * ban.teban, String::from("--")),
+    }
+}
+
+fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(log) = std::env::var_os("MOCK_EDAX_LOG") {
+        let mut f = std::fs::OpenOptions::new().create(true).append(true)
+            .open(log).unwrap();
+        writeln!(f, "{}", args.join(" ")).unwrap();
+    }
+    let obf = match args.iter().position(|a| a == "-solve") {
+        Some(i) if i + 1 < args.len() => args[i + 1].clone(),
+        _ => {
+            eprintln!("-solve is required.");
+            std::process::exit(1);
+        },
+    };
+    let txt = match std::fs::read_to_string(&obf) {
+        Ok(txt) => txt,
+        Err(e) => {
+            eprintln!("{e} : {obf}");
+            std::process::exit(1);
+        },
+    };
+    let bans = match txt.lines().filter(|l| !l.trim().is_empty())
+            .map(|l| BitBoard::from_obf(l.trim_end_matches(';')))
+            .collect::<Result<Vec<_>, _>>() {
+        Ok(bans) => bans,
+        Err(msg) => {
+            eprintln!("{msg}");
+            std::process::exit(1);
+        },
+    };
+    let crash = std::env::var("MOCK_EDAX_CRASH_AFTER").ok()
+        .and_then(|n| n.parse::<usize>().ok()).filter(|&n| n < bans.len());
+
+    println!(" # | depth|score|       time   |  nodes (N)  |   N/s      | principal variation");
+    println!("---+------+-----+--------------+-------------+------------+---------------------");
+    for (i, ban) in bans.iter().enumerate() {
+        if Some(i) == crash {
+            eprintln!("mock crash.");
+            std::process::exit(2);
+        }
+        let (score, best) = solve(ban);
+        println!("{:3}|   {:2}   {score:+03}        0:00.001            1         1000 {best}",
+            i + 1, ban.nblank());
+    }
+}
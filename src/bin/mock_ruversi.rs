@@ -0,0 +1,141 @@
+//! テスト用の偽ruversi。
+//!
+//! ruversiと同じ形式で出力する。評価値は組み込みの完全読みの結果なので毎回同じになる。
+//! 空きマスが多すぎて読めない局面は石の差を評価値にする。
+//!
+//! - `--rfen <rfen>` 局面
+//! - `--children` 子供の局面を全部出力する
+//! - `--mock-fail` 何も出力せずに失敗する
+//! - `--mock-crash` 子供の局面を半分(切り上げ)だけ出力してから失敗する
+//! - `--mock-sleep <msec>` 出力する前に待つ
+//! - `--mock-offset <value>` 子供の局面の評価値に足す。小数の評価値を試す用。
+//! - 環境変数`MOCK_RUVERSI_FAIL`があると`--mock-fail`と同じ
+//! - 他の引数は無視する
+
+#[allow(dead_code)]
+#[path = "../bitboard.rs"]
+mod bitboard;
+#[allow(dead_code)]
+#[path = "../solver.rs"]
+mod solver;
+
+use bitboard::BitBoard;
+
+/// 黒から見た値
+fn value(ban : &BitBoard) -> i8 {
+    if ban.nblank() <= solver::MAX_EMPTIES {
+        solver::solve(ban)
+    } else {
+        ban.count()
+    }
+}
+
+fn pos(mv : u8) -> String {
+    if mv == bitboard::PASS {return String::from("PS");}
+
+    let (x, y) = bitboard::cell2xy(mv);
+    format!("{}{y}", "0abcdefgh".chars().nth(x as usize).unwrap())
+}
+
+/// 最善を尽くした読み筋。読み切れない局面では空。
+fn pv(ban : &BitBoard) -> String {
+    let mut ret = String::new();
+    let mut cur = ban.clone();
+    while cur.nblank() <= solver::MAX_EMPTIES {
+        let Some(moves) = cur.genmove() else {break;};
+        // 両者打てなければ終局
+        if cur.mobility() == 0
+                && cur.r#move(bitboard::PASS).unwrap().mobility() == 0 {
+            break;
+        }
+
+        let vals = moves.iter().map(|&mv| {
+            let child = cur.r#move(mv).unwrap();
+            (value(&child), mv, child)
+        });
+        let best = if cur.teban == bitboard::GOTE {
+            vals.min_by_key(|(v, _, _)| *v)
+        } else {
+            vals.max_by_key(|(v, _, _)| *v)
+        }.unwrap();
+        ret += &pos(best.1);
+        cur = best.2;
+    }
+    ret
+}
+
+/// 盤面の表示の代わり。行数を合わせるためだけに出力する。
+fn print_board(ban : &BitBoard) {
+    println!("{}", ban.to_obf());
+    println!("  |a |b |c |d |e |f |g |h |");
+    for y in 1..=8 {
+        println!("{y} |__|__|__|__|__|__|__|__|");
+    }
+    println!("  +--+--+--+--+--+--+--+--+");
+    println!("{}'s turn.", if ban.teban == bitboard::SENTE {"@@"} else {"[]"});
+}
+
+fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(i) = args.iter().position(|a| a == "--mock-sleep") {
+        let msec = args.get(i + 1).and_then(|a| a.parse::<u64>().ok()).unwrap_or(0);
+        std::thread::sleep(std::time::Duration::from_millis(msec));
+    }
+    if args.iter().any(|a| a == "--mock-fail")
+            || std::env::var_os("MOCK_RUVERSI_FAIL").is_some() {
+        eprintln!("mock failure.");
+        std::process::exit(1);
+    }
+    let rfen = match args.iter().position(|a| a == "--rfen") {
+        Some(i) if i + 1 < args.len() => args[i + 1].clone(),
+        _ => {
+            eprintln!("--rfen is required.");
+            std::process::exit(1);
+        },
+    };
+    let ban = match BitBoard::try_from(rfen.as_str()) {
+        Ok(ban) => ban,
+        Err(msg) => {
+            eprintln!("{msg}");
+            std::process::exit(1);
+        },
+    };
+    let children = args.iter().any(|a| a == "--children");
+    let offset = args.iter().position(|a| a == "--mock-offset")
+        .and_then(|i| args.get(i + 1)).and_then(|a| a.parse::<f32>().ok()).unwrap_or(0.0);
+
+    print_board(&ban);
+    let moves = ban.genmove().unwrap_or_default();
+    let vals = moves.iter().map(|&mv| {
+        let child = ban.r#move(mv).unwrap();
+        let val = value(&child);
+        (mv, child, val)
+    }).collect::<Vec<_>>();
+    let best = if ban.teban == bitboard::GOTE {
+        vals.iter().min_by_key(|(_, _, v)| *v)
+    } else {
+        vals.iter().max_by_key(|(_, _, v)| *v)
+    };
+    let (bestpos, bestval) = match best {
+        Some((mv, _, v)) => (pos(*mv), *v),
+        None => (String::from("PS"), ban.count()),
+    };
+
+    if children {
+        for (_, child, val) in vals.iter() {
+            println!("{child},{val}");
+        }
+    }
+    println!("val:{:.4} {} nodes. {bestpos} 0msec", bestval as f32, vals.len());
+    if children {
+        let crash = args.iter().any(|a| a == "--mock-crash");
+        let n = if crash {vals.len().div_ceil(2)} else {vals.len()};
+        for (_, child, val) in vals.iter().take(n) {
+            println!("val,{:.2},{child},1 nodes. {}", *val as f32 + offset, pv(child));
+        }
+        if crash {
+            eprintln!("mock crash.");
+            std::process::exit(2);
+        }
+    }
+}
@@ -23,11 +23,6 @@ const STR_NUM : &str = "012345678";
 pub const STONE_SENTE : &str = "@@";
 pub const STONE_GOTE : &str = "[]";
 pub const LSB_CELL : u64 = 0x0000000000000001;
-const LT_CELL : u64 = LSB_CELL;
-const RT_CELL : u64 = 0x0000000000000080;
-const LB_CELL : u64 = 0x0100000000000000;
-const RB_CELL : u64 = 0x8000000000000000;
-const CORNER_CELL : u64 = 0x8100000000000081;
 const GUARD_RIGHT : u64 = 0xfefefefefefefefe;
 const GUARD_LEFT : u64 = 0x7f7f7f7f7f7f7f7f;
 const GUARD_VERTICAL : u64 = 0x7e7e7e7e7e7e7e7e;
@@ -122,26 +117,6 @@ const TBLCHKREV : [i8 ; 16130] = [
 0,0,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,
 ];
 
-// ビットが立っているところに相手の石があったら着手可能かもしれない
-const TBL_SURROUND : [u64 ; CELL_2D] = [
-    // y0
-    0x0302u64, 0x0604u64, 0x0705u64 << 1, 0x0705u64 << 2, 0x0705u64 << 3, 0x0705u64 << 4, 0x6020u64, 0xC040u64,
-    // y1
-    0x030200u64, 0x060400u64, 0x070500u64 << 1, 0x070500u64 << 2, 0x070500u64 << 3, 0x070500u64 << 4, 0x602000u64, 0xC04000u64,
-    // y2
-    0x030203u64 << 8, 0x060406u64 << 8, 0x070507u64 << 9, 0x070507u64 << 10, 0x070507u64 << 11, 0x070507u64 << 12, 0x030103u64 << 13, 0xC040C0u64 << 8,
-    // y3
-    0x030203u64 << 16, 0x060406u64 << 16, 0x070507u64 << 17, 0x070507u64 << 18, 0x070507u64 << 19, 0x070507u64 << 20, 0x030103u64 << 21, 0xC040C0u64 << 16,
-    // y4
-    0x030203u64 << 24, 0x060406u64 << 24, 0x070507u64 << 25, 0x070507u64 << 26, 0x070507u64 << 27, 0x070507u64 << 28, 0x030103u64 << 29, 0xC040C0u64 << 24,
-    // y5
-    0x030203u64 << 32, 0x060406u64 << 32, 0x070507u64 << 33, 0x070507u64 << 34, 0x070507u64 << 35, 0x070507u64 << 36, 0x030103u64 << 37, 0xC040C0u64 << 32,
-    // y6
-    0x000203u64 << 40, 0x000406u64 << 40, 0x000507u64 << 41, 0x000507u64 << 42, 0x000507u64 << 43, 0x000507u64 << 44, 0x000103u64 << 45, 0x0040C0u64 << 40,
-    // y7
-    0x0203u64 << 48, 0x0406u64 << 48, 0x0507u64 << 49, 0x0507u64 << 50, 0x0507u64 << 51, 0x0507u64 << 52, 0x0103u64 << 53, 0x40C0u64 << 48,
-];
-
 macro_rules! bit_left {
     ($x : expr) => {
         $x >>= 1;
@@ -248,6 +223,127 @@ pub fn cell(x: u8, y: u8) -> u8 {
 }
 
 
+/// 左詰めシフトのKogge-Stone fill。
+/// mineからproを通って伸ばせるところを求める。
+#[inline]
+fn fill_shl(mine : u64, pro : u64, sft : u32) -> u64 {
+    let mut gen = mine;
+    let mut pro = pro;
+    gen |= pro & (gen << sft);
+    pro &= pro << sft;
+    gen |= pro & (gen << (sft * 2));
+    pro &= pro << (sft * 2);
+    gen |= pro & (gen << (sft * 4));
+    gen
+}
+
+/// 右詰めシフトのKogge-Stone fill。
+#[inline]
+fn fill_shr(mine : u64, pro : u64, sft : u32) -> u64 {
+    let mut gen = mine;
+    let mut pro = pro;
+    gen |= pro & (gen >> sft);
+    pro &= pro >> sft;
+    gen |= pro & (gen >> (sft * 2));
+    pro &= pro >> (sft * 2);
+    gen |= pro & (gen >> (sft * 4));
+    gen
+}
+
+/// 着手可能なマスを8方向まとめて求める。
+///
+/// # Arguments
+/// - mine : 手番側の石
+/// - oppo : 相手の石
+///
+/// # Returns
+/// 着手可能なマスのbitが立った値
+pub fn mobility_of(mine : u64, oppo : u64) -> u64 {
+    let empty = !(mine | oppo);
+    // 左右と斜めは端を越えないように相手の石を削っておく
+    let horz = oppo & GUARD_VERTICAL;
+    let mut moves = 0;
+    for (sft, pro) in [(1, horz), (NUMCELL as u32, oppo),
+                       (NUMCELL as u32 - 1, horz), (NUMCELL as u32 + 1, horz)] {
+        moves |= ((fill_shl(mine, pro, sft) & pro) << sft)
+               | ((fill_shr(mine, pro, sft) & pro) >> sft);
+    }
+    moves & empty
+}
+
+/// 対称変換の数(回転と鏡反転)
+pub const N_SYMMETRY : u8 = 8;
+
+/// `BitBoard::canonical()`のキー。(black, white, teban)
+pub type CanonicalKey = (u64, u64, i8);
+
+/// 盤面の対称変換
+///
+/// - sym : 0:そのまま, 1:90度回転, 2:180度回転, 3:270度回転,
+///   4:左右反転, 5:上下反転, 6:対角反転, 7:反対角反転
+/// - colorflip : 石の色と手番を入れ替えるかどうか。
+///   入れ替えた場合は最終結果の符号も反転する。
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Transform {
+    pub sym : u8,
+    pub colorflip : bool,
+}
+
+impl Transform {
+    #[allow(dead_code)]
+    pub const IDENTITY : Transform = Transform {sym : 0, colorflip : false};
+
+    /// 全ての変換。順番は`BitBoard::rotated_mirrored()`と同じ。
+    pub fn all() -> Vec<Transform> {
+        [false, true].iter().flat_map(|&colorflip| {
+            (0..N_SYMMETRY).map(move |sym| Transform {sym, colorflip})
+        }).collect()
+    }
+
+    pub fn apply(&self, ban : &BitBoard) -> BitBoard {
+        let b = match self.sym {
+            0 => ban.clone(),
+            1 => ban.rotate90(),
+            2 => ban.rotate180(),
+            3 => ban.rotate180().rotate90(),
+            4 => ban.flip_horz(),
+            5 => ban.flip_vert(),
+            6 => ban.flip_diag(),
+            7 => ban.flip_antidiag(),
+            _ => panic!("unknown symmetry {}", self.sym),
+        };
+        if self.colorflip {b.flip_all()} else {b}
+    }
+
+    /// 変換後の局面の最終結果に変換する。
+    pub fn apply_score(&self, score : i8) -> i8 {
+        if self.colorflip {-score} else {score}
+    }
+
+    /// 変換後のマスの番号。PASSはPASSのまま。
+    pub fn apply_cell(&self, cell : u8) -> u8 {
+        if cell == PASS {return PASS;}
+
+        let ban = BitBoard {black : LSB_CELL << cell, white : 0, teban : SENTE, pass : 0};
+        let b = self.apply(&ban);
+        (b.black | b.white).trailing_zeros() as u8
+    }
+
+    /// 逆変換
+    pub fn inverse(&self) -> Transform {
+        let sym = match self.sym {
+            1 => 3,
+            3 => 1,
+            s => s,
+        };
+        Transform {sym, colorflip : self.colorflip}
+    }
+}
+
+/// 学習用の局面の情報(Bitboard, 確定石(黒), 確定石(白), 石の差, 評価値)。
+/// 評価値はエンジンが返した小数の値。石の差はそれを丸めたもの。
+pub type Sample = (BitBoard, i8, i8, i8, f32);
+
 #[derive(PartialEq, Clone)]
 pub struct BitBoard {
     pub black: u64,
@@ -1197,47 +1293,107 @@ impl BitBoard {
         Ok(ban)
     }
 
+    /// 手番側の着手可能なマス
+    pub fn mobility(&self) -> u64 {
+        let (mine, oppo) = if self.teban == SENTE {
+            (self.black, self.white)
+        } else {
+            (self.white, self.black)
+        };
+        mobility_of(mine, oppo)
+    }
+
     /// # Returns
     /// - None : no empty cells.
     /// - Some(vec![PASS])  : no available cells. pass.
     /// - Some(Vec![n]) : available cells.
     #[allow(dead_code)]
     pub fn genmove(&self) -> Option<Vec<u8>> {
-        let stones = self.black | self.white;
-        if stones == u64::MAX {return None;}
+        if self.is_full() {return None;}
 
+        let bits = self.mobility();
+        if bits == 0 {  // pass
+            return Some(vec![PASS]);
+        }
+
+        Some(cells2vec(bits))
+    }
+
+    /// 1手前の局面を全部返す。`genmove()`の逆。
+    ///
+    /// 置いた石を取り除いて裏返った石を戻す。裏返った石の数は方向毎に全通り試す。
+    /// 手番側が打てない局面はパスする前の局面も返す。
+    /// 初期配置の4マスには打てないので、そこを空けた局面は返さない。
+    /// パスの回数は戻せないので1手前の局面のpassは`self.pass - 1`か0にする。
+    ///
+    /// # Returns
+    /// (1手前の局面, その局面で打った手)の配列。パスの手はPASS。
+    #[allow(dead_code)]
+    pub fn unmoves(&self) -> Vec<(BitBoard, u8)> {
+        const CENTER : u64 = 0x0000001818000000;
+        const DIRS : [(i8, i8) ; 8] =
+            [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)];
+        let mut ret = Vec::new();
+        if self.teban == BLANK {return ret;}
+
+        let pass = if self.pass > 0 {self.pass - 1} else {0};
+        // パス。手番側も相手も打てないなら終局していたのでパスはしない。
+        let mut passed = self.clone();
+        passed.flipturn();
+        passed.pass = pass;
+        if !passed.is_full() && passed.mobility() == 0 && self.mobility() != 0 {
+            ret.push((passed, PASS));
+        }
+
+        // 打った側は手番でない方
         let (mine, oppo) = if self.teban == SENTE {
-            (self.black, self.white)
-        } else {
             (self.white, self.black)
+        } else {
+            (self.black, self.white)
         };
-
-        let mut bits = 0;
-        let mut bit = LSB_CELL;
-        for y in 0..NUMCELL {
-            for x in 0..NUMCELL {
-                let b = bit;
-                bit_right!(bit);
-                let exist = b & stones;
-                if exist != 0 {
-                    continue;
+        let mut cells = mine & !CENTER;
+        while cells != 0 {
+            let xy = cells.trailing_zeros() as u8;
+            cells &= cells - 1;
+            let (x, y) = ((xy % 8) as i8, (xy / 8) as i8);
+            // 方向毎に裏返ったかもしれない石。最後の1つは挟んだ石なので除く。
+            let lines = DIRS.iter().map(|(dx, dy)| {
+                let mut line = Vec::new();
+                let (mut cx, mut cy) = (x + dx, y + dy);
+                while (0..8).contains(&cx) && (0..8).contains(&cy)
+                        && mine & (LSB_CELL << (cx + cy * 8)) != 0 {
+                    line.push(LSB_CELL << (cx + cy * 8));
+                    cx += dx;
+                    cy += dy;
                 }
-                let xy = BitBoard::index(x, y);
-                // check surrounding stones.
-                if (TBL_SURROUND[xy] & oppo) == 0 {continue;}
-
-                if self.checkreverse_ex(xy, oppo, mine) {
-                    bits |= b;
+                line.pop();
+                line
+            }).collect::<Vec<_>>();
+            // 方向毎に裏返った数を全通り
+            let mut flips = vec![0u64];
+            for line in lines.iter() {
+                flips = flips.iter().flat_map(|&f| {
+                    let mut bits = f;
+                    let mut v = vec![f];
+                    for b in line.iter() {
+                        bits |= b;
+                        v.push(bits);
+                    }
+                    v
+                }).collect();
+            }
+            for f in flips.into_iter().filter(|&f| f != 0) {
+                let (m, o) = (mine & !f & !(LSB_CELL << xy), oppo | f);
+                let (black, white) = if self.teban == SENTE {(o, m)} else {(m, o)};
+                let prev = BitBoard {black, white, teban : -self.teban, pass};
+                // 他の方向も裏返ってしまうなら違う局面になる
+                let next = prev.r#move(xy).unwrap();
+                if next.black == self.black && next.white == self.white {
+                    ret.push((prev, xy));
                 }
             }
         }
-
-        if bits == 0 {  // pass
-            // return Some(vec![]);
-            return Some(vec![PASS]);
-        }
-
-        Some(cells2vec(bits))
+        ret
     }
 
     #[allow(dead_code)]
@@ -1411,6 +1567,40 @@ impl BitBoard {
         b
     }
 
+    /// 左上-右下の対角線で鏡反転する。(x, y) -> (y, x)
+    pub fn flip_diag(&self) -> BitBoard {
+        BitBoard {
+            black : flip_diag_a1h8(self.black),
+            white : flip_diag_a1h8(self.white),
+            teban : self.teban,
+            pass : self.pass,
+        }
+    }
+
+    /// 右上-左下の対角線で鏡反転する。(x, y) -> (7 - y, 7 - x)
+    pub fn flip_antidiag(&self) -> BitBoard {
+        BitBoard {
+            black : flip_diag_a8h1(self.black),
+            white : flip_diag_a8h1(self.white),
+            teban : self.teban,
+            pass : self.pass,
+        }
+    }
+
+    /// 対称な局面の中で代表となる局面のキーを返す。
+    /// 回転、鏡反転、色反転した局面は全て同じキーになる。
+    ///
+    /// # Returns
+    /// (キー, キーの局面にするための変換)
+    /// `Transform::apply()`で代表局面が得られ、
+    /// その逆変換で元の局面に戻る。
+    pub fn canonical(&self) -> (CanonicalKey, Transform) {
+        Transform::all().into_iter().map(|t| {
+            let b = t.apply(self);
+            ((b.black, b.white, b.teban), t)
+        }).min_by_key(|(key, _)| *key).unwrap()
+    }
+
     /// オーグメンテーション
     /// 回転させたものや鏡反転させたものを生成する。
     ///
@@ -1420,6 +1610,7 @@ impl BitBoard {
     ///
     /// # Returns
     /// 回転させたものや鏡反転させたものの配列
+    #[allow(dead_code)]
     pub fn rotated_mirrored(&self, score : i8) -> Vec<(Self, i8)> {
         vec![
             (self.clone(), score),
@@ -1428,13 +1619,17 @@ impl BitBoard {
             (self.rotate180().rotate90(), score),
             (self.flip_horz(), score),
             (self.flip_vert(), score),
+            (self.flip_diag(), score),
+            (self.flip_antidiag(), score),
             // flip color
             (self.flip_all(), -score),
             (self.rotate90().flip_all(), -score),
             (self.rotate180().flip_all(), -score),
             (self.rotate180().rotate90().flip_all(), -score),
             (self.flip_horz().flip_all(), -score),
-            (self.flip_vert().flip_all(), -score)
+            (self.flip_vert().flip_all(), -score),
+            (self.flip_diag().flip_all(), -score),
+            (self.flip_antidiag().flip_all(), -score)
         ]
     }
 
@@ -1448,25 +1643,31 @@ impl BitBoard {
     ///   確定石の数
     /// - score
     ///   最終結果
+    /// - value
+    ///   評価値
     ///
     /// # Returns
     /// 回転させたものや鏡反転させたものの配列
-    pub fn rotated_mirrored_fixed(&self, fsb: i8, fsw : i8, score : i8)
-            -> Vec<(Self, i8, i8, i8)> {
+    pub fn rotated_mirrored_fixed(&self, fsb: i8, fsw : i8, score : i8, value : f32)
+            -> Vec<Sample> {
         vec![
-            (self.clone(), fsb, fsw, score),
-            (self.rotate90(), fsb, fsw, score),
-            (self.rotate180(), fsb, fsw, score),
-            (self.rotate180().rotate90(), fsb, fsw, score),
-            (self.flip_horz(), fsb, fsw, score),
-            (self.flip_vert(), fsb, fsw, score),
+            (self.clone(), fsb, fsw, score, value),
+            (self.rotate90(), fsb, fsw, score, value),
+            (self.rotate180(), fsb, fsw, score, value),
+            (self.rotate180().rotate90(), fsb, fsw, score, value),
+            (self.flip_horz(), fsb, fsw, score, value),
+            (self.flip_vert(), fsb, fsw, score, value),
+            (self.flip_diag(), fsb, fsw, score, value),
+            (self.flip_antidiag(), fsb, fsw, score, value),
             // flip color
-            (self.flip_all(), fsw, fsb, -score),
-            (self.rotate90().flip_all(), fsw, fsb, -score),
-            (self.rotate180().flip_all(), fsw, fsb, -score),
-            (self.rotate180().rotate90().flip_all(), fsw, fsb, -score),
-            (self.flip_horz().flip_all(), fsw, fsb, -score),
-            (self.flip_vert().flip_all(), fsw, fsb, -score)
+            (self.flip_all(), fsw, fsb, -score, -value),
+            (self.rotate90().flip_all(), fsw, fsb, -score, -value),
+            (self.rotate180().flip_all(), fsw, fsb, -score, -value),
+            (self.rotate180().rotate90().flip_all(), fsw, fsb, -score, -value),
+            (self.flip_horz().flip_all(), fsw, fsb, -score, -value),
+            (self.flip_vert().flip_all(), fsw, fsb, -score, -value),
+            (self.flip_diag().flip_all(), fsw, fsb, -score, -value),
+            (self.flip_antidiag().flip_all(), fsw, fsb, -score, -value)
         ]
     }
 
@@ -1489,407 +1690,234 @@ impl BitBoard {
             format!("{},{score}", self.rotate180().rotate90()),
             format!("{},{score}", self.flip_horz()),
             format!("{},{score}", self.flip_vert()),
+            format!("{},{score}", self.flip_diag()),
+            format!("{},{score}", self.flip_antidiag()),
             // flip color
             format!("{},{mscore}", self.flip_all()),
             format!("{},{mscore}", self.rotate90().flip_all()),
             format!("{},{mscore}", self.rotate180().flip_all()),
             format!("{},{mscore}", self.rotate180().rotate90().flip_all()),
             format!("{},{mscore}", self.flip_horz().flip_all()),
-            format!("{},{mscore}", self.flip_vert().flip_all())
+            format!("{},{mscore}", self.flip_vert().flip_all()),
+            format!("{},{mscore}", self.flip_diag().flip_all()),
+            format!("{},{mscore}", self.flip_antidiag().flip_all())
         ]
     }
 
-    fn fixstones_right(startbit : u64, tgt : u64, count : &mut i32) -> u64 {
-        let mut fcells = 0u64;
-        let mut bit = startbit;
-        for _i in 1..7 {  // →
-            bit_right!(bit);
-            if (tgt & bit) == 0 {return fcells;}
-
-            fcells |= bit;
-            *count += 1;
-        }
-        fcells
+    /// 確定石を求める。
+    ///
+    /// 1. 辺は辺の上だけで打てる手を全部試してもひっくり返らない石。(`EDGE_STABILITY`)
+    /// 2. 4方向全部が埋まっている列に含まれる石。
+    /// 3. 4方向それぞれについて、列が埋まっているか
+    ///    隣が同じ色の確定石になっている石。これを変化が無くなるまで繰り返す。
+    ///
+    /// # Returns
+    /// (黒の確定石のbit, 白の確定石のbit)
+    pub fn stable_cells(&self) -> (u64, u64) {
+        let filled = self.black | self.white;
+        let full = full_lines(filled);
+        (stable_cells_of(self.black, self.white, &full),
+         stable_cells_of(self.white, self.black, &full))
     }
 
-    fn fixstones_left(startbit : u64, tgt : u64, count : &mut i32) -> u64 {
-        let mut fcells = 0u64;
-        let mut bit = startbit;
-        for _i in 1..7 {  // →
-            bit_left!(bit);
-            if (tgt & bit) == 0 {return fcells;}
-
-            fcells |= bit;
-            *count += 1;
-        }
-        fcells
+    /// # Returns
+    /// (黒の確定石の数, 白の確定石の数)
+    pub fn fixedstones(&self) -> (i8, i8) {
+        let (b, w) = self.stable_cells();
+        (b.count_ones() as i8, w.count_ones() as i8)
     }
+}
 
-    fn fixstones_up(startbit : u64, tgt : u64, count : &mut i32) -> u64 {
-        let mut fcells = 0u64;
-        let mut bit = startbit;
-        for _i in 1..7 {  // ↓
-            bit_up!(bit);
-            if (tgt & bit) == 0 {return fcells;}
+/// 辺と角以外のマス
+const CENTRAL_CELL : u64 = 0x007e7e7e7e7e7e00;
 
-            fcells |= bit;
-            *count += 1;
-        }
-        fcells
+const fn diag_masks(anti : bool) -> [u64 ; 15] {
+    let mut ret = [0u64 ; 15];
+    let mut i = 0;
+    while i < CELL_2D {
+        let x = i % NUMCELL;
+        let y = i / NUMCELL;
+        let d = if anti {x + y} else {x + NUMCELL - 1 - y};
+        ret[d] |= LSB_CELL << i;
+        i += 1;
     }
+    ret
+}
 
-    fn fixstones_down(startbit : u64, tgt : u64, count : &mut i32) -> u64 {
-        let mut fcells = 0u64;
-        let mut bit = startbit;
-        for _i in 1..7 {  // ↓
-            bit_down!(bit);
-            if (tgt & bit) == 0 {return fcells;}
+/// 左上-右下方向の斜めの列
+const DIAG_LINES : [u64 ; 15] = diag_masks(false);
+/// 右上-左下方向の斜めの列
+const ANTIDIAG_LINES : [u64 ; 15] = diag_masks(true);
 
-            fcells |= bit;
-            *count += 1;
-        }
-        fcells
+/// 埋まっている列に含まれるマス
+///
+/// # Returns
+/// [横, 縦, 左上-右下, 右上-左下]
+fn full_lines(filled : u64) -> [u64 ; 4] {
+    let mut ret = [0u64 ; 4];
+    for i in 0..NUMCELL {
+        let row = 0xffu64 << (i * NUMCELL);
+        if filled & row == row {ret[0] |= row;}
+        let col = 0x0101010101010101u64 << i;
+        if filled & col == col {ret[1] |= col;}
     }
+    for line in DIAG_LINES {
+        if filled & line == line {ret[2] |= line;}
+    }
+    for line in ANTIDIAG_LINES {
+        if filled & line == line {ret[3] |= line;}
+    }
+    ret
+}
 
-    pub fn fixedstones(&self) -> (i8, i8) {
-        // return (0, 0);  // この関数が遅いのかを見極める用
-        let mut count = 0;
-        let black = self.black;
-        let white = self.white;
-        let mut fcellsb : u64 = black & CORNER_CELL;
-        let mut fcellsw : u64 = white & CORNER_CELL;
-
-        if (fcellsb | fcellsw) == 0 {return (0, 0);}
-
-        // 四隅と辺
-        let bit = LT_CELL;
-        if (black & bit) != 0 {
-            fcellsb |= Self::fixstones_right(bit, black, &mut count);
-            fcellsb |= Self::fixstones_down(bit, black, &mut count);
-        } else if (white & bit) != 0 {
-            fcellsw |= Self::fixstones_right(bit, white, &mut count);
-            fcellsw |= Self::fixstones_down(bit, white, &mut count);
-        }
-        let bit = RT_CELL;
-        if (black & bit) != 0 {
-            fcellsb |= Self::fixstones_left(bit, black, &mut count);
-            fcellsb |= Self::fixstones_down(bit, black, &mut count);
-        } else if (white & bit) != 0 {
-            fcellsw |= Self::fixstones_left(bit, white, &mut count);
-            fcellsw |= Self::fixstones_down(bit, white, &mut count);
-        }
-        let bit = LB_CELL;
-        if (black & bit) != 0 {
-            fcellsb |= Self::fixstones_right(bit, black, &mut count);
-            fcellsb |= Self::fixstones_up(bit, black, &mut count);
-        } else if (white & bit) != 0 {
-            fcellsw |= Self::fixstones_right(bit, white, &mut count);
-            fcellsw |= Self::fixstones_up(bit, white, &mut count);
-        }
-        let bit = RB_CELL;
-        if (black & bit) != 0 {
-            fcellsb |= Self::fixstones_left(bit, black, &mut count);
-            fcellsb |= Self::fixstones_up(bit, black, &mut count);
-        } else if (white & bit) != 0 {
-            fcellsw |= Self::fixstones_left(bit, white, &mut count);
-            fcellsw |= Self::fixstones_up(bit, white, &mut count);
-        }
-        if count < 4 {
-            return (fcellsb.count_ones() as i8, fcellsw.count_ones() as i8);
-        }
-        // 中身
-        // こんな感じなら確定石
-        // xx?  x??
-        // x@?  x@?
-        // x??  xx?
-        for x in 1..7 {
-            let mut cnt = 0;
-            // 左3つ fcells[] == @
-            // 上 fcells[] == @
-            let mut bit = LSB_CELL << BitBoard::index(x, 1);
-            let mut wbit = 0x0000000000010103 << BitBoard::index(x - 1, 0);
-            // let mut wbit = 0x0000000000000107 << BitBoard::index(x - 1, 0);
-            for _y in 0..6 {
-                // println!("bit:{bit:b}, wbit:{wbit:b}");
-
-                if (bit & (fcellsb | fcellsw)) != 0 {
-                   // println!("if fcb | fcw");
-                    bit_down!(bit);
-                    bit_down!(wbit);
-                    continue;
-                }
-
-                if (bit & black) != 0 {
-                    if (fcellsb & wbit) != wbit {
-                        // println!("fcellsb : {fcellsb:b}");
-                        break;
-                    }
-
-                    fcellsb |= bit;
-                    cnt += 1;
-                    // println!("fcellsb : {fcellsb:b}, {cnt}");
-                } else if (bit & white) != 0 {
-                    if (fcellsw & wbit) != wbit {
-                        // println!("fcellsw : {fcellsw:b}");
-                        break;
-                    }
-
-                    fcellsw |= bit;
-                    cnt += 1;
-                } else {
-                    break;
-                }
-
-                bit_down!(bit);
-                bit_down!(wbit);
-            }
-            // 左3つ fcells[] == @
-            // 下 fcells[] == @
-            let mut bit = LSB_CELL << BitBoard::index(x, NUMCELL - 2);
-            let mut wbit = 0x0000000000030101 << BitBoard::index(x - 1, NUMCELL - 3);
-            // let mut wbit = 0x0000000000000407 << BitBoard::index(x - 1, NUMCELL - 3);
-            for _y in (0..6).rev() {
-                if (bit & (fcellsb | fcellsw)) != 0 {
-                    bit_up!(bit);
-                    bit_up!(wbit);
-                    continue;
-                }
-
-                if (bit & black) != 0 {
-                    if (fcellsb & wbit) != wbit {break;}
-
-                    fcellsb |= bit;
-                    cnt += 1;
-                } else if (bit & white) != 0 {
-                    if (fcellsw & wbit) != wbit {break;}
-
-                    fcellsw |= bit;
-                    cnt += 1;
-                    // println!("xy: {x:x}, {y:x}");
-                    // println!("bit:{bit:x}, wbit:{wbit:x}");
-                    // println!("fcellsw : {fcellsw:b}, {cnt}");
-                } else {
-                    break;
-                }
-
-                bit_up!(bit);
-                bit_up!(wbit);
-            }
-            if cnt == 0 {break;}
-        }
-        // xxx  xxx
-        // x@?  ?@x
-        // ???  ???
-        for y in 1..7 {
-            let mut cnt = 0;
-            // 上3つ fcells[] == @
-            // 左 fcells[] == @
-            let mut bit : u64 = LSB_CELL << BitBoard::index(1, y);
-            let mut wbit = 0x0000000000000107 << BitBoard::index(0, y - 1);
-            // let mut wbit = 0x0000000000010103 << BitBoard::index(0, y - 1);
-            for _x in 1..7 {
-                if (bit & (fcellsb | fcellsw)) != 0 {
-                    bit_right!(bit);
-                    bit_right!(wbit);
-                    continue;
-                }
-
-                if (bit & black) != 0 {
-                    if (fcellsb & wbit) != wbit {break;}
-
-                    fcellsb |= bit;
-                    cnt += 1;
-                } else if (bit & white) != 0 {
-                    if (fcellsw & wbit) != wbit {break;}
-
-                    fcellsw |= bit;
-                    cnt += 1;
-                } else {
-                    break;
-                }
-
-                bit_right!(bit);
-                bit_right!(wbit);
-            }
-            // 上3つ fcells[] == @
-            // 右 fcells[] == @
-            let mut bit : u64 = LSB_CELL << BitBoard::index(NUMCELL - 2, y);
-            let mut wbit = 0x0000000000000407 << BitBoard::index(NUMCELL - 3, y - 1);
-            for _x in (1..7).rev() {
-                if (bit & (fcellsb | fcellsw)) != 0 {
-                    bit_left!(bit);
-                    bit_left!(wbit);
-                    continue;
-                }
-
-                if (bit & black) != 0 {
-                    if (fcellsb & wbit) != wbit {break;}
-
-                    fcellsb |= bit;
-                    cnt += 1;
-                } else if (bit & white) != 0 {
-                    if (fcellsw & wbit) != wbit {break;}
-
-                    fcellsw |= bit;
-                    cnt += 1;
-                } else {
-                    break;
-                }
-
-                bit_left!(bit);
-                bit_left!(wbit);
-            }
-            if cnt == 0 {break;}
-        }
-        //
-        // ?xx  ??x
-        // ?@x  ?@x
-        // ??x  ?xx
-        for x in (1..7).rev() {
-            let mut cnt = 0;
-            // 右3つ fcells[] == @
-            // 上 fcells[] == @
-            let mut bit : u64 = LSB_CELL << BitBoard::index(x, 1);
-            let mut wbit = 0x0000000000040406 << BitBoard::index(x - 1, 0);
-            // let mut wbit = 0x0000000000070100 << BitBoard::index(x - 1, 0);
-            for _y in 1..7 {
-                if (bit & (fcellsb | fcellsw)) != 0 {
-                    bit_down!(bit);
-                    bit_down!(wbit);
-                    continue;
-                }
-
-                if (bit & black) != 0 {
-                    if (fcellsb & wbit) != wbit {break;}
-
-                    fcellsb |= bit;
-                    cnt += 1;
-                } else if (bit & white) != 0 {
-                    if (fcellsw & wbit) != wbit {break;}
-
-                    fcellsw |= bit;
-                    cnt += 1;
-                } else {
-                    break;
-                }
-
-                bit_down!(bit);
-                bit_down!(wbit);
-            }
-            // 右3つ fcells[] == @
-            // 下 fcells[] == @
-            let mut bit : u64 = LSB_CELL << BitBoard::index(x, NUMCELL - 2);
-            let mut wbit = 0x0000000000060404 << BitBoard::index(x - 1, NUMCELL - 3);
-            for _y in (1..7).rev() {
-                if (bit & (fcellsb | fcellsw)) != 0 {
-                    bit_up!(bit);
-                    bit_up!(wbit);
-                    continue;
-                }
-
-                if (bit & black) != 0 {
-                    if (fcellsb & wbit) != wbit {break;}
-
-                    fcellsb |= bit;
-                    cnt += 1;
-                } else if (bit & white) != 0 {
-                    if (fcellsw & wbit) != wbit {break;}
+/// 辺の上だけで石を打っていってもひっくり返らない石を求める。
+///
+/// # Arguments
+/// - mine, oppo : 辺の8マス分のbit
+/// - stable : 確定石の候補
+fn find_edge_stable(mine : u8, oppo : u8, stable : u8) -> u8 {
+    let empty = !(mine | oppo);
+    let mut stable = stable & mine;
+    if stable == 0 || empty == 0 {return stable;}
+
+    for x in 0..NUMCELL {
+        let bit = 1u8 << x;
+        if empty & bit == 0 {continue;}
+
+        // 自分が打つ
+        let flips = edge_flips(mine, oppo, x);
+        stable = find_edge_stable(mine | bit | flips, oppo & !flips, stable);
+        if stable == 0 {return 0;}
+
+        // 相手が打つ
+        let flips = edge_flips(oppo, mine, x);
+        stable = find_edge_stable(mine & !flips, oppo | bit | flips, stable);
+        if stable == 0 {return 0;}
+    }
+    stable
+}
 
-                    fcellsw |= bit;
-                    cnt += 1;
-                } else {
-                    break;
-                }
+/// 辺の上でxに打ったときにひっくり返る石
+fn edge_flips(mine : u8, oppo : u8, x : usize) -> u8 {
+    let mut flips = 0u8;
+    // 右へ
+    let mut f = 0u8;
+    let mut i = x + 1;
+    while i < NUMCELL && oppo & (1 << i) != 0 {
+        f |= 1 << i;
+        i += 1;
+    }
+    if i < NUMCELL && mine & (1 << i) != 0 {flips |= f;}
+    // 左へ
+    let mut f = 0u8;
+    let mut i = x as i32 - 1;
+    while i >= 0 && oppo & (1 << i) != 0 {
+        f |= 1 << i;
+        i -= 1;
+    }
+    if i >= 0 && mine & (1 << i) != 0 {flips |= f;}
+    flips
+}
 
-                bit_up!(bit);
-                bit_up!(wbit);
+/// 辺の確定石のテーブル。[mine * 256 + oppo]
+fn edge_stability() -> &'static [u8] {
+    static TBL : std::sync::OnceLock<Vec<u8>> = std::sync::OnceLock::new();
+    TBL.get_or_init(|| {
+        let mut tbl = vec![0u8 ; 256 * 256];
+        for mine in 0..256usize {
+            for oppo in 0..256usize {
+                if mine & oppo != 0 {continue;}
+
+                tbl[mine * 256 + oppo] =
+                    find_edge_stable(mine as u8, oppo as u8, mine as u8);
             }
-            if cnt == 0 {break;}
-        }
-
-        // ???  ???
-        // ?@x  x@?
-        // xxx  xxx
-        for y in (1..7).rev() {
-            let mut cnt = 0;
-            // 下3つ fcells[] == @
-            // 左 fcells[] == @
-            let mut bit : u64 = LSB_CELL << BitBoard::index(1, y);
-            let mut wbit = 0x0000000000070100 << BitBoard::index(0, y - 1);
-            for _x in 1..7 {
-                // println!("bit:{bit:08x}, wbit:{wbit:08x}");
-
-                if (bit & (fcellsb | fcellsw)) != 0 {
-                    bit_right!(bit);
-                    bit_right!(wbit);
-                    continue;
-                }
-
-                if (bit & black) != 0 {
-                    if (fcellsb & wbit) != wbit {break;}
-
-                    fcellsb |= bit;
-                    cnt += 1;
-                } else if (bit & white) != 0 {
-                    if (fcellsw & wbit) != wbit {break;}
-
-                    fcellsw |= bit;
-                    cnt += 1;
-                    // println!("fcellsw : {fcellsw:08x}, {cnt}");
-                } else {
-                    break;
-                }
+        }
+        tbl
+    })
+}
 
-                bit_right!(bit);
-                bit_right!(wbit);
-            }
-            // 下3つ fcells[] == @
-            // 右 fcells[] == @
-            let mut bit : u64 = LSB_CELL << BitBoard::index(NUMCELL - 2, y);
-            let mut wbit = 0x0000000000070400 << BitBoard::index(NUMCELL - 3, y - 1);
-            for _x in (1..7).rev() {
-                let cb = (bit & black) != 0;
-                let cw = (bit & white) != 0;
-                if !(cb | cw) {break;}
+/// 左端(x=0)または右端(x=7)の列を8bitにする。
+fn column_to_edge(bits : u64, x : usize) -> u8 {
+    let mut ret = 0u8;
+    for y in 0..NUMCELL {
+        ret |= (((bits >> (x + y * NUMCELL)) & LSB_CELL) as u8) << y;
+    }
+    ret
+}
 
-                if (bit & (fcellsb | fcellsw)) != 0 {
-                    bit_left!(bit);
-                    bit_left!(wbit);
-                    continue;
-                }
+/// 8bitを左端(x=0)または右端(x=7)の列に戻す。
+fn edge_to_column(edge : u8, x : usize) -> u64 {
+    let mut ret = 0u64;
+    for y in 0..NUMCELL {
+        ret |= (((edge >> y) & 1) as u64) << (x + y * NUMCELL);
+    }
+    ret
+}
 
-                if (bit & black) != 0 {
-                    if (fcellsb & wbit) != wbit {break;}
+/// 辺の確定石
+fn edge_stable_cells(mine : u64, oppo : u64) -> u64 {
+    let tbl = edge_stability();
+    let lookup = |m : u8, o : u8| tbl[m as usize * 256 + o as usize];
+    let top = lookup(mine as u8, oppo as u8) as u64;
+    let bottom = (lookup((mine >> 56) as u8, (oppo >> 56) as u8) as u64) << 56;
+    let left = edge_to_column(
+        lookup(column_to_edge(mine, 0), column_to_edge(oppo, 0)), 0);
+    let right = edge_to_column(
+        lookup(column_to_edge(mine, NUMCELL - 1),
+               column_to_edge(oppo, NUMCELL - 1)), NUMCELL - 1);
+    top | bottom | left | right
+}
 
-                    fcellsb |= bit;
-                    cnt += 1;
-                } else if (bit & white) != 0 {
-                    if (fcellsw & wbit) != wbit {break;}
+/// mineの確定石を求める。
+fn stable_cells_of(mine : u64, oppo : u64, full : &[u64 ; 4]) -> u64 {
+    let central = mine & CENTRAL_CELL;
+    let mut stable = edge_stable_cells(mine, oppo);
+    stable |= full[0] & full[1] & full[2] & full[3] & central;
+    loop {
+        // 端をまたいだbitは辺のマスに行くのでcentralで消える。
+        let horz = full[0] | (stable >> 1) | (stable << 1);
+        let vert = full[1] | (stable >> NUMCELL) | (stable << NUMCELL);
+        let diag = full[2]
+            | (stable >> (NUMCELL + 1)) | (stable << (NUMCELL + 1));
+        let antidiag = full[3]
+            | (stable >> (NUMCELL - 1)) | (stable << (NUMCELL - 1));
+        let newstable = stable | (horz & vert & diag & antidiag & central);
+        if newstable == stable {return stable;}
+
+        stable = newstable;
+    }
+}
 
-                    fcellsw |= bit;
-                    cnt += 1;
-                } else {
-                    break;
-                }
+/// 左上-右下の対角線で鏡反転する。
+/// bit(x + y * 8) -> bit(y + x * 8)
+fn flip_diag_a1h8(bits : u64) -> u64 {
+    const K1 : u64 = 0x5500550055005500;
+    const K2 : u64 = 0x3333000033330000;
+    const K4 : u64 = 0x0f0f0f0f00000000;
+    let mut x = bits;
+    let t = K4 & (x ^ (x << 28));
+    x ^= t ^ (t >> 28);
+    let t = K2 & (x ^ (x << 14));
+    x ^= t ^ (t >> 14);
+    let t = K1 & (x ^ (x << 7));
+    x ^= t ^ (t >> 7);
+    x
+}
 
-                bit_left!(bit);
-                bit_left!(wbit);
-            }
-            if cnt == 0 {break;}
-        }
-        //
-        // xは@と同じ色の確定石
-        // println!("fc:{fcells:?}, {count:?}");
-        // println!("fc:{count:?}");
-        // for i in 0..8 {
-        //     for j in 0..8 {
-        //         print!("{},", fcells[i * 8 + j]);
-        //     }
-        //     println!("");
-        // }
-        // println!("fc:{fcellsb:b}, {fcellsw:b}");
-        (fcellsb.count_ones() as i8, fcellsw.count_ones() as i8)
-    }
+/// 右上-左下の対角線で鏡反転する。
+/// bit(x + y * 8) -> bit((7 - y) + (7 - x) * 8)
+fn flip_diag_a8h1(bits : u64) -> u64 {
+    const K1 : u64 = 0xaa00aa00aa00aa00;
+    const K2 : u64 = 0xcccc0000cccc0000;
+    const K4 : u64 = 0xf0f0f0f00f0f0f0f;
+    let mut x = bits;
+    let t = x ^ (x << 36);
+    x ^= K4 & (t ^ (x >> 36));
+    let t = K2 & (x ^ (x << 18));
+    x ^= t ^ (t >> 18);
+    let t = K1 & (x ^ (x << 9));
+    x ^= t ^ (t >> 9);
+    x
 }
 
 /// count # of empty cells
@@ -2001,7 +2029,7 @@ fn testbitbrd() {
     assert_eq!(b.black, 0x004121110905037E);
     assert_eq!(b.white, 0xffbedeeef6fafc80);
     assert!(b.checkreverse(BitBoard::index(0, 0)));
-    assert_eq!(b.fixedstones(), (0, 15));
+    assert_eq!(b.fixedstones(), (0, 45));
     assert_eq!(b.count(),
       6 + 2 + 2 + 2 + 2 + 2 + 2 -
       (1 + 6 + 1 + 5 + 2 + 4 + 3 + 3 + 4 + 2 + 5 + 1 + 8));
@@ -2020,7 +2048,7 @@ fn testbitbrd() {
     assert_eq!(b90.to_string(), "h/AeAa/AdAb/AcAc/AbAd/AaAe/Bf/1Fa w");
     assert_eq!(b90.black, 0x7e03050911214100);
     assert_eq!(b90.white, 0x80FCFAF6EEDEBEFF);
-    assert_eq!(b90.fixedstones(), (0, 15));
+    assert_eq!(b90.fixedstones(), (0, 45));
     let br = b90.r#move(cell(1, 8));
     assert!(br.is_ok());
     let br = br.unwrap();
@@ -2031,7 +2059,7 @@ fn testbitbrd() {
     assert_eq!(b180.black, 0x7EC0A09088848200);
     assert_eq!(b180.white, 0x013f5f6f777b7dff);
     // b180.put();
-    assert_eq!(b180.fixedstones(), (0, 15));
+    assert_eq!(b180.fixedstones(), (0, 45));
     let b = b180.r#move(cell(8, 8));
     assert!(b.is_ok());
     let b = b.unwrap();
@@ -2045,7 +2073,7 @@ fn testbitbrd() {
     assert_eq!(b.black, 0xFF7D7B776F5F3F01);
     assert_eq!(b.white, 0x0082848890A0C07E);
     assert!(b.checkreverse(BitBoard::index(7, 0)));
-    assert_eq!(b.fixedstones(), (15, 0));
+    assert_eq!(b.fixedstones(), (45, 0));
     assert_eq!(b.count(),
       -(6 + 2 + 2 + 2 + 2 + 2 + 2) +
       (1 + 6 + 1 + 5 + 2 + 4 + 3 + 3 + 4 + 2 + 5 + 1 + 8));
@@ -2064,7 +2092,7 @@ fn testbitbrd() {
     assert_eq!(b90.to_string(), "1fA/bF/aAaE/aBaD/aCaC/aDaB/aEaA/H b");
     assert_eq!(b90.black, 0xFFBEDEEEF6FAFC80);
     assert_eq!(b90.white, 0x004121110905037E);
-    assert_eq!(b90.fixedstones(), (15, 0));
+    assert_eq!(b90.fixedstones(), (45, 0));
     let br = b90.r#move(cell(1, 1));
     assert!(br.is_ok());
     let br = br.unwrap();
@@ -2078,7 +2106,7 @@ fn testbitbrd() {
     assert_eq!(b180.black, 0x80FCFAF6EEDEBEFF);
     assert_eq!(b180.white, 0x7E03050911214100);
     assert!(b180.checkreverse(BitBoard::index(0, 7)));
-    assert_eq!(b180.fixedstones(), (15, 0));
+    assert_eq!(b180.fixedstones(), (45, 0));
     assert_eq!(b180.count(),
       -(6 + 2 + 2 + 2 + 2 + 2 + 2) +
       (1 + 6 + 1 + 5 + 2 + 4 + 3 + 3 + 4 + 2 + 5 + 1 + 8));
@@ -2335,7 +2363,7 @@ fn testbitbrd() {
     let b = b.unwrap();
     assert_eq!(b.to_obf(),
         "X-X-X----XXX----XXXXXXXX-XXX----X-X-X-----X--X----X---X---X----X O");
-    assert_eq!(b.fixedstones(), (2, 0));
+    assert_eq!(b.fixedstones(), (3, 0));
     assert_eq!(b.count(), 26);
     let mv = b.genmove();
     assert_eq!(mv, Some(vec![cell(0, 0)]));
@@ -2354,22 +2382,19 @@ fn testbitbrd() {
     let b = b.unwrap();
     assert_eq!(b.to_obf(),
         "X----X---X---X----X--X-----X-X-X----XXX-XXXXXXXX----XXX----X-X-X O");
-    assert_eq!(b.fixedstones(), (2, 0));
+    assert_eq!(b.fixedstones(), (3, 0));
     assert_eq!(b.count(), 26);
     let mv = b.genmove();
     // assert_eq!(mv, Some(vec![]));
     assert_eq!(mv, Some(vec![cell(0, 0)]));
-    // difficult to count correctly
-    // difficult to count correctly
-    // let b = BitBoard::try_from("H/AaF/C5/D4/C1A3/C2A2/C3A1/C4A b").unwrap();
-    // assert_eq!(b.fixedstones(), (34, 1));
-    // let b180 = b.rotate180();
-    // assert_eq!(b180.fixedstones(), (34, 1));
-    // difficult to count correctly
-    // let b = BitBoard::try_from("H/aG/C5/D4/C1A3/C2A2/C3A1/C4A b").unwrap();
-    // assert_eq!(b.fixedstones(), (31, 1));
-    // let b180 = b.rotate180();
-    // assert_eq!(b180.fixedstones(), (31, 1));
+    let b = BitBoard::try_from("H/AaF/C5/D4/C1A3/C2A2/C3A1/C4A b").unwrap();
+    assert_eq!(b.fixedstones(), (34, 1));
+    let b180 = b.rotate180();
+    assert_eq!(b180.fixedstones(), (34, 1));
+    let b = BitBoard::try_from("H/aG/C5/D4/C1A3/C2A2/C3A1/C4A b").unwrap();
+    assert_eq!(b.fixedstones(), (31, 1));
+    let b180 = b.rotate180();
+    assert_eq!(b180.fixedstones(), (31, 1));
     assert_eq!(count_empty_cells("8/8/8/3Aa3/3aA3/8/8/8 b").unwrap(), 60);
     assert_eq!(count_stones("8/8/8/3Aa3/3aA3/8/8/8 b").unwrap(), 4);
     assert_eq!(
@@ -7306,3 +7331,216 @@ fn test_progress() {
         assert!(ban.is_progress(prgs));
     }
 }
+
+#[test]
+fn test_mirror_diag() {
+    let tbl = [
+        ("8/8/8/3Aa3/3aA3/8/8/8 b", "8/8/8/3Aa3/3aA3/8/8/8 b", "8/8/8/3Aa3/3aA3/8/8/8 b"),
+        ("H/8/8/8/8/8/8/h w", "A6a/A6a/A6a/A6a/A6a/A6a/A6a/A6a w", "a6A/a6A/a6A/a6A/a6A/a6A/a6A/a6A w"),
+        ("A7/8/8/8/8/8/8/7a b", "A7/8/8/8/8/8/8/7a b", "a7/8/8/8/8/8/8/7A b"),
+        ("1A6/8/8/8/8/8/8/6a1 w", "8/A7/8/8/8/8/7a/8 w", "8/a7/8/8/8/8/7A/8 w"),
+    ];
+    for (from_rfen, diag_rfen, antidiag_rfen) in tbl {
+        let ban = BitBoard::try_from(from_rfen).unwrap();
+        assert_eq!(ban.flip_diag().to_string(), diag_rfen);
+        assert_eq!(ban.flip_antidiag().to_string(), antidiag_rfen);
+        assert_eq!(ban.flip_diag().flip_diag().to_string(), from_rfen);
+        assert_eq!(ban.flip_antidiag().flip_antidiag().to_string(), from_rfen);
+    }
+}
+
+#[test]
+fn test_symmetry_group() {
+    // 対称性の無い局面なら8通り(色反転込みで16通り)全部違う局面になる。
+    let ban = BitBoard::try_from("4A3/2AaB2/3aAa2/2Ca2/2Ad1/1BaAa2/2aBa2/1f1 b").unwrap();
+    for x in 0..8 {
+        for y in 0..8 {
+            let c = ban.at(x, y);
+            assert_eq!(ban.flip_diag().at(y, x), c);
+            assert_eq!(ban.flip_antidiag().at(7 - y, 7 - x), c);
+        }
+    }
+    // 対角反転は回転と左右反転の組み合わせと一致する。
+    assert!(ban.flip_diag() == ban.rotate90().flip_vert());
+    assert!(ban.flip_antidiag() == ban.rotate90().flip_horz());
+
+    let mut all = ban.rotated_mirrored_string(3);
+    assert_eq!(all.len(), 16);
+    all.sort();
+    all.dedup();
+    assert_eq!(all.len(), 16);
+    assert_eq!(ban.rotated_mirrored(3).len(), 16);
+    assert_eq!(ban.rotated_mirrored_fixed(1, 2, 3, 3.5).len(), 16);
+}
+
+#[test]
+fn test_canonical() {
+    let tbl = [
+        "4A3/2AaB2/3aAa2/2Ca2/2Ad1/1BaAa2/2aBa2/1f1 b",
+        "dD/AdC/BcC/BaAbAa/Af1/AaAaA1a1/BcC/G1 b",
+        "8/8/8/3Aa3/3aA3/8/8/8 b",
+    ];
+    for rfen in tbl {
+        let ban = BitBoard::try_from(rfen).unwrap();
+        let (key, t) = ban.canonical();
+        let canon = t.apply(&ban);
+        assert_eq!((canon.black, canon.white, canon.teban), key);
+        assert_eq!(t.inverse().apply(&canon).to_string(), rfen);
+        for t2 in Transform::all() {
+            let b2 = t2.apply(&ban);
+            assert_eq!(t2.inverse().apply(&b2).to_string(), rfen);
+            let (key2, t3) = b2.canonical();
+            assert_eq!(key2, key);
+            let canon2 = t3.apply(&b2);
+            assert_eq!(canon2.to_string(), canon.to_string());
+        }
+    }
+    let t = Transform {sym : 2, colorflip : true};
+    assert_eq!(t.apply_score(5), -5);
+    // 手も盤面と一緒に変換される
+    let ban = BitBoard::new();
+    for t in Transform::all() {
+        for mv in ban.genmove().unwrap() {
+            let child = t.apply(&ban.r#move(mv).unwrap());
+            assert!(t.apply(&ban).r#move(t.apply_cell(mv)).unwrap() == child);
+        }
+        assert_eq!(t.apply_cell(PASS), PASS);
+    }
+    assert_eq!(Transform::IDENTITY.apply_score(5), 5);
+
+    let ban = BitBoard::try_from(tbl[0]).unwrap();
+    let all = Transform::all().iter().map(
+        |t| t.apply(&ban).to_string()).collect::<Vec<_>>();
+    let aug = ban.rotated_mirrored(0).iter().map(
+        |(b, _)| b.to_string()).collect::<Vec<_>>();
+    assert_eq!(all, aug);
+}
+
+#[test]
+fn test_stable_cells() {
+    // 角から3段埋まっていれば全部確定
+    let b = BitBoard::try_from("H/H/H/8/8/8/8/8 w").unwrap();
+    assert_eq!(b.fixedstones(), (24, 0));
+    // 辺の間に挟まる石は確定しない
+    let b = BitBoard::try_from("A1a1A3/8/8/8/8/8/8/8 w").unwrap();
+    assert_eq!(b.fixedstones(), (1, 0));
+    // 辺が埋まっていれば色が混ざっていても確定
+    let b = BitBoard::try_from("BaAcA/8/8/8/8/8/8/8 w").unwrap();
+    assert_eq!(b.fixedstones(), (4, 4));
+    // 内側の石は4方向とも埋まっているか確定石が隣にあれば確定
+    let b = BitBoard::try_from("H/Ba6/B6/8/8/8/8/8 w").unwrap();
+    assert_eq!(b.stable_cells(), (0x00000000000103ff, 0));
+    let b = BitBoard::try_from("H/Ab6/B6/8/8/8/8/8 w").unwrap();
+    assert_eq!(b.stable_cells(), (0x00000000000101ff, 0));
+    let b = BitBoard::try_from("H/Cb3/Bb4/8/8/8/8/8 w").unwrap();
+    assert_eq!(b.stable_cells(), (0x00000000000307ff, 0));
+}
+
+#[test]
+fn test_stable_cells_never_flip() {
+    use rand::{Rng, SeedableRng};
+
+    // 確定石と判定された石はその後ひっくり返らない。
+    let mut rng = rand::rngs::StdRng::seed_from_u64(20251018);
+    for _game in 0..200 {
+        let mut ban = BitBoard::new();
+        let mut stables : Vec<(u64, u64)> = Vec::new();
+        loop {
+            let (sb, sw) = ban.stable_cells();
+            assert_eq!(sb & !ban.black, 0);
+            assert_eq!(sw & !ban.white, 0);
+            for (b, w) in stables.iter() {
+                assert_eq!(ban.black & b, *b, "{ban}");
+                assert_eq!(ban.white & w, *w, "{ban}");
+            }
+            stables.push((sb, sw));
+
+            let moves = match ban.genmove() {
+                None => break,
+                Some(mv) => mv,
+            };
+            if moves[0] == PASS && ban.pass > 0 {break;}
+
+            let mv = moves[rng.gen_range(0..moves.len())];
+            ban = ban.r#move(mv).unwrap();
+        }
+        let (sb, sw) = ban.stable_cells();
+        if ban.is_full() {
+            assert_eq!((sb, sw), (ban.black, ban.white));
+        }
+    }
+}
+
+#[test]
+fn test_unmoves() {
+    // 初期局面から適当に進めながら1手前に戻れるか調べる
+    let mut ban = BitBoard::new();
+    assert!(ban.unmoves().is_empty());
+    let mut i = 0;
+    while let Some(moves) = ban.genmove() {
+        let mv = moves[i % moves.len()];
+        i += 7;
+        let child = ban.r#move(mv).unwrap();
+        let prevs = child.unmoves();
+        assert!(prevs.iter().any(|(p, m)| *m == mv && *p == ban),
+            "{ban} -> {child} by {mv}");
+        for (p, m) in prevs.iter() {
+            let next = p.r#move(*m).unwrap();
+            assert!(next.black == child.black && next.white == child.white);
+            assert_eq!(next.teban, child.teban);
+            if *m == PASS {
+                assert_eq!(p.mobility(), 0);
+            } else {
+                assert!(p.mobility() & (LSB_CELL << m) != 0, "{p} {m}");
+            }
+        }
+        if mv == PASS && child.mobility() == 0 {break;}
+
+        ban = child;
+    }
+
+    // 黒がe3に打ってe4を裏返した局面
+    let ban = BitBoard::try_from("8/8/4A3/3AA3/3aA3/8/8/8 w").unwrap();
+    let prevs = ban.unmoves();
+    assert!(prevs.iter().all(|(p, _)| p.teban == SENTE && p.count() == ban.count() - 3));
+    assert!(prevs.iter().any(|(p, m)| *m == cell(5, 3) && *p == BitBoard::new()));
+
+    // 白がパスしてから黒の番
+    let ban = BitBoard::try_from("H/H/H/H/H/H/Fa1/F2 b").unwrap();
+    let prevs = ban.unmoves();
+    assert!(prevs.iter().any(|(p, m)| *m == PASS
+        && p.to_string() == "H/H/H/H/H/H/Fa1/F2 w"));
+}
+
+#[test]
+fn test_mobility() {
+    use rand::{Rng, SeedableRng};
+
+    // 1マスずつcheckreverseした結果と一致する。
+    let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+    for _game in 0..100 {
+        let mut ban = BitBoard::new();
+        while !ban.is_full() {
+            let (mine, oppo) = if ban.teban == SENTE {
+                (ban.black, ban.white)
+            } else {
+                (ban.white, ban.black)
+            };
+            let mut expected = 0;
+            for xy in 0..CELL_2D {
+                if ban.is_filled(xy as u8) {continue;}
+                if ban.checkreverse_ex(xy, oppo, mine) {
+                    expected |= LSB_CELL << xy;
+                }
+            }
+            assert_eq!(ban.mobility(), expected, "{ban}");
+
+            let moves = ban.genmove().unwrap();
+            if moves[0] == PASS && ban.pass > 0 {break;}
+
+            let mv = moves[rng.gen_range(0..moves.len())];
+            ban = ban.r#move(mv).unwrap();
+        }
+    }
+    assert_eq!(BitBoard::new().mobility(), 0x0000080420100000);
+}
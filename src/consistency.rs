@@ -0,0 +1,140 @@
+//! 隣り合うmateファイルの値が食い違っていないか調べる。
+//!
+//! 残りNマスの局面の値は、子供の局面(残りN-1マス)の値の最善と同じはず。
+//! パスしかできない局面はパスした後の手を子供とみなす。
+
+use crate::bitboard::{self, BitBoard, CanonicalKey, Sample};
+use std::collections::HashMap;
+
+/// 下のレベルの局面の値。対称な局面は同じ局面として引ける。
+pub struct Lower {
+    scores : HashMap<CanonicalKey, i8>,
+}
+
+impl Lower {
+    /// 同じ局面が何回出てきても最初の値を使う。
+    pub fn new(boards : &[Sample]) -> Lower {
+        let mut scores = HashMap::new();
+        for (ban, _, _, score, _) in boards.iter() {
+            let (key, t) = ban.canonical();
+            scores.entry(key).or_insert(t.apply_score(*score));
+        }
+        Lower {scores}
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    /// 黒から見た値
+    pub fn get(&self, ban : &BitBoard) -> Option<i8> {
+        let (key, t) = ban.canonical();
+        self.scores.get(&key).map(|score| t.inverse().apply_score(*score))
+    }
+}
+
+/// 調べた結果
+#[derive(Debug, PartialEq)]
+pub enum Finding {
+    /// 子供の局面からはこの値になるはず。
+    Contradiction(i8),
+    /// 下のレベルに無い子供の局面
+    Missing(Vec<String>),
+}
+
+/// 子供の局面を返す。パスしかできない時はパスした後の局面の子供を返す。
+///
+/// # Returns
+/// Some((子供の局面を選ぶ手番, 子供の局面))。終局していればNone。
+pub fn children(ban : &BitBoard) -> Option<(i8, Vec<BitBoard>)> {
+    let moves = ban.genmove()?;
+    let (cur, moves) = if moves[0] == bitboard::PASS {
+        let passed = ban.r#move(bitboard::PASS).unwrap();
+        let moves = passed.genmove()?;
+        // 両者パスなら終局
+        if moves[0] == bitboard::PASS {return None;}
+
+        (passed, moves)
+    } else {
+        (ban.clone(), moves)
+    };
+    let children = moves.iter().map(|&mv| cur.r#move(mv).unwrap()).collect();
+    Some((cur.teban, children))
+}
+
+/// 1局面を調べる。
+///
+/// # Arguments
+/// - ban 残りNマスの局面
+/// - score banの値(黒から見た値)
+/// - lower 残りN-1マスの局面の値
+///
+/// # Returns
+/// 食い違っていなければNone
+pub fn check(ban : &BitBoard, score : i8, lower : &Lower) -> Option<Finding> {
+    let Some((teban, children)) = children(ban) else {
+        let expected = ban.count();
+        return if expected == score {None} else {Some(Finding::Contradiction(expected))};
+    };
+
+    let mut scores = Vec::with_capacity(children.len());
+    let mut missing = Vec::new();
+    for child in children.iter() {
+        match lower.get(child) {
+            Some(s) => scores.push(s),
+            None => missing.push(child.to_string()),
+        }
+    }
+    if !missing.is_empty() {return Some(Finding::Missing(missing));}
+
+    let best = if teban == bitboard::GOTE {
+        scores.into_iter().min()
+    } else {
+        scores.into_iter().max()
+    }.unwrap();
+    if best == score {None} else {Some(Finding::Contradiction(best))}
+}
+
+#[test]
+fn test_check() {
+    let ban = BitBoard::try_from("dD/AdC/BcC/BaAbAa/Af1/AaAaA1a1/BcC/G1 b").unwrap();
+    let kids = ban.genmove().unwrap().iter().map(|&mv| {
+        let child = ban.r#move(mv).unwrap();
+        let score = crate::solver::solve(&child);
+        (child, 0, 0, score, score as f32)
+    }).collect::<Vec<_>>();
+    let best = crate::solver::solve(&ban);
+    let lower = Lower::new(&kids);
+    assert_eq!(check(&ban, best, &lower), None);
+    assert_eq!(check(&ban, best - 2, &lower), Some(Finding::Contradiction(best)));
+
+    // 回転させたり色を入れ替えたりした局面でも引ける
+    for t in bitboard::Transform::all() {
+        let b = t.apply(&ban);
+        assert_eq!(check(&b, t.apply_score(best), &lower), None, "{t:?}");
+    }
+
+    // 足りない子供
+    let lower = Lower::new(&kids[1..]);
+    assert_eq!(check(&ban, best, &lower),
+        Some(Finding::Missing(vec![kids[0].0.to_string()])));
+
+    // 白がパスする局面は黒の手を子供にする
+    let ban = BitBoard::try_from("H/H/H/H/H/H/Fa1/F2 w").unwrap();
+    let (teban, kids) = children(&ban).unwrap();
+    assert_eq!(teban, bitboard::SENTE);
+    let samples = kids.iter().map(|c| {
+        let score = crate::solver::solve(c);
+        (c.clone(), 0, 0, score, score as f32)
+    }).collect::<Vec<_>>();
+    let lower = Lower::new(&samples);
+    // 対称な子供は1つにまとまる
+    assert!(lower.len() <= kids.len());
+    assert_eq!(check(&ban, crate::solver::solve(&ban), &lower), None);
+
+    // 終局している局面は石の差
+    let ban = BitBoard::try_from("H/H/H/H/H/H/H/h b").unwrap();
+    assert!(children(&ban).is_none());
+    assert_eq!(check(&ban, 48, &Lower::new(&[])), None);
+    assert_eq!(check(&ban, 0, &Lower::new(&[])), Some(Finding::Contradiction(48)));
+}
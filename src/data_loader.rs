@@ -1,6 +1,7 @@
 use super::*;
 
 use rayon::prelude::*;
+use std::io::Read;
 
 // const INPUTSIZE :i64 = weight::N_INPUT as i64;
 
@@ -24,9 +25,88 @@ pub fn findfiles(kifupath : &str) -> Vec<String> {
     files
 }
 
+/// 局面のファイルかどうか。".txt"か".bin"で終わるもの。".zst", ".zstd"が付いていてもよい。
+pub fn is_mate_file(fname : &str) -> bool {
+    let name = fname.strip_suffix(".zst")
+        .or_else(|| fname.strip_suffix(".zstd")).unwrap_or(fname);
+    name.ends_with(".txt") || name.ends_with(".bin")
+}
+
+/// ディレクトリにある局面のファイルを列挙する。
+pub fn find_mate_files(path : &str) -> Vec<String> {
+    let dir = std::fs::read_dir(path).unwrap();
+    let mut files = dir.filter_map(|entry| {
+        entry.ok().and_then(|e| e.file_name().to_str().map(|n| n.to_string()))
+    }).filter(|fnm| is_mate_file(fnm)).collect::<Vec<String>>();
+    files.sort();
+    files
+}
+
+/// 棋譜ファイルを読む。".zst", ".zstd"で終わるファイルは展開して読む。
+pub fn read_kifu(path : &str) -> Result<String, String> {
+    if path.ends_with(".zst") || path.ends_with(".zstd") {
+        let f = std::fs::File::open(path)
+                .map_err(|e| format!("error: {e} @ File::open({path})"))?;
+        let mut z = zstd::Decoder::new(f)
+                .map_err(|e| format!("error: {e} @ zstd::Decoder::new({path})"))?;
+        let mut content = String::new();
+        z.read_to_string(&mut content)
+                .map_err(|e| format!("error: {e} @ read_to_string({path})"))?;
+        Ok(content)
+    } else {
+        std::fs::read_to_string(path).map_err(|e| format!("error: {e} @ {path}"))
+    }
+}
+
+/// 棋譜から残りmateマスの局面を取り出す。
+fn extract_mate(content : &str, mate : u32) -> Vec<bitboard::Sample> {
+    let lines: Vec<&str> = content.split('\n').collect();
+    let kifu = kifu::Kifu::from(&lines);
+    kifu.list.iter().filter_map(|t| {
+        let ban = bitboard::BitBoard::try_from(t.rfen.as_str()).unwrap();
+        // 指定の局面じゃない
+        if ban.is_last_n(mate) {
+            let score = ban.count();
+            let (fsb, fsw) = ban.fixedstones();
+            Some((ban, fsb, fsw, score, score as f32))
+        } else {
+            None
+        }
+    }).collect::<Vec<_>>()
+}
+
+/// 棋譜の局面を終局の石の差と一緒に取り出す。
+///
+/// # Arguments
+/// - content 棋譜
+/// - keep 取り出す局面ならtrue
+/// - discount Someなら終局までの手数分だけ掛ける。パスも1手と数える。
+///
+/// # Returns
+/// (局面, 石の差)の配列。棋譜に結果が無ければErr。
+pub fn extract_midgame(content : &str, keep : impl Fn(&bitboard::BitBoard) -> bool,
+        discount : Option<f32>) -> Result<Vec<(bitboard::BitBoard, f32)>, String> {
+    let lines: Vec<&str> = content.split('\n').collect();
+    let kifu = kifu::Kifu::from(&lines);
+    let score = kifu.score.ok_or("no result")?;
+    let nply = kifu.list.len();
+    let mut ret = Vec::new();
+    for (i, t) in kifu.list.iter().enumerate() {
+        let ban = bitboard::BitBoard::try_from(t.rfen.as_str())?;
+        if !keep(&ban) {continue;}
+
+        let value = match discount {
+            Some(gamma) => score as f32 * gamma.powi((nply - i) as i32),
+            None => score as f32,
+        };
+        ret.push((ban, value));
+    }
+    Ok(ret)
+}
+
 pub fn loadkifu_for_mate(files : &[String], d : &str, mate : u32,
         log : &mut std::fs::File, show_path : bool)
-        -> Vec<(bitboard::BitBoard, i8, i8, i8)> {
+        -> Vec<bitboard::Sample> {
     // let sta = std::time::Instant::now();
     let shared = std::sync::Mutex::new(log);
     let boards = files.par_iter().flat_map(|fname| {
@@ -36,30 +116,121 @@ pub fn loadkifu_for_mate(files : &[String], d : &str, mate : u32,
             l.write_all(format!("{path}\n").as_bytes()).unwrap();
             if show_path {print!("{path}\r");}
         }
-        let content = std::fs::read_to_string(&path).unwrap();
-        let lines: Vec<&str> = content.split('\n').collect();
-        let kifu = kifu::Kifu::from(&lines);
-        kifu.list.iter().filter_map(|t| {
-            let ban = bitboard::BitBoard::try_from(t.rfen.as_str()).unwrap();
-            // 指定の局面じゃない
-            if ban.is_last_n(mate) {
-                let score = ban.count();
-                let (fsb, fsw) = ban.fixedstones();
-                Some((ban, fsb, fsw, score))
-            } else {
-                None
-            }
-        }).collect::<Vec<_>>()
+        let content = match read_kifu(&path) {
+            Ok(content) => content,
+            Err(msg) => {panic!("{msg}");},
+        };
+        extract_mate(&content, mate)
     }).collect();
     if show_path {println!();}
     // println!("{}usec",sta.elapsed().as_micros());
     boards
 }
 
+/// tarの棋譜集かどうか。
+pub fn is_archive(path : &str) -> bool {
+    [".tar", ".tar.zst", ".tar.zstd", ".tzst"].iter().any(|ext| path.ends_with(ext))
+}
+
+/// tarの棋譜集から棋譜を1つずつ取り出す。
+/// ".txt"を含む名前のファイルだけを読み、".zst"で終わるものは展開する。
+///
+/// # Returns
+/// (棋譜の名前, 棋譜)を返すiterator
+pub fn archive_kifus(path : &str)
+        -> Result<impl Iterator<Item = Result<(String, String), String>>, String> {
+    let f = std::fs::File::open(path)
+            .map_err(|e| format!("error: {e} @ File::open({path})"))?;
+    let reader : Box<dyn Read + Send> = if path.ends_with(".tar") {
+        Box::new(std::io::BufReader::new(f))
+    } else {
+        Box::new(zstd::Decoder::new(f)
+            .map_err(|e| format!("error: {e} @ zstd::Decoder::new({path})"))?)
+    };
+    Ok(tarball::TarReader::new(reader).filter_map(|entry| {
+        let (name, data) = match entry {
+            Ok(entry) => entry,
+            Err(msg) => {return Some(Err(msg));},
+        };
+        if !name.contains(".txt") {return None;}
+
+        let data = if name.ends_with(".zst") || name.ends_with(".zstd") {
+            match zstd::decode_all(data.as_slice()) {
+                Ok(data) => data,
+                Err(e) => {return Some(Err(format!("error: {e} @ {name}")));},
+            }
+        } else {
+            data
+        };
+        Some(Ok((name, String::from_utf8_lossy(&data).to_string())))
+    }))
+}
+
+/// (棋譜の名前, 棋譜)を順に返すiterator
+pub type KifuIter<'a> = Box<dyn Iterator<Item = (String, Result<String, String>)> + 'a>;
+
+/// --kifudirで指定されたディレクトリかtarの棋譜集の棋譜を順に読む。
+///
+/// # Returns
+/// (iterator, 棋譜の数)。tarの時は数が分からないのでNone。
+pub fn iter_kifus(d : &str) -> Result<(KifuIter<'_>, Option<usize>), String> {
+    if is_archive(d) {
+        let kifus = archive_kifus(d)?;
+        Ok((Box::new(kifus.map(move |entry| match entry {
+            Ok((name, content)) => (format!("{d}:{name}"), Ok(content)),
+            Err(msg) => (d.to_string(), Err(msg)),
+        })), None))
+    } else {
+        let files = findfiles(&format!("./{d}"));
+        let nkifu = files.len();
+        Ok((Box::new(files.into_iter().map(move |fname| {
+            let path = format!("{d}/{fname}");
+            let content = read_kifu(&path);
+            (path, content)
+        })), Some(nkifu)))
+    }
+}
+
+/// tarの棋譜集から残りmateマスの局面を取り出す。
+pub fn loadkifu_archive_for_mate(path : &str, mate : u32,
+        log : &mut std::fs::File, show_path : bool)
+        -> Vec<bitboard::Sample> {
+    let kifus = match archive_kifus(path) {
+        Ok(kifus) => kifus,
+        Err(msg) => {panic!("{msg}");},
+    };
+    let shared = std::sync::Mutex::new(log);
+    let boards = kifus.par_bridge().flat_map(|entry| {
+        let (name, content) = match entry {
+            Ok(entry) => entry,
+            Err(msg) => {panic!("{msg} in {path}");},
+        };
+        {
+            let mut l = shared.lock().unwrap();
+            l.write_all(format!("{path}:{name}\n").as_bytes()).unwrap();
+            if show_path {print!("{path}:{name}\r");}
+        }
+        extract_mate(&content, mate)
+    }).collect();
+    if show_path {println!();}
+    boards
+}
+
+/// --kifudirで指定されたディレクトリかtarの棋譜集から残りmateマスの局面を取り出す。
+pub fn loadkifu_dir_for_mate(d : &str, mate : u32,
+        log : &mut std::fs::File, show_path : bool)
+        -> Vec<bitboard::Sample> {
+    if is_archive(d) {
+        loadkifu_archive_for_mate(d, mate, log, show_path)
+    } else {
+        loadkifu_for_mate(&findfiles(&format!("./{d}")), d, mate, log, show_path)
+    }
+}
+
 #[allow(dead_code)]
 pub fn load_mate(files : &[String], d : &str,
         log : &mut std::fs::File, show_path : bool)
-        -> Vec<(bitboard::BitBoard, i8, i8, i8)> {
+        -> Vec<bitboard::Sample> {
     // let sta = std::time::Instant::now();
     let shared = std::sync::Mutex::new(log);
     let boards = files.par_iter().flat_map(|fname| {
@@ -69,14 +240,17 @@ pub fn load_mate(files : &[String], d : &str,
             l.write_all(format!("{path}\n").as_bytes()).unwrap();
             if show_path {print!("{path}\r");}
         }
-        let content = std::fs::read_to_string(&path).unwrap();
+        let content = match read_kifu(&path) {
+            Ok(content) => content,
+            Err(msg) => {panic!("{msg}");},
+        };
         let lines: Vec<&str> = content.split('\n').collect();
         let kifu = kifu::Kifu::from(&lines);
         kifu.list.iter().map(|t| {
             let ban = bitboard::BitBoard::try_from(t.rfen.as_str()).unwrap();
             let score = ban.count();
             let (fsb, fsw) = ban.fixedstones();
-            (ban, fsb, fsw, score)
+            (ban, fsb, fsw, score, score as f32)
         }).collect::<Vec<_>>()
     }).collect();
     if show_path {println!();}
@@ -84,9 +258,24 @@ pub fn load_mate(files : &[String], d : &str,
     boards
 }
 
+/// "rfen,score[,value]"の石の差と評価値を読む。評価値が無ければ石の差を使う。
+pub fn parse_score_value(elem : &[&str]) -> Result<(i8, f32), String> {
+    let score = match elem.get(1).map(|s| s.parse::<i8>()) {
+        None => {return Err(String::from("error: no score"));},
+        Some(Err(msg)) => {return Err(format!("error: parse score : {msg}"));},
+        Some(Ok(num)) => {num},
+    };
+    let value = match elem.get(2).map(|s| s.parse::<f32>()) {
+        None => score as f32,
+        Some(Err(msg)) => {return Err(format!("error: parse value : {msg}"));},
+        Some(Ok(num)) => {num},
+    };
+    Ok((score, value))
+}
+
 #[allow(dead_code)]
 fn read_mate_file_all(buf : impl std::io::BufRead)
-        -> Result<Vec<(bitboard::BitBoard, i8, i8, i8)>, String> {
+        -> Result<Vec<bitboard::Sample>, String> {
     let mut ret = Vec::new();
 
     for line in buf.lines() {
@@ -95,16 +284,13 @@ fn read_mate_file_all(buf : impl std::io::BufRead)
             Ok(l) => {
                 // コメント行 or 11文字未満
                 if l.len() < 7 || l.starts_with("#") {continue;}
-                // rfen,score
+                // rfen,score[,value]
                 let elem : Vec<&str> = l.split(",").collect();
                 let ban = bitboard::BitBoard::try_from(elem[0])?;
 
                 let (b, w) = ban.fixedstones();
-                let score = match elem[1].parse::<i8>() {
-                    Err(msg) => {return Err(format!("error: parse score : {msg}"));},
-                    Ok(num) => {num},
-                };
-                ret.push((ban, b, w, score));
+                let (score, value) = parse_score_value(&elem)?;
+                ret.push((ban, b, w, score, value));
             }
         }
     }
@@ -114,7 +300,7 @@ fn read_mate_file_all(buf : impl std::io::BufRead)
 
 #[allow(dead_code)]
 fn read_mate_file(buf : impl std::io::BufRead, mate : u32)
-        -> Result<Vec<(bitboard::BitBoard, i8, i8, i8)>, String> {
+        -> Result<Vec<bitboard::Sample>, String> {
     let mut ret = Vec::new();
 
     for line in buf.lines() {
@@ -123,17 +309,14 @@ fn read_mate_file(buf : impl std::io::BufRead, mate : u32)
             Ok(l) => {
                 // コメント行 or 11文字未満
                 if l.len() < 11 || l.starts_with("#") {continue;}
-                // rfen,score
+                // rfen,score[,value]
                 let elem : Vec<&str> = l.split(",").collect();
                 let ban = bitboard::BitBoard::try_from(elem[0])?;
                 if !ban.is_last_n(mate) {continue;}
 
                 let (b, w) = ban.fixedstones();
-                let score = match elem[1].parse::<i8>() {
-                    Err(msg) => {return Err(format!("error: parse score : {msg}"));},
-                    Ok(num) => {num},
-                };
-                ret.push((ban, b, w, score));
+                let (score, value) = parse_score_value(&elem)?;
+                ret.push((ban, b, w, score, value));
             }
         }
     }
@@ -143,7 +326,7 @@ fn read_mate_file(buf : impl std::io::BufRead, mate : u32)
 
 #[allow(dead_code)]
 pub fn load_mates(path : &str, mate : u32)
-        -> Result<Vec<(bitboard::BitBoard, i8, i8, i8)>, String> {
+        -> Result<Vec<bitboard::Sample>, String> {
     let filepath = std::path::Path::new(path);
     if !filepath.exists() {return Err(format!("{path} does NOT exist!"));}
 
@@ -153,21 +336,29 @@ pub fn load_mates(path : &str, mate : u32)
         let z = zstd::Decoder::new(f)
                 .map_err(|e| format!("error: {e} @ zstd::Decoder::new"))?;
 
-        let buf = std::io::BufReader::new(z);
-        let ret = read_mate_file(buf, mate)?;
+        let mut buf = std::io::BufReader::new(z);
+        let ret = if is_binary(&mut buf)? {
+            read_binary_file(buf, |ban| ban.is_last_n(mate))?
+        } else {
+            read_mate_file(buf, mate)?
+        };
         Ok(ret)
     } else {
         let f = std::fs::File::open(path).map_err(|e| format!("{e}"))?;
 
-        let buf = std::io::BufReader::new(f);
-        let ret = read_mate_file(buf, mate)?;
+        let mut buf = std::io::BufReader::new(f);
+        let ret = if is_binary(&mut buf)? {
+            read_binary_file(buf, |ban| ban.is_last_n(mate))?
+        } else {
+            read_mate_file(buf, mate)?
+        };
         Ok(ret)
     }
 }
 
 #[allow(dead_code)]
 pub fn load_mates_all(path : &str)
-        -> Result<Vec<(bitboard::BitBoard, i8, i8, i8)>, String> {
+        -> Result<Vec<bitboard::Sample>, String> {
     let filepath = std::path::Path::new(path);
     if !filepath.exists() {return Err(format!("{path} does NOT exist!"));}
 
@@ -177,19 +368,192 @@ pub fn load_mates_all(path : &str)
         let z = zstd::Decoder::new(f)
                 .map_err(|e| format!("error: {e} @ zstd::Decoder::new"))?;
 
-        let buf = std::io::BufReader::new(z);
-        let ret = read_mate_file_all(buf)?;
+        let mut buf = std::io::BufReader::new(z);
+        let ret = if is_binary(&mut buf)? {
+            read_binary_file(buf, |_| true)?
+        } else {
+            read_mate_file_all(buf)?
+        };
         Ok(ret)
     } else {
         let f = std::fs::File::open(path).map_err(|e| format!("{e}"))?;
 
-        let buf = std::io::BufReader::new(f);
-        let ret = read_mate_file_all(buf)?;
+        let mut buf = std::io::BufReader::new(f);
+        let ret = if is_binary(&mut buf)? {
+            read_binary_file(buf, |_| true)?
+        } else {
+            read_mate_file_all(buf)?
+        };
         Ok(ret)
     }
 }
 
-pub fn dedupboards(boards : &mut Vec<(bitboard::BitBoard, i8, i8, i8)>,
+/// バイナリ形式のファイルの先頭4byte
+pub const BINARY_MAGIC : &[u8 ; 4] = b"IVPR";
+/// バイナリ形式の版
+pub const BINARY_VERSION : u16 = 1;
+/// ヘッダの大きさ[byte]
+/// magic(4), version(u16), 1局面の大きさ(u16)
+pub const BINARY_HEADER_SIZE : usize = 8;
+/// 1局面の大きさ[byte]
+/// black(u64), white(u64), teban, score, fsb, fsw, flags, 評価値x100(i16), 予備(1)
+pub const BINARY_RECORD_SIZE : usize = 24;
+/// 確定石の欄が有効
+pub const FLAG_FIXEDSTONES : u8 = 0x01;
+/// 完全読みの結果
+pub const FLAG_EXACT : u8 = 0x02;
+/// 評価値の欄が有効
+pub const FLAG_VALUE : u8 = 0x04;
+
+pub fn binary_header() -> [u8 ; BINARY_HEADER_SIZE] {
+    let mut header = [0u8 ; BINARY_HEADER_SIZE];
+    header[0..4].copy_from_slice(BINARY_MAGIC);
+    header[4..6].copy_from_slice(&BINARY_VERSION.to_le_bytes());
+    header[6..8].copy_from_slice(&(BINARY_RECORD_SIZE as u16).to_le_bytes());
+    header
+}
+
+/// 1局面をバイナリ形式にする。数値はlittle endian。
+/// 評価値は100倍して整数にする。
+pub fn encode_record(ban : &bitboard::BitBoard, fsb : i8, fsw : i8, score : i8,
+        value : f32, flags : u8) -> [u8 ; BINARY_RECORD_SIZE] {
+    let mut rec = [0u8 ; BINARY_RECORD_SIZE];
+    rec[0..8].copy_from_slice(&ban.black.to_le_bytes());
+    rec[8..16].copy_from_slice(&ban.white.to_le_bytes());
+    rec[16] = ban.teban as u8;
+    rec[17] = score as u8;
+    rec[18] = fsb as u8;
+    rec[19] = fsw as u8;
+    rec[20] = flags;
+    rec[21..23].copy_from_slice(&((value * 100.0).round() as i16).to_le_bytes());
+    rec
+}
+
+/// バイナリ形式の1局面を読む。
+///
+/// # Returns
+/// (局面, 黒の確定石数, 白の確定石数, 石の差, 評価値, flags)
+/// 評価値の欄が無効な時は石の差を評価値にする。
+pub fn decode_record(rec : &[u8])
+        -> Result<(bitboard::BitBoard, i8, i8, i8, f32, u8), String> {
+    if rec.len() != BINARY_RECORD_SIZE {
+        return Err(format!("invalid record size {}", rec.len()));
+    }
+    let black = u64::from_le_bytes(rec[0..8].try_into().unwrap());
+    let white = u64::from_le_bytes(rec[8..16].try_into().unwrap());
+    if black & white != 0 {
+        return Err(format!("black and white overlap {black:#x} {white:#x}"));
+    }
+    let teban = rec[16] as i8;
+    if teban != bitboard::SENTE && teban != bitboard::GOTE
+            && teban != bitboard::BLANK {
+        return Err(format!("invalid teban {teban}"));
+    }
+    let ban = bitboard::BitBoard {black, white, teban, pass : 0};
+    let score = rec[17] as i8;
+    let value = if rec[20] & FLAG_VALUE != 0 {
+        i16::from_le_bytes([rec[21], rec[22]]) as f32 / 100.0
+    } else {
+        score as f32
+    };
+    Ok((ban, rec[18] as i8, rec[19] as i8, score, value, rec[20]))
+}
+
+/// バイナリ形式のファイルかどうか先頭を覗いて調べる。
+fn is_binary(buf : &mut impl std::io::BufRead) -> Result<bool, String> {
+    let head = buf.fill_buf().map_err(|e| format!("{e}"))?;
+    Ok(head.starts_with(BINARY_MAGIC))
+}
+
+/// bufが尽きるかdestが埋まるまで読む。
+///
+/// # Returns
+/// 読んだバイト数。destより短ければファイルの終わり。
+fn read_full(buf : &mut impl std::io::Read, dest : &mut [u8]) -> Result<usize, String> {
+    let mut n = 0;
+    while n < dest.len() {
+        match buf.read(&mut dest[n..]) {
+            Ok(0) => {break;},
+            Ok(len) => {n += len;},
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {},
+            Err(e) => {return Err(format!("{e}"));},
+        }
+    }
+    Ok(n)
+}
+
+/// バイナリ形式のファイルを読む。
+/// 大きなファイルでも全部をメモリに読まないように1局面ずつ読む。
+///
+/// # Arguments
+/// - buf 読み込み元
+/// - filter 読み込む局面の条件
+fn read_binary_file(mut buf : impl std::io::Read,
+        filter : impl Fn(&bitboard::BitBoard) -> bool)
+        -> Result<Vec<bitboard::Sample>, String> {
+    let mut head = [0u8 ; BINARY_HEADER_SIZE];
+    let n = read_full(&mut buf, &mut head)?;
+    if n < BINARY_HEADER_SIZE || !head.starts_with(BINARY_MAGIC) {
+        return Err(String::from("invalid binary header"));
+    }
+    let version = u16::from_le_bytes([head[4], head[5]]);
+    if version != BINARY_VERSION {
+        return Err(format!("unsupported binary version {version}"));
+    }
+    let size = u16::from_le_bytes([head[6], head[7]]) as usize;
+    if size != BINARY_RECORD_SIZE {
+        return Err(format!("unsupported record size {size}"));
+    }
+
+    let mut ret = Vec::new();
+    let mut rec = [0u8 ; BINARY_RECORD_SIZE];
+    let mut offset = BINARY_HEADER_SIZE;
+    loop {
+        let n = read_full(&mut buf, &mut rec)?;
+        if n == 0 {break;}
+        if n < BINARY_RECORD_SIZE {
+            return Err(format!("truncated record at {offset}"));
+        }
+
+        offset += BINARY_RECORD_SIZE;
+        let (ban, fsb, fsw, score, value, flags) = decode_record(&rec)?;
+        if !filter(&ban) {continue;}
+
+        let (fsb, fsw) = if flags & FLAG_FIXEDSTONES != 0 {
+            (fsb, fsw)
+        } else {
+            ban.fixedstones()
+        };
+        ret.push((ban, fsb, fsw, score, value));
+    }
+    Ok(ret)
+}
+
+/// バイナリ形式の局面をファイルに追記する。空のファイルにはヘッダを付ける。
+pub fn append_binary_records(path : &std::path::Path, records : &[u8])
+        -> Result<(), std::io::Error> {
+    let mut f = std::fs::OpenOptions::new()
+        .create(true).append(true).open(path)?;
+    if f.metadata()?.len() == 0 {
+        f.write_all(&binary_header())?;
+    }
+    f.write_all(records)
+}
+
+/// 局面をバイナリ形式でファイルに追記する。
+/// 確定石の欄は局面から計算して入れる。
+/// 評価値の欄はflagsに`FLAG_VALUE`がある時だけ有効になる。
+pub fn append_binary(path : &std::path::Path,
+        boards : &[bitboard::Sample], flags : u8)
+        -> Result<(), std::io::Error> {
+    let records = boards.iter().flat_map(|(ban, _, _, score, value)| {
+        let (fsb, fsw) = ban.fixedstones();
+        encode_record(ban, fsb, fsw, *score, *value, flags | FLAG_FIXEDSTONES)
+    }).collect::<Vec<u8>>();
+    append_binary_records(path, &records)
+}
+
+pub fn dedupboards(boards : &mut Vec<bitboard::Sample>,
                    log : &mut std::fs::File, show_path : bool) {
     // println!("board: {} boards", boards.len());
     // let sta = std::time::Instant::now();
@@ -202,3 +566,276 @@ pub fn dedupboards(boards : &mut Vec<(bitboard::BitBoard, i8, i8, i8)>,
     log.write_all(msg.as_bytes()).unwrap();
     if show_path {print!("{msg}");}
 }
+
+/// 対称な局面をまとめる。
+///
+/// # Returns
+/// (代表局面, 代表局面から元の局面に戻す変換の配列)の配列。
+/// 最初に出てきた順に並ぶ。
+pub fn group_symmetric(boards : &[bitboard::Sample],
+                       log : &mut std::fs::File, show_path : bool)
+        -> Vec<(bitboard::BitBoard, Vec<bitboard::Transform>)> {
+    let mut index : std::collections::HashMap<bitboard::CanonicalKey, usize>
+            = std::collections::HashMap::new();
+    let mut groups : Vec<(bitboard::BitBoard, Vec<bitboard::Transform>)>
+            = Vec::new();
+    for (ban, _, _, _, _) in boards.iter() {
+        let (key, t) = ban.canonical();
+        match index.get(&key) {
+            Some(&i) => {groups[i].1.push(t.inverse());},
+            None => {
+                index.insert(key, groups.len());
+                groups.push((t.apply(ban), vec![t.inverse()]));
+            }
+        }
+    }
+    let msg = format!("canonical: {} boards\n", groups.len());
+    log.write_all(msg.as_bytes()).unwrap();
+    if show_path {print!("{msg}");}
+    groups
+}
+
+/// 代表局面の子供の局面の情報を元の局面の向きに戻す。
+pub fn restore_symmetric(children : &[bitboard::Sample],
+                         transforms : &[bitboard::Transform])
+        -> Vec<bitboard::Sample> {
+    transforms.iter().flat_map(|t| {
+        children.iter().map(|(ban, fsb, fsw, score, value)| {
+            if t.colorflip {
+                (t.apply(ban), *fsw, *fsb, t.apply_score(*score), -*value)
+            } else {
+                (t.apply(ban), *fsb, *fsw, *score, *value)
+            }
+        })
+    }).collect()
+}
+
+#[test]
+fn test_group_symmetric() {
+    let ban = bitboard::BitBoard::try_from(
+        "dD/AdC/BcC/BaAbAa/Af1/AaAaA1a1/BcC/G1 b").unwrap();
+    let other = bitboard::BitBoard::new();
+    let boards = vec![
+        (ban.clone(), 0, 0, 0, 0.0),
+        (ban.rotate90(), 0, 0, 0, 0.0),
+        (other.clone(), 0, 0, 0, 0.0),
+        (ban.flip_diag().flip_all(), 0, 0, 0, 0.0),
+    ];
+    let mut log = std::fs::File::create(
+        if cfg!(target_os="windows") {"nul"} else {"/dev/null"}).unwrap();
+    let groups = group_symmetric(&boards, &mut log, false);
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].1.len(), 3);
+    assert_eq!(groups[1].1.len(), 1);
+    for ((b, _, _, _, _), t) in boards.iter().filter(|(b, _, _, _, _)| *b != other)
+            .zip(groups[0].1.iter()) {
+        assert_eq!(t.apply(&groups[0].0).to_string(), b.to_string());
+    }
+
+    // 子供も元の向きに戻る
+    let canon = &groups[0].0;
+    let children = canon.genmove().unwrap().iter().map(|&mv| {
+        let child = canon.r#move(mv).unwrap();
+        let score = child.count();
+        (child, 1, 2, score, score as f32 + 0.5 * score.signum() as f32)
+    }).collect::<Vec<_>>();
+    let restored = restore_symmetric(&children, &groups[0].1);
+    assert_eq!(restored.len(), children.len() * 3);
+    for (i, (b, _, _, _, _)) in boards.iter().filter(|(b, _, _, _, _)| *b != other)
+            .enumerate() {
+        let mut expected = b.genmove().unwrap().iter().map(|&mv| {
+            b.r#move(mv).unwrap().to_string()
+        }).collect::<Vec<_>>();
+        let mut actual = restored[i * children.len()..(i + 1) * children.len()]
+            .iter().map(|(c, fsb, fsw, score, value)| {
+                assert_eq!(*score, c.count());
+                // 色を入れ替えたら評価値の符号も変わる
+                assert_eq!(*value, *score as f32 + 0.5 * score.signum() as f32);
+                assert_eq!(*fsb + *fsw, 3);
+                c.to_string()
+            }).collect::<Vec<_>>();
+        expected.sort();
+        actual.sort();
+        assert_eq!(actual, expected);
+    }
+}
+
+#[test]
+fn test_binary_record() {
+    let tbl = [
+        ("8/8/8/3Aa3/3aA3/8/8/8 b", 0, 0.0),
+        ("dD/AdC/BcC/BaAbAa/Af1/AaAaA1a1/BcC/G1 b", -12, -12.34),
+        ("H/H/H/H/H/h/h/h w", 16, 15.5),
+    ];
+    let boards = tbl.iter().map(|(rfen, score, value)| {
+        let ban = bitboard::BitBoard::try_from(*rfen).unwrap();
+        let (fsb, fsw) = ban.fixedstones();
+        (ban, fsb, fsw, *score, *value)
+    }).collect::<Vec<_>>();
+
+    let path = std::env::temp_dir().join(
+        format!("incuversi_test_binary_{}.bin", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    append_binary(&path, &boards[0..1], 0).unwrap();
+    append_binary(&path, &boards[1..], FLAG_EXACT | FLAG_VALUE).unwrap();
+    let size = std::fs::metadata(&path).unwrap().len() as usize;
+    assert_eq!(size, BINARY_HEADER_SIZE + BINARY_RECORD_SIZE * boards.len());
+
+    let loaded = load_mates_all(path.to_str().unwrap()).unwrap();
+    assert_eq!(loaded.len(), boards.len());
+    for (a, b) in loaded.iter().zip(boards.iter()) {
+        assert!(a == b);
+        assert_eq!(a.0.to_string(), b.0.to_string());
+    }
+    let loaded = load_mates(path.to_str().unwrap(), 4).unwrap();
+    assert_eq!(loaded.len(), 1);
+    assert_eq!(loaded[0].0.to_string(), tbl[1].0);
+
+    // 壊れたファイル
+    let mut data = std::fs::read(&path).unwrap();
+    data.pop();
+    std::fs::write(&path, &data).unwrap();
+    assert!(load_mates_all(path.to_str().unwrap()).is_err());
+    data[4] = 99;
+    std::fs::write(&path, &data).unwrap();
+    match load_mates_all(path.to_str().unwrap()) {
+        Ok(_) => {panic!("broken version was accepted.");},
+        Err(err) => {assert!(err.contains("version"), "{err}");},
+    }
+    std::fs::remove_file(&path).unwrap();
+
+    // 少しずつしか読めなくても同じ
+    struct Slow<'a>(&'a [u8]);
+    impl std::io::Read for Slow<'_> {
+        fn read(&mut self, buf : &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(5);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+    let mut data = binary_header().to_vec();
+    for (ban, fsb, fsw, score, value) in boards.iter() {
+        data.extend_from_slice(&encode_record(ban, *fsb, *fsw, *score, *value, FLAG_VALUE));
+    }
+    let loaded = read_binary_file(Slow(&data), |_| true).unwrap();
+    assert!(loaded == boards);
+    data.truncate(data.len() - 3);
+    assert_eq!(read_binary_file(Slow(&data), |_| true).err(),
+        Some(format!("truncated record at {}", BINARY_HEADER_SIZE + BINARY_RECORD_SIZE * 2)));
+    assert!(read_binary_file(Slow(&data[..5]), |_| true).is_err());
+
+    let rec = encode_record(&boards[1].0, 1, 2, -12, -12.34, FLAG_EXACT);
+    let (ban, fsb, fsw, score, value, flags) = decode_record(&rec).unwrap();
+    assert_eq!(ban.to_string(), tbl[1].0);
+    assert_eq!((fsb, fsw, score, value, flags), (1, 2, -12, -12.0, FLAG_EXACT));
+    let rec = encode_record(&boards[1].0, 1, 2, -12, -12.34, FLAG_VALUE);
+    assert_eq!(decode_record(&rec).unwrap().4, -12.34);
+}
+
+#[test]
+fn test_read_mate_file_value() {
+    let txt = "# comment\n\
+        8/8/8/3Aa3/3aA3/8/8/8 b,0\n\
+        dD/AdC/BcC/BaAbAa/Af1/AaAaA1a1/BcC/G1 b,-1,-1.62\n";
+    let boards = read_mate_file_all(txt.as_bytes()).unwrap();
+    assert_eq!(boards.len(), 2);
+    assert_eq!((boards[0].3, boards[0].4), (0, 0.0));
+    assert_eq!((boards[1].3, boards[1].4), (-1, -1.62));
+    assert!(read_mate_file_all("8/8/8/3Aa3/3aA3/8/8/8 b,0,x\n".as_bytes()).is_err());
+}
+
+#[test]
+fn test_read_kifu_zst() {
+    let txt = kifu::random_kifu(3).to_str();
+    let dir = std::env::temp_dir().join(
+        format!("incuversi_test_kifu_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("kifu1.txt"), &txt).unwrap();
+    let z = zstd::encode_all(txt.as_bytes(), 3).unwrap();
+    std::fs::write(dir.join("kifu2.txt.zst"), z).unwrap();
+
+    let d = dir.to_str().unwrap();
+    assert_eq!(read_kifu(&format!("{d}/kifu2.txt.zst")).unwrap(), txt);
+
+    // 局面のファイルは棋譜として読まない
+    std::fs::write(dir.join("mate5.bin"), binary_header()).unwrap();
+    std::fs::write(dir.join("mate5.bin.bak"), b"").unwrap();
+    let files = findfiles(d);
+    assert_eq!(files, vec!["kifu1.txt", "kifu2.txt.zst"]);
+    assert_eq!(find_mate_files(d), vec!["kifu1.txt", "kifu2.txt.zst", "mate5.bin"]);
+    assert!(is_mate_file("mate5.bin.zstd"));
+    assert!(!is_mate_file("mate5.txt.bak"));
+    let mut log = std::fs::File::create(
+        if cfg!(target_os="windows") {"nul"} else {"/dev/null"}).unwrap();
+    let boards = loadkifu_for_mate(&files, d, 10, &mut log, false);
+    assert_eq!(boards.len(), 2);
+    assert!(boards[0] == boards[1]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_loadkifu_archive() {
+    let txt1 = kifu::random_kifu(4).to_str();
+    let txt2 = kifu::random_kifu(5).to_str();
+    let z2 = zstd::encode_all(txt2.as_bytes(), 3).unwrap();
+    let tar = tarball::build_tar(&[
+        ("kifu/kifu1.txt", txt1.as_bytes()),
+        ("kifu/readme.md", b"not a kifu"),
+        ("kifu/kifu2.txt.zst", &z2),
+    ]);
+    let dir = std::env::temp_dir().join(
+        format!("incuversi_test_archive_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path_tar = dir.join("kifu.tar");
+    std::fs::write(&path_tar, &tar).unwrap();
+    let path_zst = dir.join("kifu.tar.zst");
+    std::fs::write(&path_zst, zstd::encode_all(tar.as_slice(), 3).unwrap()).unwrap();
+
+    let mut log = std::fs::File::create(
+        if cfg!(target_os="windows") {"nul"} else {"/dev/null"}).unwrap();
+    for path in [path_tar, path_zst] {
+        let path = path.to_str().unwrap();
+        assert!(is_archive(path));
+        let kifus = archive_kifus(path).unwrap()
+            .collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(kifus, vec![
+            (String::from("kifu/kifu1.txt"), txt1.clone()),
+            (String::from("kifu/kifu2.txt.zst"), txt2.clone()),
+        ]);
+
+        let mut boards = loadkifu_dir_for_mate(path, 10, &mut log, false);
+        let mut expected = extract_mate(&txt1, 10);
+        expected.extend(extract_mate(&txt2, 10));
+        assert_eq!(boards.len(), expected.len());
+        boards.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        expected.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        assert!(boards == expected);
+    }
+    assert!(!is_archive(dir.to_str().unwrap()));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_extract_midgame() {
+    let kifu = kifu::random_kifu(6);
+    let txt = kifu.to_str() + "\n";
+    let score = kifu.score.unwrap() as f32;
+    let nply = kifu.list.len();
+
+    let boards = extract_midgame(&txt, |_| true, None).unwrap();
+    assert_eq!(boards.len(), nply);
+    assert!(boards.iter().all(|(_, v)| *v == score));
+
+    let boards = extract_midgame(&txt, |ban| (20..=30).contains(&ban.nblank()), None).unwrap();
+    assert!(!boards.is_empty());
+    assert!(boards.iter().all(|(ban, _)| (20..=30).contains(&ban.nblank())));
+
+    // 終局に近いほど割り引かない
+    let boards = extract_midgame(&txt, |_| true, Some(0.9)).unwrap();
+    assert_eq!(boards[nply - 1].1, score * 0.9);
+    assert_eq!(boards[0].1, score * 0.9f32.powi(nply as i32));
+
+    let txt = txt.replace(&kifu.to_str().lines().last().unwrap().to_string(), "");
+    assert!(extract_midgame(&txt, |_| true, None).is_err());
+}
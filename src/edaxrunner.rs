@@ -0,0 +1,333 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::bitboard::{self, BitBoard};
+use crate::engine::{Children, Engine};
+use crate::engineconfig::EngineConfig;
+use crate::ruversirunner;
+
+/// 一時ファイルの名前がぶつからないようにする
+static OBF_COUNTER : AtomicUsize = AtomicUsize::new(0);
+
+/// run Edax with `-solve`
+pub struct EdaxRunner {
+    curdir : String,
+    path : String,
+    evfile : String,
+    verbose : bool,
+    args : Vec<String>,
+    depth : Option<u32>,
+    timeout : Option<Duration>,
+}
+
+impl std::fmt::Display for EdaxRunner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "curdir:{}, edax:{}, evfile:{}, args:{:?}",
+               self.curdir, self.path, self.evfile, self.args)
+    }
+}
+
+/// `edax -solve`の出力を読む。
+///
+/// ex.
+///  # | depth|score|       time   |  nodes (N)  |   N/s      | principal variation
+/// ---+------+-----+--------------+-------------+------------+---------------------
+///   1|   14   +18        0:00.011        83466      7587818 g8 h8 ...
+///
+/// # Returns
+/// 問題の順に(手番から見た石の差, 最善手)
+#[cfg(test)]
+fn parse_solve_output(txt : &str) -> Vec<(i8, String)> {
+    let ptn = solve_line_pattern();
+    txt.lines().filter_map(|line| parse_solve_line(&ptn, line)).collect()
+}
+
+/// `edax -solve`の結果の行
+fn solve_line_pattern() -> regex::Regex {
+    regex::Regex::new(
+        "^\\s*\\d+\\|\\s*\\S+\\s+([-+]?\\d+)\\s+[0-9:.]+\\s+\\d+\\s+(?:\\d+\\s+)?(.*)$")
+        .unwrap()
+}
+
+/// 結果の行なら(手番から見た石の差, 最善手)
+fn parse_solve_line(ptn : &regex::Regex, line : &str) -> Option<(i8, String)> {
+    let cap = ptn.captures(line)?;
+    let score = cap[1].parse::<i8>().ok()?;
+    let pos = cap[2].split_whitespace().next().unwrap_or("").to_lowercase();
+    Some((score, pos))
+}
+
+/// 両者打てない局面かどうか。
+fn is_finished(ban : &BitBoard) -> bool {
+    ban.mobility() == 0 && ban.r#move(bitboard::PASS).unwrap().mobility() == 0
+}
+
+/// 子供の局面を全部並べる。
+fn child_boards(ban : &BitBoard) -> Vec<BitBoard> {
+    ban.genmove().unwrap_or_default().iter().map(|&mv| ban.r#move(mv).unwrap()).collect()
+}
+
+/// 子供の局面に黒から見た石の差を付ける。
+///
+/// # Arguments
+/// - children 子供の局面
+/// - scores 終局していない子供の局面の手番から見た石の差を順に並べたもの
+fn label_children(children : Vec<BitBoard>, scores : &[i8]) -> Children {
+    let mut scores = scores.iter();
+    children.into_iter().map(|child| {
+        // 終局している局面は読まなくて良い
+        let score = if is_finished(&child) {
+            child.count()
+        } else {
+            scores.next().unwrap() * child.teban
+        };
+        (child, 0, 0, score, score as f32)
+    }).collect()
+}
+
+impl EdaxRunner {
+    pub fn new() -> EdaxRunner {
+        EdaxRunner {
+            curdir : String::from("../edax"),
+            path : String::from("./bin/edax"),
+            evfile : String::from("data/eval.dat"),
+            verbose : false,
+            args : vec!["-n".to_string(), "1".to_string()],
+            depth : None,
+            timeout : None,
+        }
+    }
+
+    pub fn set_verbose(&mut self, verbose : bool) {
+        self.verbose = verbose;
+    }
+
+    pub fn set_timeout(&mut self, timeout : Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    #[allow(dead_code)]
+    pub fn from_config(path : &std::path::Path)
+            -> Result<EdaxRunner, String> {
+        let mut er = EdaxRunner::new();
+        if path.as_os_str().is_empty() {
+            return Ok(er);
+        }
+
+        er.read(path)?;
+        Ok(er)
+    }
+
+    /// read config from a file. keys are same as ruversi.
+    ///
+    /// ex.
+    /// engine: edax
+    /// curdir: ~/edax/
+    /// path: ./bin/lEdax-x64
+    /// evfile: ./data/eval.dat
+    /// args: -n,4
+    /// depth: 30
+    ///
+    /// depthは完全読みでない時の`-level`になる。
+    #[allow(dead_code)]
+    pub fn read(&mut self, path : &std::path::Path) -> Result<(), String> {
+        self.configure(&EngineConfig::load(path, None)?)
+    }
+
+    /// 設定ファイルの内容を反映する。書いていない項目はそのまま。
+    pub fn configure(&mut self, cfg : &EngineConfig) -> Result<(), String> {
+        if let Some(cd) = &cfg.curdir {self.curdir = cd.clone();}
+        if let Some(path) = &cfg.path {self.path = path.clone();}
+        if let Some(evf) = &cfg.evfile {self.evfile = evf.clone();}
+        if let Some(args) = &cfg.args {self.args = args.clone();}
+        if cfg.depth.is_some() {self.depth = cfg.depth;}
+        if cfg.timeout.is_some() {self.timeout = cfg.timeout;}
+        if cfg.cache.is_some() {
+            return Err(String::from("cache is not supported with edax."));
+        }
+        Ok(())
+    }
+
+    /// 実行ファイルのパス。相対パスはcurdirからの相対パスとして扱う。
+    fn program(&self) -> std::path::PathBuf {
+        let path = std::path::Path::new(&self.path);
+        if path.is_relative() && path.components().count() > 1 {
+            std::path::Path::new(&self.curdir).join(path)
+        } else {
+            path.to_path_buf()
+        }
+    }
+
+    /// 局面をOBFのファイルに書いて`-solve`で読んでもらう。
+    ///
+    /// # Arguments
+    /// - boards 読んでもらう局面
+    /// - exact trueなら最後まで読む
+    ///
+    /// # Returns
+    /// 局面の順に(手番から見た石の差, 最善手)
+    fn run_obf(&self, boards : &[BitBoard], exact : bool)
+            -> Result<Vec<(i8, String)>, String> {
+        let mut ret = Vec::new();
+        self.run_obf_each(boards, exact, &mut |res| ret.push(res))?;
+        Ok(ret)
+    }
+
+    /// `run_obf()`と同じだが、1つのedaxが1局面読み終わる毎に結果をeachに渡す。
+    /// timeoutは1局面毎の制限時間になる。
+    fn run_obf_each(&self, boards : &[BitBoard], exact : bool,
+            each : &mut dyn FnMut((i8, String))) -> Result<(), String> {
+        let obf = std::env::temp_dir().join(format!("incuversi_{}_{}.obf",
+            std::process::id(), OBF_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        {
+            let mut f = std::fs::File::create(&obf).map_err(|e| e.to_string())?;
+            for ban in boards {
+                writeln!(f, "{};", ban.to_obf()).map_err(|e| e.to_string())?;
+            }
+        }
+
+        let mut cmd = Command::new(self.program());
+        cmd.current_dir(&self.curdir)
+            .arg("-solve").arg(&obf).arg("-eval-file").arg(&self.evfile)
+            .args(&self.args);
+        if exact {
+            cmd.arg("-level").arg("60");
+        } else if let Some(depth) = self.depth {
+            cmd.arg("-level").arg(format!("{depth}"));
+        }
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        if self.verbose {eprintln!("cmd:{cmd:?}");}
+        let ptn = solve_line_pattern();
+        let mut n = 0;
+        let output = cmd.spawn()
+            .map_err(|e| format!("error running edax... [{e}], config:[{self}]"))
+            .and_then(|child| ruversirunner::wait_output_lines(child, self.timeout, |line| {
+                let Some(res) = parse_solve_line(&ptn, line) else {return false;};
+                n += 1;
+                if n <= boards.len() {each(res);}
+                true
+            }).map_err(|e| format!("{e}, config:[{self}]")));
+        std::fs::remove_file(&obf).map_err(|e| e.to_string())?;
+        let (txt, stderr) = output?;
+        if self.verbose {println!("txt:{txt}");}
+
+        if n != boards.len() {
+            return Err(ruversirunner::with_stderr(
+                format!("edax returned {n} results for {} positions. \"{txt}\"",
+                    boards.len()), &stderr));
+        }
+        Ok(())
+    }
+}
+
+impl Engine for EdaxRunner {
+    fn evaluate(&self, ban : &BitBoard) -> Result<(String, f32), String> {
+        if is_finished(ban) {return Ok((String::new(), ban.count() as f32));}
+
+        let (score, pos) = self.run_obf(std::slice::from_ref(ban), false)?.remove(0);
+        Ok((pos, (score * ban.teban) as f32))
+    }
+
+    fn children(&self, ban : &BitBoard)
+            -> Result<Vec<bitboard::Sample>, String> {
+        let children = child_boards(ban);
+        let unfinished = children.iter().filter(|c| !is_finished(c))
+            .cloned().collect::<Vec<_>>();
+        let scores = if unfinished.is_empty() {
+            Vec::new()
+        } else {
+            self.run_obf(&unfinished, true)?.into_iter().map(|(s, _)| s).collect()
+        };
+        Ok(label_children(children, &scores))
+    }
+
+    /// 全部の親の子供の局面を1つのOBFにして1つのedaxで読む。
+    fn children_batch(&self, bans : &[BitBoard],
+            done : &mut dyn FnMut(usize, Children)) -> Result<(), String> {
+        let children = bans.iter().map(child_boards).collect::<Vec<_>>();
+        // 親毎に終局していない子供の局面の数を足していったもの
+        let ends = children.iter().scan(0, |n, c| {
+            *n += c.iter().filter(|c| !is_finished(c)).count();
+            Some(*n)
+        }).collect::<Vec<_>>();
+        let unfinished = children.iter().flatten().filter(|c| !is_finished(c))
+            .cloned().collect::<Vec<_>>();
+        // 子供の局面を全部読み終わった親から順に返す
+        let flush = |scores : &[i8], next : &mut usize,
+                done : &mut dyn FnMut(usize, Children)| {
+            while *next < bans.len() && ends[*next] <= scores.len() {
+                let start = if *next == 0 {0} else {ends[*next - 1]};
+                done(*next, label_children(children[*next].clone(), &scores[start..ends[*next]]));
+                *next += 1;
+            }
+        };
+        let mut scores = Vec::new();
+        let mut next = 0;
+        flush(&scores, &mut next, done);
+        if !unfinished.is_empty() {
+            self.run_obf_each(&unfinished, true, &mut |(score, _)| {
+                scores.push(score);
+                flush(&scores, &mut next, done);
+            })?;
+        }
+        Ok(())
+    }
+
+    fn is_batched(&self) -> bool {true}
+
+    fn solve(&self, ban : &BitBoard) -> Result<i8, String> {
+        if is_finished(ban) {return Ok(ban.count());}
+
+        let (score, _) = self.run_obf(std::slice::from_ref(ban), true)?.remove(0);
+        Ok(score * ban.teban)
+    }
+
+    fn is_exact(&self) -> bool {true}
+
+    fn set_timeout(&mut self, timeout : Option<Duration>) {
+        EdaxRunner::set_timeout(self, timeout);
+    }
+}
+
+#[test]
+fn test_parse_solve_output() {
+    let txt = "\
+ # | depth|score|       time   |  nodes (N)  |   N/s      | principal variation
+---+------+-----+--------------+-------------+------------+---------------------
+  1|   14   +18        0:00.011        83466      7587818 G8 h8 ps a1
+  2| 21@98%  -04        0:00.000           12             ps
+  3|   10   +00        0:01.234      1234567      1000459 b2
+------------------------------------------------------------------------------
+A total of 1318045 nodes in 1.245 s (1058670 N/s).
+";
+    assert_eq!(parse_solve_output(txt), vec![
+        (18, String::from("g8")), (-4, String::from("ps")), (0, String::from("b2"))]);
+    assert!(parse_solve_output("").is_empty());
+}
+
+#[test]
+fn test_edaxrunner_read_config() {
+    let config_path = std::env::temp_dir().join(
+        format!("test_edaxrunner_config_{}.txt", std::process::id()));
+    std::fs::write(&config_path, "\
+engine: edax
+curdir:/tmp/edax
+path:./lEdax-x64
+args:-n,4
+").unwrap();
+    let er = EdaxRunner::from_config(&config_path).unwrap();
+    assert_eq!(er.curdir, "/tmp/edax");
+    assert_eq!(er.program(), std::path::PathBuf::from("/tmp/edax/./lEdax-x64"));
+    assert_eq!(er.evfile, "data/eval.dat");
+    assert_eq!(er.args, vec!["-n", "4"]);
+    std::fs::remove_file(&config_path).unwrap();
+
+    // 終局している局面はedax無しで分かる
+    let ban = BitBoard::try_from("H/H/H/H/H/h/h/h b").unwrap();
+    assert_eq!(er.solve(&ban), Ok(16));
+    assert!(er.children(&ban).unwrap().is_empty());
+    // edaxが無ければエラー
+    assert!(er.solve(&BitBoard::new()).is_err());
+}
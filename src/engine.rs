@@ -0,0 +1,322 @@
+use crate::argument::Rounding;
+use crate::bitboard::{self, BitBoard};
+use crate::edaxrunner::EdaxRunner;
+use crate::engineconfig::EngineConfig;
+use crate::kifu;
+use crate::ruversirunner::RuversiRunner;
+use crate::solver;
+
+use std::time::Duration;
+
+/// 局面を読んでくれるもの。
+///
+/// 評価値や石の差は全て黒から見た値(黒 - 白)で返す。
+pub trait Engine : Sync {
+    /// 局面を評価する。
+    ///
+    /// # Returns
+    /// (最善手, 評価値)
+    fn evaluate(&self, ban : &BitBoard) -> Result<(String, f32), String>;
+
+    /// 子供の局面を全部評価する。
+    ///
+    /// # Returns
+    /// 子供の局面の情報(Bitboard, 確定石(黒), 確定石(白), 石の差, 評価値)。
+    /// 石の差は評価値を`set_rounding()`の方法で整数にしたもの。
+    /// ダブり解消の処理のために確定石の欄にゼロを入れている。
+    fn children(&self, ban : &BitBoard)
+        -> Result<Vec<bitboard::Sample>, String>;
+
+    /// `children()`と同じだが読み筋をたどった局面も返す。
+    /// 読み筋を出力しないエンジンは空を返す。
+    ///
+    /// # Returns
+    /// (子供の局面の情報, 読み筋の局面の情報)。読み筋の局面の値はその子供の局面と同じ。
+    fn children_pv(&self, ban : &BitBoard) -> Result<(Children, Children), String> {
+        Ok((self.children(ban)?, Vec::new()))
+    }
+
+    /// 何局面もまとめて`children()`する。
+    ///
+    /// 読み終わった局面から順に`done(番号, 子供の局面の情報)`を呼ぶ。
+    /// 途中で失敗したらErr。それまでに`done`に渡した結果はそのまま使える。
+    fn children_batch(&self, bans : &[BitBoard],
+            done : &mut dyn FnMut(usize, Children)) -> Result<(), String> {
+        for (i, ban) in bans.iter().enumerate() {
+            done(i, self.children(ban)?);
+        }
+        Ok(())
+    }
+
+    /// 1つのプロセスで`children_batch()`の局面を全部読めるかどうか。
+    /// trueなら起動や評価関数の読み込みが1回で済むので局面をまとめて渡す。
+    fn is_batched(&self) -> bool {false}
+
+    /// 完全読みをする。
+    ///
+    /// # Returns
+    /// 最善を尽くした場合の最終的な石の差
+    #[allow(dead_code)]
+    fn solve(&self, ban : &BitBoard) -> Result<i8, String>;
+
+    /// `children()`の値が完全読みの結果かどうか。
+    fn is_exact(&self) -> bool {false}
+
+    /// 1回の呼び出しの制限時間。外部プログラムを使わないものは無視する。
+    fn set_timeout(&mut self, _timeout : Option<Duration>) {}
+
+    /// 評価値を石の差にする方法。評価値が整数のものは無視する。
+    fn set_rounding(&mut self, _rounding : Rounding) {}
+}
+
+/// 局面の情報(Bitboard, 確定石(黒), 確定石(白), 石の差, 評価値)の配列
+pub type Children = Vec<bitboard::Sample>;
+
+/// 子供の局面の値から親の局面の値を求める。
+/// 子供が無い時は終局しているので石の差を返す。
+fn best_of_children(ban : &BitBoard, children : &[bitboard::Sample]) -> i8 {
+    let scores = children.iter().map(|(_, _, _, score, _)| *score);
+    let best = if ban.teban == bitboard::GOTE {scores.min()} else {scores.max()};
+    best.unwrap_or_else(|| ban.count())
+}
+
+impl Engine for RuversiRunner {
+    fn evaluate(&self, ban : &BitBoard) -> Result<(String, f32), String> {
+        let (pos, score) = self.run(&ban.to_string())?;
+        let score = score.parse::<f32>().map_err(
+            |e| format!("invalid score \"{score}\" from ruversi : {e}"))?;
+        Ok((pos, score))
+    }
+
+    fn children(&self, ban : &BitBoard)
+            -> Result<Vec<bitboard::Sample>, String> {
+        self.run_children(&ban.to_string())
+    }
+
+    fn children_pv(&self, ban : &BitBoard) -> Result<(Children, Children), String> {
+        let mut children = Vec::new();
+        let mut samples = Vec::new();
+        for (child, value, pv) in self.run_children_pv(&ban.to_string())? {
+            let score = self.label(value);
+            samples.extend(replay_pv(&child, score, value, &pv)?);
+            children.push((child, 0, 0, score, value));
+        }
+        Ok((children, samples))
+    }
+
+    fn solve(&self, ban : &BitBoard) -> Result<i8, String> {
+        // 子供を最後まで読んでもらってその中から一番良いものを選ぶ
+        let children = self.run_children(&ban.to_string())?;
+        Ok(best_of_children(ban, &children))
+    }
+
+    fn set_timeout(&mut self, timeout : Option<Duration>) {
+        RuversiRunner::set_timeout(self, timeout);
+    }
+
+    fn set_rounding(&mut self, rounding : Rounding) {
+        RuversiRunner::set_rounding(self, rounding);
+    }
+}
+
+/// 手を"d3"や"ps"の形式にする。
+fn move_to_str(mv : u8) -> String {
+    let (x, y) = if mv == bitboard::PASS {(0, 0)} else {bitboard::cell2xy(mv)};
+    kifu::Te::new(x as usize, y as usize, bitboard::BLANK, String::new())
+        .pos().to_lowercase()
+}
+
+/// "d3"や"ps"の手をマスの番号にする。
+pub fn move_from_str(txt : &str) -> Result<u8, String> {
+    let txt = txt.to_ascii_lowercase();
+    if txt == "ps" {return Ok(bitboard::PASS);}
+
+    let mut chars = txt.chars();
+    let x = chars.next().and_then(|c| "abcdefgh".find(c));
+    let y = chars.next().and_then(|c| c.to_digit(10)).filter(|y| (1..=8).contains(y));
+    match (x, y, chars.next()) {
+        (Some(x), Some(y), None) => Ok(bitboard::cell(x as u8 + 1, y as u8)),
+        _ => Err(format!("invalid move \"{txt}\"")),
+    }
+}
+
+/// 読み筋をたどった局面を返す。
+/// 最善を尽くすと最終結果は変わらないので値は全部scoreとvalueにする。
+/// 読み筋で省略されているパスは補う。
+///
+/// # Arguments
+/// - ban 読み筋の最初の局面。これ自体は返さない。
+/// - score 最終結果
+/// - value 評価値
+/// - pv 読み筋
+pub fn replay_pv(ban : &BitBoard, score : i8, value : f32, pv : &[String])
+        -> Result<Vec<bitboard::Sample>, String> {
+    let mut ret = Vec::new();
+    let mut cur = ban.clone();
+    for txt in pv {
+        let mv = move_from_str(txt)?;
+        let moves = cur.mobility();
+        if mv == bitboard::PASS {
+            if moves != 0 {return Err(format!("can not pass at \"{cur}\" in pv {pv:?}"));}
+        } else if moves & (bitboard::LSB_CELL << mv) == 0 {
+            // パスが省略されている?
+            let passed = cur.r#move(bitboard::PASS).unwrap();
+            if moves != 0 || passed.mobility() & (bitboard::LSB_CELL << mv) == 0 {
+                return Err(format!("illegal move {txt} at \"{cur}\" in pv {pv:?}"));
+            }
+            cur = passed;
+            ret.push((cur.clone(), 0, 0, score, value));
+        }
+        cur = cur.r#move(mv).unwrap();
+        ret.push((cur.clone(), 0, 0, score, value));
+    }
+    Ok(ret)
+}
+
+/// 組み込みの完全読み
+pub struct Builtin {}
+
+impl Engine for Builtin {
+    fn evaluate(&self, ban : &BitBoard) -> Result<(String, f32), String> {
+        let children = solver::solve_children(ban)?;
+        let best = best_of_children(ban, &children);
+        let moves = ban.genmove().unwrap_or_default();
+        let pos = children.iter().zip(moves).find_map(|((_, _, _, score, _), mv)| {
+            if *score != best {return None;}
+
+            Some(move_to_str(mv))
+        }).unwrap_or_default();
+        Ok((pos, best as f32))
+    }
+
+    fn children(&self, ban : &BitBoard)
+            -> Result<Vec<bitboard::Sample>, String> {
+        solver::solve_children(ban)
+    }
+
+    fn solve(&self, ban : &BitBoard) -> Result<i8, String> {
+        if ban.nblank() > solver::MAX_EMPTIES {
+            return Err(format!("too many empty cells to solve. \"{ban}\""));
+        }
+        Ok(solver::solve(ban))
+    }
+
+    fn is_exact(&self) -> bool {true}
+}
+
+/// 設定ファイルに従ってエンジンを用意する。
+///
+/// # Arguments
+/// - path 設定ファイル。"engine:"でruversi, edax, builtinを選ぶ。
+/// - profile 設定ファイルの中のプロファイル
+/// - use_solver trueなら設定ファイルに関係なく組み込みの完全読みを使う。
+/// - verbose
+pub fn from_config(path : &std::path::PathBuf, profile : Option<&str>,
+        use_solver : bool, verbose : bool) -> Result<Box<dyn Engine>, String> {
+    if use_solver {return Ok(Box::new(Builtin {}));}
+
+    let cfg = if path.as_os_str().is_empty() {
+        if let Some(name) = profile {
+            return Err(format!("profile \"{name}\" needs a config file."));
+        }
+        EngineConfig::default()
+    } else {
+        EngineConfig::load(path, profile)?
+    };
+    match cfg.engine.as_deref().unwrap_or("ruversi") {
+        "ruversi" => {
+            let mut rr = RuversiRunner::new();
+            rr.configure(&cfg)?;
+            rr.set_verbose(verbose);
+            Ok(Box::new(rr))
+        },
+        "edax" => {
+            let mut er = EdaxRunner::new();
+            er.configure(&cfg)?;
+            er.set_verbose(verbose);
+            Ok(Box::new(er))
+        },
+        "builtin" => Ok(Box::new(Builtin {})),
+        kind => Err(format!("unknown engine \"{kind}\" in {path:?}")),
+    }
+}
+
+#[test]
+fn test_builtin() {
+    let ban = BitBoard::try_from(
+        "dD/AdC/BcC/BaAbAa/Af1/AaAaA1a1/BcC/G1 b").unwrap();
+    let engine = Builtin {};
+    let best = engine.solve(&ban).unwrap();
+    assert_eq!(best, solver::solve(&ban));
+    let (pos, val) = engine.evaluate(&ban).unwrap();
+    assert_eq!(val, best as f32);
+    let mv = ban.genmove().unwrap().into_iter()
+        .find(|&mv| move_to_str(mv) == pos).unwrap();
+    assert_eq!(solver::solve(&ban.r#move(mv).unwrap()), best);
+
+    let children = engine.children(&ban).unwrap();
+    assert_eq!(best_of_children(&ban, &children), best);
+
+    assert!(engine.solve(&BitBoard::new()).is_err());
+}
+
+#[test]
+fn test_replay_pv() {
+    let pv = |txt : &str| txt.split(' ').map(String::from).collect::<Vec<_>>();
+    // 初期局面から合法手を2手たどる
+    let ban = BitBoard::new();
+    let child = ban.r#move(ban.genmove().unwrap()[0]).unwrap();
+    let mv1 = child.genmove().unwrap()[0];
+    let b1 = child.r#move(mv1).unwrap();
+    let mv2 = b1.genmove().unwrap()[0];
+    let b2 = b1.r#move(mv2).unwrap();
+    let samples = replay_pv(&child, -2, -1.62,
+        &[move_to_str(mv1), move_to_str(mv2).to_uppercase()]).unwrap();
+    assert_eq!(samples.len(), 2);
+    assert!(samples[0].0 == b1);
+    assert!(samples[1].0 == b2);
+    assert!(samples.iter().all(|(_, _, _, s, v)| *s == -2 && *v == -1.62));
+    assert!(replay_pv(&child, 0, 0.0, &[]).unwrap().is_empty());
+    assert!(replay_pv(&child, 0, 0.0, &pv("a1")).is_err());
+    assert!(replay_pv(&child, 0, 0.0, &pv("ps")).is_err());
+    assert!(replay_pv(&child, 0, 0.0, &pv("z9")).is_err());
+
+    // 白がパスする局面。パスを省略しても補う。
+    let ban = BitBoard::try_from("H/H/H/H/H/H/Fa1/F2 w").unwrap();
+    assert_eq!(ban.mobility(), 0);
+    let withpass = replay_pv(&ban, 64, 64.0, &pv("ps h7")).unwrap();
+    let nopass = replay_pv(&ban, 64, 64.0, &pv("h7")).unwrap();
+    assert_eq!(withpass.len(), 2);
+    assert_eq!(nopass.len(), 2);
+    assert!(withpass[1].0 == nopass[1].0);
+}
+
+#[test]
+fn test_from_config() {
+    let tmp = std::env::temp_dir();
+    let config_path = tmp.join(
+        format!("test_engine_config_{}.txt", std::process::id()));
+    for (txt, ok) in [
+            ("engine: builtin\n", true),
+            ("engine:EDAX\npath:./edax\n", true),
+            ("curdir:/tmp\n", true),
+            ("engine: gnugo\n", false),
+            ("engine: edax\ncache: /tmp\n", false),
+            ("curdir:/tmp\ndepht: 3\n", false)] {
+        std::fs::write(&config_path, txt).unwrap();
+        assert_eq!(from_config(&config_path, None, false, false).is_ok(), ok, "{txt}");
+    }
+    // プロファイルでエンジンを変えられる
+    std::fs::write(&config_path, "engine: gnugo\n[solver]\nengine: builtin\n").unwrap();
+    assert!(from_config(&config_path, None, false, false).is_err());
+    assert!(from_config(&config_path, Some("solver"), false, false).unwrap().is_exact());
+    assert!(from_config(&config_path, Some("fast"), false, false).is_err());
+    std::fs::remove_file(&config_path).unwrap();
+
+    assert!(from_config(&std::path::PathBuf::from(""), None, true, false).is_ok());
+    assert!(from_config(&std::path::PathBuf::from(""), Some("fast"), false, false).is_err());
+    assert!(from_config(&std::path::PathBuf::from("/tmp/no_such_engine_config.txt"),
+        None, true, false).is_ok());
+    assert!(from_config(&std::path::PathBuf::from("/tmp/no_such_engine_config.txt"),
+        None, false, false).is_err());
+}
@@ -0,0 +1,355 @@
+use std::io::{BufRead, BufReader};
+use std::time::Duration;
+
+/// エンジンの設定ファイルの中身。
+///
+/// ex.
+/// ```text
+/// # コメント
+/// engine: ruversi
+/// curdir: ~/ruversi/
+/// path: ./target/release/ruversi
+/// evfile: "$HOME/data/eval table.txt"
+/// args: --silent,"--name=a,b"
+///
+/// [fast]
+/// depth: 5
+/// timeout: 10
+///
+/// [deep]
+/// depth: 12
+/// evfile: ./data/deep.txt
+/// ```
+///
+/// `[name]`より後ろはプロファイルで、選ばれた時だけ前の設定を上書きする。
+/// 書いていない項目はNone。
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EngineConfig {
+    pub engine : Option<String>,
+    pub curdir : Option<String>,
+    pub path : Option<String>,
+    pub evfile : Option<String>,
+    pub args : Option<Vec<String>>,
+    pub cache : Option<String>,
+    pub depth : Option<u32>,
+    pub timeout : Option<Duration>,
+}
+
+/// "#"から後ろを取り除く。引用符の中と単語の途中の"#"はそのまま。
+fn strip_comment(line : &str) -> &str {
+    let mut quote = None;
+    let mut prev = ' ';
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) => {
+                if c == q {quote = None;}
+            },
+            None => {
+                if c == '"' || c == '\'' {
+                    quote = Some(c);
+                } else if c == '#' && prev.is_whitespace() {
+                    return &line[..i];
+                }
+            },
+        }
+        prev = c;
+    }
+    line
+}
+
+/// `$`の後ろの変数名を読んで環境変数の値を返す。
+/// 変数名が無ければ`$`のまま。
+fn expand_var(chars : &mut std::iter::Peekable<std::str::Chars>, txt : &str)
+        -> Result<String, String> {
+    let mut name = String::new();
+    if chars.peek() == Some(&'{') {
+        chars.next();
+        loop {
+            match chars.next() {
+                Some('}') => {break;},
+                Some(c) => {name.push(c);},
+                None => {return Err(format!("missing '}}' in \"{txt}\""));},
+            }
+        }
+    } else {
+        while let Some(&c) = chars.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_') {break;}
+            name.push(c);
+            chars.next();
+        }
+        if name.is_empty() {return Ok(String::from("$"));}
+    }
+    std::env::var(&name).map_err(|_| format!("undefined variable \"{name}\""))
+}
+
+/// `$VAR`と`${VAR}`を環境変数で置き換える。
+fn expand_vars(txt : &str) -> Result<String, String> {
+    let mut ret = String::new();
+    let mut chars = txt.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '$' {
+            ret += &expand_var(&mut chars, txt)?;
+        } else {
+            ret.push(c);
+        }
+    }
+    Ok(ret)
+}
+
+/// 先頭の`~`をホームディレクトリにする。
+fn expand_home(txt : &str) -> Result<String, String> {
+    if txt != "~" && !txt.starts_with("~/") {return Ok(txt.to_string());}
+
+    let home = std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE"))
+        .map_err(|_| format!("can not expand \"~\" in \"{txt}\""))?;
+    Ok(home + &txt[1..])
+}
+
+/// 値を1つ読む。
+///
+/// - `"..."` : `\"`と`\\`が使える。`$VAR`は展開する。
+/// - `'...'` : そのまま。
+/// - それ以外 : 前後の空白を取って`~`と`$VAR`を展開する。
+pub fn parse_value(txt : &str) -> Result<String, String> {
+    let txt = txt.trim();
+    if let Some(body) = txt.strip_prefix('\'') {
+        return body.strip_suffix('\'').filter(|b| !b.contains('\''))
+            .map(|b| b.to_string())
+            .ok_or_else(|| format!("unterminated quote in {txt}"));
+    }
+    if let Some(body) = txt.strip_prefix('"') {
+        let mut ret = String::new();
+        let mut chars = body.chars().peekable();
+        loop {
+            match chars.next() {
+                Some('"') => {break;},
+                Some('\\') => {
+                    match chars.next() {
+                        Some(c @ ('"' | '\\' | '$')) => {ret.push(c);},
+                        Some(c) => {ret.push('\\'); ret.push(c);},
+                        None => {return Err(format!("unterminated quote in {txt}"));},
+                    }
+                },
+                Some('$') => {ret += &expand_var(&mut chars, txt)?;},
+                Some(c) => {ret.push(c);},
+                None => {return Err(format!("unterminated quote in {txt}"));},
+            }
+        }
+        if chars.next().is_some() {
+            return Err(format!("unexpected characters after quote in {txt}"));
+        }
+        return Ok(ret);
+    }
+    expand_vars(&expand_home(txt)?)
+}
+
+/// 引用符の外のカンマで区切る。区切った結果は引用符を含んだまま。
+pub fn split_commas(txt : &str) -> Result<Vec<String>, String> {
+    let mut ret = Vec::new();
+    let mut cur = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+    for c in txt.chars() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if q == '"' && c == '\\' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+                cur.push(c);
+            },
+            None => {
+                if c == ',' {
+                    ret.push(std::mem::take(&mut cur));
+                    continue;
+                }
+                if c == '"' || c == '\'' {quote = Some(c);}
+                cur.push(c);
+            },
+        }
+    }
+    if quote.is_some() {
+        return Err(format!("unterminated quote in \"{txt}\""));
+    }
+    ret.push(cur);
+    Ok(ret)
+}
+
+impl EngineConfig {
+    /// 設定ファイルを読む。
+    ///
+    /// # Arguments
+    /// - path 設定ファイル
+    /// - profile 使うプロファイル。Noneなら`[name]`より前の設定だけ使う。
+    pub fn load(path : &std::path::Path, profile : Option<&str>)
+            -> Result<EngineConfig, String> {
+        let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        let mut base = EngineConfig::default();
+        let mut profiles : Vec<(String, EngineConfig)> = Vec::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            let lineno = i + 1;
+            let at = |msg : String| format!("{}:{lineno}: {msg}", path.display());
+
+            let l = strip_comment(&line).trim();
+            if l.is_empty() {continue;}
+
+            if let Some(name) = l.strip_prefix('[') {
+                let name = name.strip_suffix(']').map(|n| n.trim())
+                    .filter(|n| !n.is_empty())
+                    .ok_or_else(|| at(format!("invalid profile \"{l}\"")))?;
+                if profiles.iter().any(|(n, _)| n == name) {
+                    return Err(at(format!("profile \"{name}\" appears twice")));
+                }
+                profiles.push((name.to_string(), EngineConfig::default()));
+                continue;
+            }
+
+            let (key, value) = l.split_once(':')
+                .ok_or_else(|| at(format!("expected \"key: value\" but \"{l}\"")))?;
+            let cfg = match profiles.last_mut() {
+                Some((_, cfg)) => cfg,
+                None => &mut base,
+            };
+            cfg.set(key.trim(), value, at)?;
+        }
+
+        if let Some(name) = profile {
+            let Some((_, cfg)) = profiles.iter().find(|(n, _)| n == name) else {
+                return Err(format!("profile \"{name}\" is not in {}", path.display()));
+            };
+            base.merge(cfg);
+        }
+        Ok(base)
+    }
+
+    /// 1項目を設定する。エラーには`at`でファイル名と行番号を付ける。
+    fn set(&mut self, key : &str, value : &str, at : impl Fn(String) -> String)
+            -> Result<(), String> {
+        match key {
+            "engine" => {
+                self.engine = Some(parse_value(value).map_err(&at)?.to_ascii_lowercase());
+            },
+            "curdir" => {self.curdir = Some(parse_value(value).map_err(&at)?);},
+            "path" => {self.path = Some(parse_value(value).map_err(&at)?);},
+            "evfile" => {self.evfile = Some(parse_value(value).map_err(&at)?);},
+            "cache" => {self.cache = Some(parse_value(value).map_err(&at)?);},
+            "args" => {
+                self.args = Some(crate::ruversirunner::parse_args_tag(value).map_err(&at)?);
+            },
+            "depth" => {
+                let v = parse_value(value).map_err(&at)?;
+                self.depth = Some(v.parse::<u32>()
+                    .map_err(|e| at(format!("invalid depth \"{v}\" : {e}")))?);
+            },
+            "timeout" => {
+                let v = parse_value(value).map_err(&at)?;
+                let sec = v.parse::<f64>().ok().filter(|s| s.is_finite() && *s > 0.0)
+                    .ok_or_else(|| at(format!("invalid timeout \"{v}\"")))?;
+                self.timeout = Some(Duration::from_secs_f64(sec));
+            },
+            _ => {return Err(at(format!("unknown key \"{key}\"")));},
+        }
+        Ok(())
+    }
+
+    /// otherに書いてある項目で上書きする。
+    fn merge(&mut self, other : &EngineConfig) {
+        fn over<T : Clone>(a : &mut Option<T>, b : &Option<T>) {
+            if b.is_some() {*a = b.clone();}
+        }
+        over(&mut self.engine, &other.engine);
+        over(&mut self.curdir, &other.curdir);
+        over(&mut self.path, &other.path);
+        over(&mut self.evfile, &other.evfile);
+        over(&mut self.args, &other.args);
+        over(&mut self.cache, &other.cache);
+        over(&mut self.depth, &other.depth);
+        over(&mut self.timeout, &other.timeout);
+    }
+}
+
+#[test]
+fn test_parse_value() {
+    std::env::set_var("INCUVERSI_TEST_VAR", "abc");
+    let home = std::env::var("HOME").unwrap();
+    assert_eq!(parse_value("  plain  ").unwrap(), "plain");
+    assert_eq!(parse_value("~/ruversi/").unwrap(), format!("{home}/ruversi/"));
+    assert_eq!(parse_value("a~b").unwrap(), "a~b");
+    assert_eq!(parse_value("$INCUVERSI_TEST_VAR/x").unwrap(), "abc/x");
+    assert_eq!(parse_value("${INCUVERSI_TEST_VAR}x").unwrap(), "abcx");
+    assert_eq!(parse_value("a$").unwrap(), "a$");
+    assert_eq!(parse_value("\"a b, $INCUVERSI_TEST_VAR\"").unwrap(), "a b, abc");
+    assert_eq!(parse_value("\"\\\"\\\\ \\$X ~\"").unwrap(), "\"\\ $X ~");
+    assert_eq!(parse_value("'$INCUVERSI_TEST_VAR ~'").unwrap(), "$INCUVERSI_TEST_VAR ~");
+    assert_eq!(parse_value("\"\"").unwrap(), "");
+    assert!(parse_value("$INCUVERSI_NO_SUCH_VAR").is_err());
+    assert!(parse_value("${INCUVERSI_TEST_VAR").is_err());
+    assert!(parse_value("\"abc").is_err());
+    assert!(parse_value("'abc").is_err());
+    assert!(parse_value("\"a\"b").is_err());
+
+    assert_eq!(split_commas("a, \"b,c\" ,'d,\"e'").unwrap(),
+        vec!["a", " \"b,c\" ", "'d,\"e'"]);
+    assert_eq!(split_commas("\"a\\\",b\"").unwrap(), vec!["\"a\\\",b\""]);
+    assert!(split_commas("\"a,b").is_err());
+    assert_eq!(strip_comment("a # b"), "a ");
+    assert_eq!(strip_comment("# b"), "");
+    assert_eq!(strip_comment("a#b \"c #d\""), "a#b \"c #d\"");
+}
+
+#[test]
+fn test_engineconfig_load() {
+    let path = std::env::temp_dir().join(
+        format!("test_engineconfig_{}.txt", std::process::id()));
+    std::fs::write(&path, "\
+# engine config
+engine: Ruversi
+curdir: /tmp/ruversi   # comment
+args: --silent,\"--name=a,b\"
+
+[fast]
+depth: 5
+timeout: 1.5
+
+[deep]
+depth: 12
+evfile: ./data/deep.txt
+").unwrap();
+    let cfg = EngineConfig::load(&path, None).unwrap();
+    assert_eq!(cfg.engine.as_deref(), Some("ruversi"));
+    assert_eq!(cfg.curdir.as_deref(), Some("/tmp/ruversi"));
+    assert_eq!(cfg.args, Some(vec![String::from("--silent"), String::from("--name=a,b")]));
+    assert_eq!(cfg.depth, None);
+    let fast = EngineConfig::load(&path, Some("fast")).unwrap();
+    assert_eq!(fast.depth, Some(5));
+    assert_eq!(fast.timeout, Some(Duration::from_millis(1500)));
+    assert_eq!(fast.evfile, None);
+    assert_eq!(fast.curdir.as_deref(), Some("/tmp/ruversi"));
+    let deep = EngineConfig::load(&path, Some("deep")).unwrap();
+    assert_eq!(deep.depth, Some(12));
+    assert_eq!(deep.evfile.as_deref(), Some("./data/deep.txt"));
+    assert_eq!(deep.timeout, None);
+    assert!(EngineConfig::load(&path, Some("medium")).is_err());
+
+    for (txt, err) in [
+            ("curdir:/tmp\nfoo: bar\n", ":2: unknown key \"foo\""),
+            ("depth: deep\n", ":1: invalid depth \"deep\""),
+            ("timeout: -1\n", ":1: invalid timeout \"-1\""),
+            ("\n\ncurdir\n", ":3: expected \"key: value\""),
+            ("[]\n", ":1: invalid profile"),
+            ("[a]\n[a]\n", ":2: profile \"a\" appears twice"),
+            ("evfile: \"abc\n", ":1: unterminated quote"),
+            ("depth: 3\nargs: a,\"b\n", ":2: unterminated quote"),
+            ("args:a,,b\n", ":1: \"a,,b\" contains empty part"),
+            ("path: $INCUVERSI_NO_SUCH_VAR\n", ":1: undefined variable")] {
+        std::fs::write(&path, txt).unwrap();
+        let msg = EngineConfig::load(&path, None).unwrap_err();
+        assert!(msg.starts_with(&path.display().to_string()), "{msg}");
+        assert!(msg.contains(err), "{msg} != {err}");
+    }
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,102 @@
+//! 自己対局の棋譜に無い局面を作る。
+//!
+//! 初期局面や棋譜の局面から乱数で手を選んで、残りのマスが指定の数になるまで進める。
+//! epsilon-greedyの時はepsilonの確率で乱数、それ以外はエンジンの最善手を選ぶ。
+
+use crate::bitboard::{self, BitBoard};
+#[cfg(test)]
+use crate::bitboard::cell;
+use crate::engine;
+use rand::{Rng, SeedableRng};
+
+/// 1局分進める。
+///
+/// # Arguments
+/// - starts 始めの局面の候補。乱数で1つ選ぶ。
+/// - empties この数まで残りのマスを減らす。
+/// - seed 乱数の種。同じ種なら同じ局面になる。
+/// - epsilon 乱数で手を選ぶ確率。1.0なら`greedy`は呼ばない。
+/// - greedy 乱数で選ばない時の手。Noneなら乱数で選ぶ。
+///
+/// # Returns
+/// 残りemptiesマスの局面。その前に終局したらNone。
+pub fn playout(starts : &[BitBoard], empties : u32, seed : u64, epsilon : f64,
+        greedy : impl Fn(&BitBoard) -> Option<u8>) -> Option<BitBoard> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    let mut ban = starts[rng.gen_range(0..starts.len())].clone();
+    while ban.nblank() > empties {
+        let moves = ban.genmove()?;
+        let mv = if moves[0] == bitboard::PASS {
+            // 両者パスなら終局
+            if ban.r#move(bitboard::PASS).unwrap().mobility() == 0 {return None;}
+
+            bitboard::PASS
+        } else if epsilon < 1.0 && !rng.gen_bool(epsilon.max(0.0)) {
+            greedy(&ban).unwrap_or_else(|| moves[rng.gen_range(0..moves.len())])
+        } else {
+            moves[rng.gen_range(0..moves.len())]
+        };
+        ban = ban.r#move(mv).unwrap();
+    }
+    Some(ban)
+}
+
+/// エンジンの最善手"d3"や"ps"をマスの番号にする。
+///
+/// # Returns
+/// 最善手。読めない手や打てない手の時はNone。
+pub fn parse_move(ban : &BitBoard, txt : &str) -> Option<u8> {
+    let mv = engine::move_from_str(txt).ok()?;
+    ban.genmove()?.contains(&mv).then_some(mv)
+}
+
+#[test]
+fn test_playout() {
+    let ban = BitBoard::new();
+    for seed in 0..10 {
+        let b = playout(std::slice::from_ref(&ban), 50, seed, 1.0, |_| panic!("greedy")).unwrap();
+        assert!(b.is_last_n(50));
+        // 同じ種なら同じ局面
+        assert!(playout(std::slice::from_ref(&ban), 50, seed, 1.0, |_| None).unwrap() == b);
+    }
+    assert!((0..10).any(|seed| {
+        playout(std::slice::from_ref(&ban), 50, seed, 1.0, |_| None) != playout(std::slice::from_ref(&ban), 50, 0, 1.0, |_| None)
+    }));
+
+    // epsilon 0なら全部greedyの手
+    let b = playout(std::slice::from_ref(&ban), 56, 0, 0.0, |b| b.genmove().map(|m| m[0])).unwrap();
+    let mut expected = ban.clone();
+    for _ in 0..4 {
+        expected = expected.r#move(expected.genmove().unwrap()[0]).unwrap();
+    }
+    assert!(b == expected);
+
+    // 途中で終局する
+    let ban = BitBoard::try_from("H/H/H/H/H/H/H/G1 b").unwrap();
+    assert!(playout(std::slice::from_ref(&ban), 0, 0, 1.0, |_| None).is_none());
+    // 始めの局面は種で選ぶ
+    let starts = [BitBoard::new(), BitBoard::new().r#move(cell(6, 4)).unwrap()];
+    let picked = (0..20).map(|seed| {
+        playout(&starts, 59, seed, 1.0, |_| None).unwrap() == starts[1]
+    }).collect::<Vec<_>>();
+    assert!(picked.contains(&true) && picked.contains(&false));
+    // もう残りが少ない
+    let ban = BitBoard::try_from("H/H/H/H/H/H/Fa1/F2 w").unwrap();
+    assert!(playout(std::slice::from_ref(&ban), 3, 0, 1.0, |_| None).unwrap() == ban);
+}
+
+#[test]
+fn test_parse_move() {
+    let ban = BitBoard::new();
+    assert_eq!(parse_move(&ban, "e3"), Some(cell(5, 3)));
+    assert_eq!(parse_move(&ban, "C5"), Some(cell(3, 5)));
+    // 打てない手や読めない手
+    assert_eq!(parse_move(&ban, "d3"), None);
+    assert_eq!(parse_move(&ban, "ps"), None);
+    assert_eq!(parse_move(&ban, ""), None);
+    assert_eq!(parse_move(&ban, "z9"), None);
+    // パスしかない時はパス
+    let ban = BitBoard::try_from("H/H/H/H/H/H/Fa1/F2 w").unwrap();
+    assert_eq!(parse_move(&ban, "PS"), Some(bitboard::PASS));
+    assert_eq!(parse_move(&ban, "h7"), None);
+}
@@ -3,19 +3,57 @@ use chrono::Utc;
 use indicatif::{ProgressBar, ProgressStyle, MultiProgress};
 use std::io::{BufRead, BufReader};
 use std::fs::OpenOptions;
+use std::collections::{HashMap, HashSet};
 use std::sync::mpsc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::path::PathBuf;
+use std::time::Duration;
 
 pub struct Incubator {
+    jobs : usize,
     kifudir : Vec<String>,
     log : std::fs::File,
+    engine_profile : Option<String>,
     mate : u32,
     // matefiles : String,
     mode : argument::Mode,
     multibar : MultiProgress,
+    format : argument::Format,
     outdir : String,
+    pv_samples : bool,
+    /// midgameで取り出す局面の残りのマスの範囲
+    min_empties : Option<u32>,
+    max_empties : Option<u32>,
+    /// midgameで取り出す局面の進行度
+    progress : Option<usize>,
+    /// midgameで終局までの1手毎に結果に掛ける値
+    discount : Option<f32>,
+    /// generateで進める局数
+    playouts : usize,
+    /// generateの乱数の種
+    seed : u64,
+    /// generateで乱数で手を選ぶ確率。Noneなら全部乱数。
+    epsilon : Option<f64>,
+    /// 親の局面毎に子供の局面の評価値を出力する。
+    policy : bool,
+    policy_temperature : Option<f32>,
+    /// spreadで展開する深さ
+    spread_depth : usize,
+    /// 失敗した局面を書き出すファイル。最初に失敗した時に作る。
+    quarantine : Mutex<Option<std::fs::File>>,
+    nfailed : AtomicUsize,
+    retries : usize,
+    rounding : argument::Rounding,
+    /// rolloutで1局面あたり打つ数
+    rollouts : usize,
+    rollout_policy : argument::RolloutPolicy,
     ruversi_config : String,
     show_progressbar : bool,
+    timeout : Option<Duration>,
+    use_solver : bool,
+    /// 評価値も3列目に出力する。
+    value_column : bool,
     verbose : bool,
 }
 
@@ -25,6 +63,79 @@ impl std::fmt::Display for Incubator {
     }
 }
 
+/// 1局面を"rfen,score"か"rfen,score,value"の1行にする。
+fn format_sample(ban : &bitboard::BitBoard, score : i8, value : f32, value_column : bool)
+        -> String {
+    if value_column {
+        format!("{ban},{score},{value}")
+    } else {
+        format!("{ban},{score}")
+    }
+}
+
+/// spreadで見つけた局面。対称な局面は同じとみなす。
+struct Spread {
+    index : HashMap<bitboard::CanonicalKey, usize>,
+    /// (最初に見つけた向きの局面の情報, 見つけた親の数)
+    found : Vec<(bitboard::Sample, usize)>,
+    /// 展開した親
+    expanded : HashSet<bitboard::CanonicalKey>,
+}
+
+impl Spread {
+    fn new() -> Spread {
+        Spread {index : HashMap::new(), found : Vec::new(), expanded : HashSet::new()}
+    }
+
+    /// 親を展開する時に呼ぶ。
+    ///
+    /// # Returns
+    /// 初めての親ならtrue。棋譜のディレクトリや向きが違っても同じ親ならfalse。
+    fn expand(&mut self, ban : &bitboard::BitBoard) -> bool {
+        self.expanded.insert(ban.canonical().0)
+    }
+
+    fn len(&self) -> usize {
+        self.found.len()
+    }
+
+    /// 1つの親の子供の局面を足す。
+    ///
+    /// # Returns
+    /// 初めて見つけた局面
+    fn add(&mut self, children : &[bitboard::Sample]) -> Vec<bitboard::BitBoard> {
+        let mut mine = HashSet::new();
+        let mut ret = Vec::new();
+        for child in children.iter() {
+            let (key, _) = child.0.canonical();
+            // 同じ親から対称な局面が2つ出ても1回と数える
+            if !mine.insert(key) {continue;}
+
+            match self.index.get(&key) {
+                Some(&i) => {self.found[i].1 += 1;},
+                None => {
+                    self.index.insert(key, self.found.len());
+                    self.found.push((child.clone(), 1));
+                    ret.push(child.0.clone());
+                },
+            }
+        }
+        ret
+    }
+
+    /// "rfen,score"か"rfen,score,value"の行
+    fn lines(&self, value_column : bool) -> Vec<String> {
+        self.found.iter().map(|((ban, _, _, score, value), _)| {
+            format_sample(ban, *score, *value, value_column)
+        }).collect()
+    }
+
+    /// "rfen,count"の行。countは見つけた親の数。
+    fn counts(&self) -> Vec<String> {
+        self.found.iter().map(|((ban, _, _, _, _), count)| format!("{ban},{count}")).collect()
+    }
+}
+
 fn format_log_path(txt : &Option<String>) -> String {
     let strdt = Utc::now().format("%Y%m%d%H%M%S").to_string();
     if let Some(path) = txt {
@@ -55,15 +166,38 @@ impl From<argument::Arg> for Incubator {
         let verbose = arg.verbose;
 
         Self {
+            jobs : arg.jobs,
             kifudir,
             log,
+            engine_profile : arg.engine_profile,
             mate,
             // matefiles,
             mode,
             multibar : MultiProgress::new(),
+            format : arg.format,
             outdir,
+            pv_samples : arg.pv_samples,
+            min_empties : arg.min_empties,
+            max_empties : arg.max_empties,
+            progress : arg.progress,
+            discount : arg.discount,
+            playouts : arg.playouts,
+            seed : arg.seed,
+            epsilon : arg.epsilon,
+            policy : arg.policy,
+            policy_temperature : arg.policy_temperature,
+            spread_depth : arg.spread_depth,
+            quarantine : Mutex::new(None),
+            nfailed : AtomicUsize::new(0),
+            retries : arg.retries,
+            rounding : arg.rounding,
+            rollouts : arg.rollouts,
+            rollout_policy : arg.rollout_policy,
             ruversi_config,
             show_progressbar : !arg.no_progressbar,
+            timeout : arg.timeout.map(Duration::from_secs_f64),
+            use_solver : arg.solver,
+            value_column : arg.value_column,
             verbose,
         }
     }
@@ -75,7 +209,7 @@ impl Incubator {
             panic!("self.mate < 3 || 60 <= self.mate");
         }
 
-        let dest_file = format!("mate{}.txt", self.mate - 1);
+        let dest_file = format!("mate{}.{}", self.mate - 1, self.format.extension());
         if std::path::Path::new(&dest_file).exists() {
             panic!("{dest_file} exists!");
         }
@@ -111,8 +245,7 @@ impl Incubator {
                 None
             };
             let mut boards = 
-                    data_loader::loadkifu_for_mate(
-                        &data_loader::findfiles(&format!("./{d}")),
+                    data_loader::loadkifu_dir_for_mate(
                         d, self.mate, &mut self.log, show_path);
             if let Some(pb) = &pbchild {pb.inc(1);}  // 1
 
@@ -131,36 +264,51 @@ impl Incubator {
             } else {
                 None
             };
-            let mut rr = ruversirunner::RuversiRunner::from_config(
-                &std::path::PathBuf::from(
-                    self.ruversi_config.clone())).unwrap();
-            rr.set_verbose(self.verbose);
-            let mut mates = boards.iter().flat_map(|(ban, _, _, _)| {
+            let engine = self.engine();
+            // 対称な局面は1回だけ展開して元の向きに戻す
+            let groups = data_loader::group_symmetric(
+                    &boards, &mut self.log, show_path);
+            let pool = ruversirunner::WorkerPool::new(self.jobs).unwrap();
+            let bans = groups.iter().map(|(ban, _)| {
                 if !ban.is_last_n(self.mate) {panic!("!ban.is_last_n({})", self.mate);}
-                match rr.run_children(&ban.to_string()) {
-                    Err(msg) => {panic!("{msg}")},
-                    Ok(ban) => {
-                        if let Some(pb) = &pbgrandchild {pb.inc(1);}
-                        ban
-                    },
-                }
+                ban.clone()
             }).collect::<Vec<_>>();
+            let sources = groups.iter().map(|(ban, transforms)| {
+                transforms.iter().map(|t| t.apply(ban)).collect::<Vec<_>>()
+            }).collect::<Vec<_>>();
+            let results = self.expand_children(&pool, engine.as_ref(), &bans, &sources,
+                self.pv_samples, |i| {
+                    if let Some(pb) = &pbgrandchild {
+                        pb.inc(groups[i].1.len() as u64);
+                    }
+                });
+            let mut mates = Vec::new();
+            let mut pvs = Vec::new();
+            let mut policies = Vec::new();
+            for ((ban, transforms), children) in groups.iter().zip(results) {
+                let Some((children, samples)) = children else {continue;};
+                if self.policy {
+                    policies.extend(self.format_policies(ban, &children, transforms));
+                }
+                mates.extend(data_loader::restore_symmetric(&children, transforms));
+                pvs.extend(data_loader::restore_symmetric(&samples, transforms));
+            }
             if let Some(pb) = &pbchild {pb.inc(1);}  // 3
             if let Some(pb) = &pbgrandchild {
                 pb.finish();
                 self.multibar.remove(pb);
             }
-            if mates.is_empty() {panic!("mates: {}", mates.len());}
+            if mates.is_empty() && !self.has_failed() {panic!("mates: {}", mates.len());}
 
             data_loader::dedupboards(&mut mates, &mut self.log, show_path);
             if let Some(pb) = &pbchild {pb.inc(1);}  // 4
-            if mates.is_empty() {panic!("mates: {}", mates.len());}
+            if mates.is_empty() && !self.has_failed() {panic!("mates: {}", mates.len());}
 
             // augmentation
             const AUGMENTATION_KIFU : bool = false;
             let mates = if AUGMENTATION_KIFU {
-                let mut newmates = mates.iter().flat_map(|(ban, fsb, fsw, score)| {
-                    ban.rotated_mirrored_fixed(*fsb, *fsw, *score)
+                let mut newmates = mates.iter().flat_map(|(ban, fsb, fsw, score, value)| {
+                    ban.rotated_mirrored_fixed(*fsb, *fsw, *score, *value)
                 }).collect::<Vec<_>>();
                 if let Some(pb) = &pbchild {pb.inc(1);}  // 5
 
@@ -174,20 +322,29 @@ impl Incubator {
 
             // write to a file.
             let n1 = self.mate - 1;
-            let text = format!("# {d}\n")
-                + &mates.iter().filter_map(|(ban, _, _, score)| {
-                if ban.is_last_n(n1) {
-                    Some(format!("{ban},{score}\n"))
-                } else {
-                    // PASSだとこっちに来る。
-                    // panic!("{ban} != {n1}");
-                    None
+            if self.format == argument::Format::Binary {
+                self.store_binary(&dest_file, &mates, n1, engine.is_exact())?;
+            } else {
+                let text = format!("# {d}\n")
+                    + &mates.iter().filter_map(|(ban, _, _, score, value)| {
+                    if ban.is_last_n(n1) {
+                        Some(format_sample(ban, *score, *value, self.value_column) + "\n")
+                    } else {
+                        // PASSだとこっちに来る。
+                        // panic!("{ban} != {n1}");
+                        None
+                    }
+                }).collect::<Vec<String>>().join("");
+                {
+                    let mut f = OpenOptions::new()
+                        .create(true).append(true).open(&dest_file).unwrap();
+                    f.write_all(text.as_bytes()).unwrap();
                 }
-            }).collect::<Vec<String>>().join("");
-            {
-                let mut f = OpenOptions::new()
-                    .create(true).append(true).open(&dest_file).unwrap();
-                f.write_all(text.as_bytes()).unwrap();
+            }
+            if self.policy {self.store_policies(&policies);}
+            if self.pv_samples {
+                Incubator::store_pv_samples(&mut pvs, &mut self.log, &self.outdir,
+                    self.format, self.value_column, show_path);
             }
 
             if let Some(pb) = &pbchild {
@@ -229,8 +386,7 @@ impl Incubator {
         let show_path = false;
         let mut boards = self.kifudir.iter().flat_map(
             |d| {
-                let ret = data_loader::loadkifu_for_mate(
-                    &data_loader::findfiles(&format!("./{d}")),
+                let ret = data_loader::loadkifu_dir_for_mate(
                     d, 3, &mut self.log, show_path);
                 if let Some(pb) = &pbchild {pb.inc(1);}
                 ret
@@ -243,7 +399,7 @@ impl Incubator {
         // write to a file.
         let dest = "mate2.txt";
         let mut f = std::fs::File::create(dest).unwrap();
-        for (ban, _, _, score) in boards {
+        for (ban, _, _, score, _) in boards {
             if !ban.is_last_n(2) {
                 continue;
             }
@@ -264,7 +420,7 @@ impl Incubator {
             panic!("self.mate < 3 || 60 <= self.mate");
         }
 
-        let dest_file = format!("mate{}.txt", self.mate - 1);
+        let dest_file = format!("mate{}.{}", self.mate - 1, self.format.extension());
         // if std::path::Path::new(&dest_file).exists() {
         //     panic!("{dest_file} exists!");
         // }
@@ -299,7 +455,7 @@ impl Incubator {
             } else {
                 None
             };
-            let files = data_loader::findfiles(&format!("./{d}")).iter().map(
+            let files = data_loader::find_mate_files(&format!("./{d}")).iter().map(
                     |fname| format!("{d}/{fname}")).collect::<Vec<String>>();
             let mut boards = files.iter().flat_map(|path| {
                     data_loader::load_mates(path, self.mate).unwrap()
@@ -321,27 +477,41 @@ impl Incubator {
             } else {
                 None
             };
-            let mut rr = ruversirunner::RuversiRunner::from_config(
-                &std::path::PathBuf::from(
-                    self.ruversi_config.clone())).unwrap();
-            rr.set_verbose(self.verbose);
-            let mut mates = boards.iter().flat_map(|(ban, _, _, _)| {
+            let engine = self.engine();
+            // 対称な局面は1回だけ展開して元の向きに戻す
+            let groups = data_loader::group_symmetric(
+                    &boards, &mut self.log, show_path);
+            let pool = ruversirunner::WorkerPool::new(self.jobs).unwrap();
+            let bans = groups.iter().map(|(ban, _)| {
                 if !ban.is_last_n(self.mate) {panic!("!ban.is_last_n({})", self.mate);}
-                // rr.set_verbose(true);
-                match rr.run_children(&ban.to_string()) {
-                    Err(msg) => {panic!("{msg}")},
-                    Ok(ban) => {
-                        if let Some(pb) = &pbgrandchild {pb.inc(1);}
-                        ban
-                    },
-                }
+                ban.clone()
+            }).collect::<Vec<_>>();
+            let sources = groups.iter().map(|(ban, transforms)| {
+                transforms.iter().map(|t| t.apply(ban)).collect::<Vec<_>>()
             }).collect::<Vec<_>>();
+            let results = self.expand_children(&pool, engine.as_ref(), &bans, &sources,
+                self.pv_samples, |i| {
+                    if let Some(pb) = &pbgrandchild {
+                        pb.inc(groups[i].1.len() as u64);
+                    }
+                });
+            let mut mates = Vec::new();
+            let mut pvs = Vec::new();
+            let mut policies = Vec::new();
+            for ((ban, transforms), children) in groups.iter().zip(results) {
+                let Some((children, samples)) = children else {continue;};
+                if self.policy {
+                    policies.extend(self.format_policies(ban, &children, transforms));
+                }
+                mates.extend(data_loader::restore_symmetric(&children, transforms));
+                pvs.extend(data_loader::restore_symmetric(&samples, transforms));
+            }
             if let Some(pb) = &pbchild {pb.inc(1);}  // 3
             if let Some(pb) = &pbgrandchild {
                 pb.finish();
                 self.multibar.remove(pb);
             }
-            if mates.is_empty() {panic!("mates: {}", mates.len());}
+            if mates.is_empty() && !self.has_failed() {panic!("mates: {}", mates.len());}
 
             data_loader::dedupboards(&mut mates, &mut self.log, show_path);
             if let Some(pb) = &pbchild {pb.inc(1);}  // 4
@@ -349,8 +519,8 @@ impl Incubator {
             // augmentation
             const AUGMENTATION_MATE : bool = false;
             let mates = if AUGMENTATION_MATE {
-                let mut newmates = mates.iter().flat_map(|(ban, fsb, fsw, score)| {
-                    ban.rotated_mirrored_fixed(*fsb, *fsw, *score)
+                let mut newmates = mates.iter().flat_map(|(ban, fsb, fsw, score, value)| {
+                    ban.rotated_mirrored_fixed(*fsb, *fsw, *score, *value)
                 }).collect::<Vec<_>>();
                 if let Some(pb) = &pbchild {pb.inc(1);}  // 5
                 data_loader::dedupboards(&mut newmates, &mut self.log, show_path);
@@ -360,24 +530,33 @@ impl Incubator {
                 mates
             };
             if let Some(pb) = &pbchild {pb.inc(1);}  // 6
-            if mates.is_empty() {panic!("mates: {}", mates.len());}
+            if mates.is_empty() && !self.has_failed() {panic!("mates: {}", mates.len());}
 
             // write to a file.
             let n1 = self.mate - 1;
-            let text = files.join("\n# ") + "\n"
-                + &mates.iter().filter_map(|(ban, _, _, score)| {
-                if ban.is_last_n(n1) {
-                    Some(format!("{ban},{score}\n"))
-                } else {
-                    // PASSだとこっちに来る。
-                    // panic!("{ban} != {n1}");
-                    None
+            if self.format == argument::Format::Binary {
+                self.store_binary(&dest_file, &mates, n1, engine.is_exact())?;
+            } else {
+                let text = format!("# {}\n", files.join("\n# "))
+                    + &mates.iter().filter_map(|(ban, _, _, score, value)| {
+                    if ban.is_last_n(n1) {
+                        Some(format_sample(ban, *score, *value, self.value_column) + "\n")
+                    } else {
+                        // PASSだとこっちに来る。
+                        // panic!("{ban} != {n1}");
+                        None
+                    }
+                }).collect::<Vec<String>>().join("");
+                {
+                    let mut f = OpenOptions::new()
+                        .create(true).append(true).open(&dest_file).unwrap();
+                    f.write_all(text.as_bytes()).unwrap();
                 }
-            }).collect::<Vec<String>>().join("");
-            {
-                let mut f = OpenOptions::new()
-                    .create(true).append(true).open(&dest_file).unwrap();
-                f.write_all(text.as_bytes()).unwrap();
+            }
+            if self.policy {self.store_policies(&policies);}
+            if self.pv_samples {
+                Incubator::store_pv_samples(&mut pvs, &mut self.log, &self.outdir,
+                    self.format, self.value_column, show_path);
             }
 
             if let Some(pb) = &pbchild {
@@ -394,7 +573,197 @@ impl Incubator {
         Ok(())
     }
 
+    /// 残りn1マスの局面をバイナリ形式でファイルに追記する。
+    fn store_binary(&self, dest_file : &str,
+            mates : &[bitboard::Sample], n1 : u32, exact : bool)
+            -> Result<(), std::io::Error> {
+        let mates = mates.iter().filter(|(ban, _, _, _, _)| {
+            // PASSだと残りのマスが合わない。
+            ban.is_last_n(n1)
+        }).cloned().collect::<Vec<_>>();
+        let mut flags = if exact {data_loader::FLAG_EXACT} else {0};
+        if self.value_column {flags |= data_loader::FLAG_VALUE;}
+        data_loader::append_binary(std::path::Path::new(dest_file), &mates, flags)
+    }
+
+    /// 子供の局面の評価値を元の向きの親の局面毎に方策の行にする。
+    /// 子供の局面がおかしい時はログに書いて飛ばす。
+    fn format_policies(&self, ban : &bitboard::BitBoard, children : &[bitboard::Sample],
+            transforms : &[bitboard::Transform]) -> Vec<String> {
+        transforms.iter().filter_map(|t| {
+            let parent = t.apply(ban);
+            let children = data_loader::restore_symmetric(children, std::slice::from_ref(t));
+            match policy::format(&parent, &children, self.policy_temperature) {
+                Ok(line) => Some(line),
+                Err(msg) => {
+                    let _ = (&self.log).write_all(format!("policy: {msg}\n").as_bytes());
+                    None
+                },
+            }
+        }).collect()
+    }
+
+    /// 方策の行を`{outdir}/policy{n}.csv`に追記する。
+    /// "rfen,score"ではないので局面のファイルとして読まれない名前にする。
+    fn store_policies(&self, policies : &[String]) {
+        if policies.is_empty() {return;}
+
+        let dir = PathBuf::from(&self.outdir);
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            panic!("failed to create dir \"{dir:?}\" : {e}");
+        }
+        let path = dir.join(format!("policy{}.csv", self.mate));
+        let mut f = OpenOptions::new().create(true).append(true).open(&path).unwrap();
+        f.write_all((policies.join("\n") + "\n").as_bytes()).unwrap();
+    }
+
+    /// 読み筋の局面を残りのマス毎に`{outdir}/mate{n}_pv.{ext}`に書き出す。
+    fn store_pv_samples(samples : &mut Vec<bitboard::Sample>,
+            log : &mut std::fs::File, outdir : &str, format : argument::Format,
+            value_column : bool, show : bool) {
+        data_loader::dedupboards(samples, log, show);
+        let (tx, rx) = mpsc::channel::<String>();
+        let data = samples.iter().map(|(ban, _, _, score, value)| {
+            format_sample(ban, *score, *value, value_column)
+        }).collect::<Vec<_>>().join("\n");
+        if !data.is_empty() {tx.send(data).unwrap();}
+        tx.send(String::new()).unwrap();  // send quit
+        Incubator::store_rfen_thread(rx, &PathBuf::from(outdir), "pv", format);
+    }
+
+    /// 設定ファイルで選ばれたエンジンを用意する。
+    fn engine(&self) -> Box<dyn engine::Engine> {
+        match engine::from_config(&std::path::PathBuf::from(&self.ruversi_config),
+                self.engine_profile.as_deref(), self.use_solver, self.verbose) {
+            Ok(mut engine) => {
+                // 設定ファイルのtimeoutより--timeoutを優先する
+                if self.timeout.is_some() {engine.set_timeout(self.timeout);}
+                engine.set_rounding(self.rounding);
+                engine
+            },
+            Err(msg) => {panic!("{msg}");},
+        }
+    }
+
+    fn quarantine_path(&self) -> PathBuf {
+        PathBuf::from(&self.outdir).join("quarantine.txt")
+    }
+
+    fn has_failed(&self) -> bool {
+        self.nfailed.load(Ordering::Relaxed) > 0
+    }
+
+    /// エンジンを呼ぶ。失敗したらretries回までやり直す。
+    /// 全部失敗したらログに書いて局面をquarantine.txtに書き出す。
+    ///
+    /// # Arguments
+    /// - ban エンジンに渡す局面
+    /// - sources 諦めた時に書き出す入力の局面。
+    ///   対称な局面をまとめて代表局面を渡した時は元の向きの局面を全部渡す。
+    /// - f エンジンを呼ぶ処理
+    ///
+    /// # Returns
+    /// 成功したらSome、諦めたらNone
+    fn call_engine<T>(&self, ban : &bitboard::BitBoard, sources : &[bitboard::BitBoard],
+            f : impl Fn() -> Result<T, String>) -> Option<T> {
+        for i in 0..=self.retries {
+            match f() {
+                Ok(ret) => {return Some(ret);},
+                Err(msg) => {
+                    // 複数のスレッドから書くので1行ずつまとめて書く
+                    let _ = (&self.log).write_all(
+                        format!("try {}/{} failed: {ban} : {msg}\n",
+                            i + 1, self.retries + 1).as_bytes());
+                },
+            }
+        }
+
+        self.nfailed.fetch_add(sources.len(), Ordering::Relaxed);
+        let mut quarantine = self.quarantine.lock().unwrap();
+        if quarantine.is_none() {
+            let _ = std::fs::create_dir_all(&self.outdir);
+            match OpenOptions::new().create(true).append(true)
+                    .open(self.quarantine_path()) {
+                Ok(f) => {*quarantine = Some(f);},
+                Err(e) => {panic!("{e} : {:?}", self.quarantine_path());},
+            }
+        }
+        let lines = sources.iter().map(|b| format!("{b}\n")).collect::<String>();
+        quarantine.as_ref().unwrap().write_all(lines.as_bytes()).unwrap();
+        None
+    }
+
+    /// 局面毎に子供の局面をエンジンに読んでもらう。
+    ///
+    /// まとめて読めるエンジンなら局面を--jobs個に分けて1まとまりずつ1つのエンジンに渡すので、
+    /// エンジンの起動は--jobs回で済む。まとめて読めなかった局面は1局面ずつ読み直す。
+    ///
+    /// # Arguments
+    /// - pool エンジンを動かすスレッド
+    /// - engine 読んでもらうエンジン
+    /// - bans 展開する局面
+    /// - sources 諦めた時に書き出す入力の局面。bansと同じ順番。
+    /// - pv trueなら読み筋の局面も返す
+    /// - done 1局面終わる毎にその番号で呼ばれる
+    ///
+    /// # Returns
+    /// bansと同じ順番に(子供の局面の情報, 読み筋の局面の情報)。諦めた局面はNone。
+    #[allow(clippy::type_complexity)]
+    fn expand_children(&self, pool : &ruversirunner::WorkerPool, engine : &dyn engine::Engine,
+            bans : &[bitboard::BitBoard], sources : &[Vec<bitboard::BitBoard>], pv : bool,
+            done : impl Fn(usize) + Sync)
+            -> Vec<Option<(engine::Children, engine::Children)>> {
+        let mut ret = vec![None; bans.len()];
+        if engine.is_batched() && !pv {
+            let batches = pool.split(bans, |offset, chunk| {
+                let mut found = Vec::new();
+                if let Err(msg) = engine.children_batch(chunk, &mut |i, children| {
+                    done(offset + i);
+                    found.push((offset + i, children));
+                }) {
+                    // 複数のスレッドから書くので1行ずつまとめて書く
+                    let _ = (&self.log).write_all(
+                        format!("batch failed after {}/{} positions: {msg}\n",
+                            found.len(), chunk.len()).as_bytes());
+                }
+                found
+            });
+            for (i, children) in batches.into_iter().flatten() {
+                ret[i] = Some((children, Vec::new()));
+            }
+        }
+
+        let rest = (0..bans.len()).filter(|&i| ret[i].is_none()).collect::<Vec<_>>();
+        let retried = pool.map(&rest, |&i| {
+            let ban = &bans[i];
+            let children = self.call_engine(ban, &sources[i], || {
+                if pv {
+                    engine.children_pv(ban)
+                } else {
+                    Ok((engine.children(ban)?, Vec::new()))
+                }
+            });
+            done(i);
+            children
+        });
+        for (i, children) in rest.into_iter().zip(retried) {
+            ret[i] = children;
+        }
+        ret
+    }
+
     pub fn run(&mut self) -> Result<(), std::io::Error> {
+        let ret = self.run_mode();
+        if self.has_failed() {
+            let msg = format!("{} position(s) failed, see {}",
+                self.nfailed.load(Ordering::Relaxed), self.quarantine_path().display());
+            self.log.write_all(format!("{msg}\n").as_bytes())?;
+            eprintln!("{msg}");
+        }
+        ret
+    }
+
+    fn run_mode(&mut self) -> Result<(), std::io::Error> {
         match self.mode {
             argument::Mode::Kifu => {
                 self.run_kifu()
@@ -414,8 +783,20 @@ impl Incubator {
             argument::Mode::Validate => {
                 self.run_validate()
             },
-            _ => {
-                Ok(())
+            argument::Mode::Lint => {
+                self.run_lint()
+            },
+            argument::Mode::Consistency => {
+                self.run_consistency()
+            },
+            argument::Mode::Generate => {
+                self.run_generate()
+            },
+            argument::Mode::Rollout => {
+                self.run_rollout()
+            },
+            argument::Mode::Midgame => {
+                self.run_midgame()
             },
         }
     }
@@ -427,9 +808,30 @@ impl Incubator {
     ///   文字列受信チャンネル。emptyデータを受信すると関数を抜けます。
     /// - outdir
     ///   ファイルの出力ディレクトリ
-    fn store_rfen_thread(rx : std::sync::mpsc::Receiver<String>, outdir : &PathBuf, suffix : &str) {
-        let mut buf = vec![String::new() ; 64];
+    /// - format
+    ///   出力形式。バイナリの時は"rfen,score"を読み直して変換する。
+    fn store_rfen_thread(rx : std::sync::mpsc::Receiver<String>, outdir : &PathBuf,
+            suffix : &str, format : argument::Format) {
+        let mut buf = vec![Vec::<u8>::new() ; 64];
         const THREASHOLD_BYTES : usize = 10 * 1024;
+        let store = |n : usize, b : &[u8]| {
+            let dir = std::path::Path::new(outdir);
+            if !dir.is_dir() {
+                if let Err(e) =
+                        std::fs::create_dir_all(outdir) {
+                    panic!("failed to create dir \"{outdir:?}\" : {e}");
+                }
+            }
+            let mut dest_file = outdir.clone();
+            dest_file.push(format!("mate{n}_{suffix}.{}", format.extension()));
+            if format == argument::Format::Binary {
+                data_loader::append_binary_records(&dest_file, b).unwrap();
+            } else {
+                let mut f = OpenOptions::new()
+                    .create(true).append(true).open(&dest_file).unwrap();
+                f.write_all(b).unwrap();
+            }
+        };
         loop {
             match rx.recv() {
                 Ok(lines) => {
@@ -437,11 +839,7 @@ impl Incubator {
                         for (n,b) in buf.iter().enumerate() {
                             if b.is_empty() {continue;}
 
-                            let mut dest_file = outdir.clone();
-                            dest_file.push(format!("mate{n}_{suffix}.txt"));
-                            let mut f = OpenOptions::new()
-                                .create(true).append(true).open(dest_file).unwrap();
-                            f.write_all(buf[n].as_bytes()).unwrap();
+                            store(n, b);
                         }
                         return;
                     }
@@ -456,25 +854,34 @@ impl Incubator {
                                     panic!("{msg}");
                                 },
                             };
-                        buf[n] += line;
-                        buf[n] += "\n";
+                        if format == argument::Format::Binary {
+                            let ban = match bitboard::BitBoard::try_from(elem[0]) {
+                                Ok(b) => {b},
+                                Err(e) => {panic!("{e} w/ {line}");},
+                            };
+                            let (score, value) = match data_loader::parse_score_value(&elem) {
+                                Ok(sv) => {sv},
+                                Err(e) => {panic!("{e} w/ {line}");},
+                            };
+                            // 3列目があれば評価値の欄も使う
+                            let flags = if elem.len() > 2 {
+                                data_loader::FLAG_FIXEDSTONES | data_loader::FLAG_VALUE
+                            } else {
+                                data_loader::FLAG_FIXEDSTONES
+                            };
+                            let (fsb, fsw) = ban.fixedstones();
+                            buf[n].extend_from_slice(&data_loader::encode_record(
+                                &ban, fsb, fsw, score, value, flags));
+                        } else {
+                            buf[n].extend_from_slice(line.as_bytes());
+                            buf[n].push(b'\n');
+                        }
                     }
                     // 残りのマス毎にファイルに分けて出力する。
                     for (n,b) in buf.iter_mut().enumerate() {
                         if b.len() < THREASHOLD_BYTES {continue;}
 
-                        let dir = std::path::Path::new(outdir);
-                        if !dir.is_dir() {
-                            if let Err(e) =
-                                    std::fs::create_dir_all(outdir) {
-                                panic!("failed to create dir \"{outdir:?}\" : {e}");
-                            }
-                        }
-                        let mut dest_file = outdir.clone();
-                        dest_file.push(format!("mate{n}_{suffix}.txt"));
-                        let mut f = OpenOptions::new()
-                            .create(true).append(true).open(&dest_file).unwrap();
-                        f.write_all(b.as_bytes()).unwrap();
+                        store(n, b);
                         b.clear();
                     }
                 },
@@ -490,10 +897,10 @@ impl Incubator {
         if self.mate < 3 || 60 <= self.mate {
             panic!("self.mate < 3 || 60 <= self.mate");
         }
-
+        // 固定長のレコードには親の数を入れられない
         let pbtop = if self.show_progressbar {
             let pb = self.multibar.add(
-                ProgressBar::new(self.kifudir.len() as u64 + 1));
+                ProgressBar::new(self.kifudir.len() as u64 + 2));
             Some(pb)
         } else {
             None
@@ -504,10 +911,15 @@ impl Incubator {
         if let Some(pb) = &pbtop {pb.inc(1);}  // 1
         let mut outdir = std::env::current_dir().unwrap().clone();
         outdir.push(&self.outdir);
+        // 棋譜のディレクトリをまたいで同じ局面は1回だけ出力する
+        let mut spread = Spread::new();
+        let engine = self.engine();
+        let pool = ruversirunner::WorkerPool::new(self.jobs).unwrap();
         for d in self.kifudir.iter() {
             let pbchild = if self.show_progressbar {
-                let pb = self.multibar.add(ProgressBar::new(7));
-                    // load, dedup, extract, dedup, augmentation, dedup, store
+                let pb = self.multibar.add(
+                    ProgressBar::new(2 + self.spread_depth as u64));
+                    // load, dedup, spread x depth
                 pb.set_style(
                     ProgressStyle::with_template(
                         "[{elapsed_precise}]{wide_bar}[{eta_precise}] {pos}/{len} {msg}").unwrap()
@@ -518,8 +930,7 @@ impl Incubator {
                 None
             };
             let mut boards = 
-                    data_loader::loadkifu_for_mate(
-                        &data_loader::findfiles(&format!("./{d}")),
+                    data_loader::loadkifu_dir_for_mate(
                         d, self.mate, &mut self.log, show_path);
             if let Some(pb) = &pbchild {
                 let path = std::path::Path::new(d);
@@ -535,63 +946,69 @@ impl Incubator {
             data_loader::dedupboards(&mut boards, &mut self.log, show_path);
             if let Some(pb) = &pbchild {pb.inc(1);}  // 2
 
-            let (tx, rx) = std::sync::mpsc::channel::<String>();
-            let outdir = outdir.clone();
-            let store_thread = std::thread::spawn(move || {
-                Self::store_rfen_thread(rx, &outdir, "spread");
-            });
+            // ruversiに展開してもらう。見つけた局面をさらにspread_depth段まで展開する。
+            let mut parents = boards.into_iter().map(|b| b.0).collect::<Vec<_>>();
+            for _ in 0..self.spread_depth {
+                // 展開済みの親はもうエンジンに渡さない。子供の親の数も増えない。
+                parents.retain(|ban| spread.expand(ban));
+                if parents.is_empty() {break;}
 
-            // ruversiに展開してもらう
-            let pbgrandchild = if self.show_progressbar {
-                let pb = self.multibar.add(
-                ProgressBar::new(boards.len() as u64));
-                pb.set_style(
-                    ProgressStyle::with_template(
-                        "[{elapsed_precise}] {wide_bar} [{eta_precise}] {pos}/{len} {msg}").unwrap()
-                    .progress_chars("🥚🐔🐤"));
-                Some(pb)
-            } else {
-                None
-            };
-            let mut rr = ruversirunner::RuversiRunner::from_config(
-                &std::path::PathBuf::from(
-                    self.ruversi_config.clone())).unwrap();
-            rr.set_verbose(self.verbose);
-            for (ban, _, _, _) in boards.iter() {
-                let mates = match rr.run_all_children(&ban.to_string()) {
-                    Err(msg) => {panic!("{msg}")},
-                    Ok(ban) => {
-                        if let Some(pb) = &pbgrandchild {pb.inc(1);}
-                        ban
-                    },
+                let pbgrandchild = if self.show_progressbar {
+                    let pb = self.multibar.add(
+                    ProgressBar::new(parents.len() as u64));
+                    pb.set_style(
+                        ProgressStyle::with_template(
+                            "[{elapsed_precise}] {wide_bar} [{eta_precise}] {pos}/{len} {msg}").unwrap()
+                        .progress_chars("🥚🐔🐤"));
+                    Some(pb)
+                } else {
+                    None
                 };
-
-                let data = mates.join("\n");
-                if !data.is_empty() {tx.send(data).unwrap();}
-            }
-            if let Some(pb) = &pbchild {pb.inc(1);}  // 3
-
-            tx.send(String::new()).unwrap();  // send quit
-            store_thread.join().unwrap();
-            if let Some(pb) = &pbgrandchild {
-                pb.finish();
-                self.multibar.remove(pb);
-            }
-            if let Some(pb) = &pbchild {
-                pb.inc(1);  // 7
-                pb.finish();
-                // self.multibar.remove(pb);
+                // 親の順番に並べて手の順に足すので実行毎に同じ出力になる
+                let sources = parents.iter().map(|ban| vec![ban.clone()]).collect::<Vec<_>>();
+                let results = self.expand_children(&pool, engine.as_ref(), &parents, &sources,
+                    false, |_| {
+                        if let Some(pb) = &pbgrandchild {pb.inc(1);}
+                    });
+                parents = results.into_iter().flat_map(|children| {
+                    spread.add(&children.unwrap_or_default().0)
+                }).collect();
+                if let Some(pb) = &pbgrandchild {
+                    pb.finish();
+                    self.multibar.remove(pb);
+                }
+                if let Some(pb) = &pbchild {pb.inc(1);}  // 3..
             }
+            if let Some(pb) = &pbchild {pb.finish();}
             if let Some(pb ) = &pbtop {pb.inc(1);}
         }
 
+        let msg = format!("spread: {} boards\n", spread.len());
+        self.log.write_all(msg.as_bytes())?;
+        if show_path {print!("{msg}");}
+        let (tx, rx) = std::sync::mpsc::channel::<String>();
+        let data = spread.lines(self.value_column).join("\n");
+        if !data.is_empty() {tx.send(data).unwrap();}
+        tx.send(String::new()).unwrap();  // send quit
+        Self::store_rfen_thread(rx, &outdir, "spread", self.format);
+        // 親の数は局面のファイルに混ぜずに別のファイルにする
+        if spread.len() > 0 {
+            std::fs::create_dir_all(&outdir)?;
+            std::fs::write(outdir.join("spread_count.csv"), spread.counts().join("\n") + "\n")?;
+        }
         if let Some(pb ) = &pbtop {
+            pb.inc(1);
             pb.finish_with_message("done!");
         }
         Ok(())
     }
 
     fn run_dedup(&mut self) -> Result<(), std::io::Error> {
+        // 棋譜のファイルをその場で書き換えるので形式は変えられない
+        if self.format == argument::Format::Binary {
+            return Err(std::io::Error::other(
+                "dedup rewrites the files in place and does not support --format binary"));
+        }
         let pbtop = if self.show_progressbar {
             let pb = self.multibar.add(
                 ProgressBar::new(self.kifudir.len() as u64 + 1));
@@ -617,7 +1034,8 @@ impl Incubator {
             } else {
                 None
             };
-            for path in files {
+            for fname in files {
+                let path = format!("{d}/{fname}");
                 if show_path {self.putlog(&path.to_string());}
 
                 // if let Err(e) = self.dedup_rfen(&path, &pbchild) {
@@ -722,7 +1140,7 @@ impl Incubator {
     }
 
     #[allow(dead_code)]
-    fn dedup_rfen(&self, path : &str, pb : &Option<ProgressBar>) -> Result<(), std::io::Error> {
+    fn dedup_rfen(&self, path : &str, _pb : &Option<ProgressBar>) -> Result<(), std::io::Error> {
         let path_aug = path.to_string() + ".Aug";
         let path_uniq = path.to_string() + ".Uniq";
 
@@ -786,9 +1204,9 @@ impl Incubator {
                 // find a same line in Aug
                 if Self::find_line_any(&target, &path_uniq) {continue;}
             }
-            let mut finB = OpenOptions::new()
+            let mut fin_b = OpenOptions::new()
                     .create(true).append(true).open(&path_uniq)?;
-            finB.write_all((line + "\n").as_bytes())?;
+            fin_b.write_all((line + "\n").as_bytes())?;
         }
 
         if let Some(pb) = &pbar {
@@ -798,7 +1216,7 @@ impl Incubator {
         Ok(())
     }
 
-    fn dedup_rfen_in_mem(&self, path : &str, pb : &Option<ProgressBar>) -> Result<(), std::io::Error> {
+    fn dedup_rfen_in_mem(&self, path : &str, _pb : &Option<ProgressBar>) -> Result<(), std::io::Error> {
         let path_uniq = path.to_string() + ".Uniq";
         let path_aug = path.to_string() + ".Aug";
         // let mut filtered = Vec::with_capacity(1000000);
@@ -947,7 +1365,7 @@ impl Incubator {
             //     panic!("if !filtered.contains(&line)");
             // }
         }
-        // let mut finB = OpenOptions::new()
+        // let mut fin_b = OpenOptions::new()
         //         .create(true).append(true).open(&path_uniq)?;
         // // finB.write_all((filtered..join("\n") + "\n").as_bytes())?;
         // finB.write_all((filtered.into_iter().collect::<Vec<String>>()
@@ -972,6 +1390,11 @@ impl Incubator {
         if self.mate < 3 || 60 <= self.mate {
             panic!("self.mate < 3 || 60 <= self.mate");
         }
+        // 短いrfenは文字列の形式なのでバイナリにはできない
+        if self.format == argument::Format::Binary {
+            return Err(std::io::Error::other(
+                "shorten writes short rfen text and does not support --format binary"));
+        }
 
         let pbtop = if self.show_progressbar {
             let pb = self.multibar.add(
@@ -999,7 +1422,7 @@ impl Incubator {
             } else {
                 None
             };
-            let files = data_loader::findfiles(&format!("./{d}"));
+            let files = data_loader::find_mate_files(&format!("./{d}"));
             if let Some(pb) = &pbchild {pb.set_length(files.len() as u64 + 4);}
             for fname in files {
                 let path = format!("{d}/{fname}");
@@ -1018,12 +1441,13 @@ impl Incubator {
                 let (tx, rx) = std::sync::mpsc::channel::<String>();
                 let outdir = outdir.clone();
                 let store_thread = std::thread::spawn(move || {
-                    Self::store_rfen_thread(rx, &outdir, "shorten");
+                    Self::store_rfen_thread(rx, &outdir, "shorten",
+                        argument::Format::Text);
                 });
 
                 // convert to short rfen
                 let mut data = String::new();
-                for (ban, _, _, score) in boards {
+                for (ban, _, _, score, _) in boards {
                     // if let Some(pb) = &pbgrandchild {pb.inc(1);}
                     data += &format!("{},{score}\n", ban.to_string_short());
                 }
@@ -1075,7 +1499,7 @@ impl Incubator {
         // let outdir = self.outdir.clone();
         if let Some(pb) = &pbtop {pb.inc(1);}  // 1
         for d in self.kifudir.iter() {
-            let files = data_loader::findfiles(&format!("./{d}"));
+            let files = data_loader::find_mate_files(&format!("./{d}"));
             if let Some(pb) = &pbtop {pb.inc(1);}  // 2n
 
             let pbchild = if self.show_progressbar {
@@ -1106,8 +1530,9 @@ impl Incubator {
 
                 let outdir = outdir.clone();
                 let (tx, rx) = std::sync::mpsc::channel::<String>();
+                let format = self.format;
                 let store_thread = std::thread::spawn(move || {
-                    Self::store_rfen_thread(rx, &outdir, "validate");
+                    Self::store_rfen_thread(rx, &outdir, "validate", format);
                 });
 
                 // validate score w/ ruversi
@@ -1122,32 +1547,28 @@ impl Incubator {
                 } else {
                     None
                 };
-                for (ban, _, _, score) in boards {
-                    let data;
-                    // if let Some(pb) = &pbgrandchild {pb.inc(1);}
-                    // data += &format!("{},{score}\n", ban.to_string_short());
+                let engine = self.engine();
+                let pool = ruversirunner::WorkerPool::new(self.jobs).unwrap();
+                pool.stream(&boards, |(ban, _, _, score, _)| {
+                    let score = *score;
                     // eprintln!("{},{score}", ban.to_string_short());
-                    let mut rr = ruversirunner::RuversiRunner::from_config(
-                        &std::path::PathBuf::from(
-                            self.ruversi_config.clone())).unwrap();
-                    rr.set_verbose(self.verbose);
-                    match rr.run(&ban.to_string()) {
-                        Err(msg) => {panic!("{msg}")},
-                        Ok((_txt, new_score)) => {
+                    let data = match self.call_engine(ban, std::slice::from_ref(ban),
+                            || engine.evaluate(ban)) {
+                        None => String::new(),
+                        Some((_txt, new_score)) => {
                             // eprintln!("(,): {txt}, {new_score}");
-                            //if let Some(pb) = &pbgrandchild {pb.inc(1);}
-                            data = format!("{},{}", ban.to_string_short(),
-                                if new_score.parse::<f32>().unwrap() * (score as f32) < 0f32 {
+                            format!("{},{}", ban.to_string_short(),
+                                if new_score * (score as f32) < 0f32 {
                                     // eprintln!("{new_score} != {score}");
                                     -score
                                 } else {
                                     score
-                                });
+                                })
                         },
-                    }
-                    if !data.is_empty() {tx.send(data).unwrap();}
+                    };
                     if let Some(pb) = &pbgrandchild {pb.inc(1);}
-                }
+                    if data.is_empty() {None} else {Some(data)}
+                }, &tx);
                 if let Some(pb) = &pbchild {pb.inc(1);}  // 3
 
                 tx.send(String::new()).unwrap();  // send quit
@@ -1177,6 +1598,377 @@ impl Incubator {
         Ok(())
     }
 
+    /// 棋譜を並べ直して問題のある棋譜を報告する。
+    fn run_lint(&mut self) -> Result<(), std::io::Error> {
+        let pbtop = if self.show_progressbar {
+            let pb = self.multibar.add(
+                ProgressBar::new(self.kifudir.len() as u64));
+            Some(pb)
+        } else {
+            None
+        };
+
+        let mut nfiles = 0;
+        let mut nbad = 0;
+        for d in self.kifudir.iter() {
+            let (kifus, nkifu) = match data_loader::iter_kifus(d) {
+                Ok(kifus) => kifus,
+                Err(msg) => {panic!("{msg}");},
+            };
+            let pbchild = if self.show_progressbar {
+                let pb = match nkifu {
+                    Some(n) => self.multibar.add(ProgressBar::new(n as u64)),
+                    None => self.multibar.add(ProgressBar::no_length()),
+                };
+                pb.set_style(
+                    ProgressStyle::with_template(
+                        "[{elapsed_precise}]{wide_bar}[{eta_precise}] {pos}/{len} {msg}").unwrap()
+                    .progress_chars("📜📔📖"));
+                Some(pb)
+            } else {
+                None
+            };
+            for (path, content) in kifus {
+                if let Some(pb) = &pbchild {pb.set_message(path.clone());}
+                let content = match content {
+                    Ok(content) => content,
+                    Err(msg) => {
+                        nfiles += 1;
+                        nbad += 1;
+                        let report = format!("{path}: {msg}\n");
+                        self.log.write_all(report.as_bytes())?;
+                        print!("{report}");
+                        continue;
+                    },
+                };
+                let lines = content.split('\n').collect::<Vec<_>>();
+                let errors = kifu::lint(&lines);
+                nfiles += 1;
+                let mut report = if errors.is_empty() {
+                    format!("{path}: OK\n")
+                } else {
+                    nbad += 1;
+                    format!("{path}: {} error(s)\n", errors.len())
+                };
+                for e in errors.iter() {
+                    report += &format!("  {e}\n");
+                }
+                self.log.write_all(report.as_bytes())?;
+                if errors.is_empty() && !self.verbose {
+                    // 問題の無い棋譜はログにだけ残す
+                } else if let Some(pb) = &pbchild {
+                    pb.println(report.trim_end());
+                } else {
+                    print!("{report}");
+                }
+                if let Some(pb) = &pbchild {pb.inc(1);}
+            }
+            if let Some(pb) = &pbchild {pb.finish();}
+            if let Some(pb) = &pbtop {pb.inc(1);}
+        }
+        if let Some(pb) = &pbtop {pb.finish_with_message("done!");}
+
+        let summary = format!("{nbad} / {nfiles} file(s) have errors.\n");
+        self.log.write_all(summary.as_bytes())?;
+        print!("{summary}");
+        Ok(())
+    }
+
+    /// 隣り合うmateファイルの値が子供の局面の値と合っているか調べる。
+    fn run_consistency(&mut self) -> Result<(), std::io::Error> {
+        let show_path = self.verbose;
+        let ptn = regex::Regex::new("^mate(\\d+)\\.(?:txt|bin)(?:\\.zstd?)?$").unwrap();
+        let pool = ruversirunner::WorkerPool::new(self.jobs).unwrap();
+        let mut npair = 0;
+        let mut nbad = 0;
+        for d in self.kifudir.clone() {
+            // 残りのマス毎のファイル
+            let mut levels : std::collections::BTreeMap<u32, Vec<String>>
+                    = std::collections::BTreeMap::new();
+            for fname in data_loader::find_mate_files(&format!("./{d}")) {
+                let Some(cap) = ptn.captures(&fname) else {continue;};
+                let n = cap[1].parse::<u32>().unwrap();
+                levels.entry(n).or_default().push(format!("{d}/{fname}"));
+            }
+            let load = |paths : &[String]| -> Result<Vec<bitboard::Sample>, std::io::Error> {
+                let mut boards = Vec::new();
+                for path in paths.iter() {
+                    boards.extend(data_loader::load_mates_all(path).map_err(
+                        |msg| std::io::Error::other(format!("{msg} @ {path}")))?);
+                }
+                Ok(boards)
+            };
+            for (n, paths) in levels.iter() {
+                let Some(lowerpaths) = n.checked_sub(1).and_then(|n1| levels.get(&n1)) else {
+                    continue;
+                };
+                npair += 1;
+                let mut boards = load(paths)?;
+                data_loader::dedupboards(&mut boards, &mut self.log, show_path);
+                let lower = consistency::Lower::new(&load(lowerpaths)?);
+                let msg = format!("mate{}: {} boards\n", n - 1, lower.len());
+                self.log.write_all(msg.as_bytes())?;
+                if show_path {print!("{msg}");}
+                let findings = pool.map(&boards, |(ban, _, _, score, _)| {
+                    consistency::check(ban, *score, &lower)
+                });
+
+                let name = paths.join(",");
+                let mut ncontra = 0;
+                let mut nmissing = 0;
+                let mut report = String::new();
+                for ((ban, _, _, score, _), finding) in boards.iter().zip(findings) {
+                    match finding {
+                        None => {},
+                        Some(consistency::Finding::Contradiction(expected)) => {
+                            ncontra += 1;
+                            report += &format!("{name}: {ban},{score} : children say {expected}\n");
+                        },
+                        Some(consistency::Finding::Missing(children)) => {
+                            nmissing += 1;
+                            report += &format!("{name}: {ban},{score} : {} child(ren) missing\n",
+                                children.len());
+                            for child in children.iter() {
+                                report += &format!("  {child}\n");
+                            }
+                        },
+                    }
+                }
+                report += &format!(
+                    "mate{n} vs mate{} in {d}: {ncontra} contradiction(s), \
+                    {nmissing} with missing children / {} position(s).\n",
+                    n - 1, boards.len());
+                if ncontra + nmissing > 0 {nbad += 1;}
+                self.log.write_all(report.as_bytes())?;
+                print!("{report}");
+            }
+        }
+
+        let summary = format!("{nbad} / {npair} pair(s) of mate files have problems.\n");
+        self.log.write_all(summary.as_bytes())?;
+        print!("{summary}");
+        Ok(())
+    }
+
+    /// 初期局面か棋譜の局面から乱数で進めて残りmateマスの局面を作る。
+    /// 石の差はまだ分からないので今の石の差を入れておく。mateモードで値を付ける。
+    fn run_generate(&mut self) -> Result<(), std::io::Error> {
+        if self.mate < 3 || 60 <= self.mate {
+            panic!("self.mate < 3 || 60 <= self.mate");
+        }
+
+        let show_path = self.verbose;
+        // 始めの局面。棋譜が無ければ初期局面から。
+        let mut starts = Vec::new();
+        for d in self.kifudir.iter() {
+            let (kifus, _) = match data_loader::iter_kifus(d) {
+                Ok(kifus) => kifus,
+                Err(msg) => {panic!("{msg}");},
+            };
+            for (path, content) in kifus {
+                let content = match content {
+                    Ok(content) => content,
+                    Err(msg) => {
+                        self.log.write_all(format!("{path}: {msg}\n").as_bytes())?;
+                        continue;
+                    },
+                };
+                self.log.write_all(format!("{path}\n").as_bytes())?;
+                if show_path {print!("{path}\r");}
+                let lines = content.split('\n').collect::<Vec<_>>();
+                let kifu = kifu::Kifu::from(&lines);
+                starts.extend(kifu.list.iter().filter_map(|t| {
+                    bitboard::BitBoard::try_from(t.rfen.as_str()).ok()
+                }).filter(|ban| ban.nblank() > self.mate));
+            }
+        }
+        if self.kifudir.is_empty() {starts.push(bitboard::BitBoard::new());}
+        if starts.is_empty() {
+            return Err(std::io::Error::other(
+                format!("no position with more than {} empties", self.mate)));
+        }
+
+        let pb = if self.show_progressbar {
+            let pb = self.multibar.add(ProgressBar::new(self.playouts as u64));
+            pb.set_style(
+                ProgressStyle::with_template(
+                    "[{elapsed_precise}] {wide_bar} [{eta_precise}] {pos}/{len} {msg}").unwrap()
+                .progress_chars("🎲🎯🏁"));
+            Some(pb)
+        } else {
+            None
+        };
+        let engine = self.epsilon.map(|_| self.engine());
+        let epsilon = self.epsilon.unwrap_or(1.0);
+        let pool = ruversirunner::WorkerPool::new(self.jobs).unwrap();
+        // 1局毎に種を変えるので並列に動かしても同じ結果になる
+        let seeds = (0..self.playouts as u64).map(|i| self.seed.wrapping_add(i))
+            .collect::<Vec<_>>();
+        let results = pool.map(&seeds, |&seed| {
+            let ret = generator::playout(&starts, self.mate, seed, epsilon, |ban| {
+                let engine = engine.as_ref()?;
+                let (mv, _) = self.call_engine(ban, std::slice::from_ref(ban),
+                    || engine.evaluate(ban))?;
+                generator::parse_move(ban, &mv)
+            });
+            if let Some(pb) = &pb {pb.inc(1);}
+            ret
+        });
+        if let Some(pb) = &pb {
+            pb.finish();
+            self.multibar.remove(pb);
+        }
+
+        let mut seen = HashSet::new();
+        let mut nend = 0;
+        let mut lines = Vec::new();
+        for ban in results {
+            let Some(ban) = ban else {
+                nend += 1;
+                continue;
+            };
+            let (key, _) = ban.canonical();
+            if !seen.insert(key) {continue;}
+
+            let score = ban.count();
+            lines.push(format_sample(&ban, score, score as f32, self.value_column));
+        }
+        let msg = format!("generate: {} boards from {} playout(s), {nend} ended early\n",
+            lines.len(), self.playouts);
+        self.log.write_all(msg.as_bytes())?;
+        if show_path {print!("{msg}");}
+
+        let mut outdir = std::env::current_dir().unwrap().clone();
+        outdir.push(&self.outdir);
+        let (tx, rx) = mpsc::channel::<String>();
+        if !lines.is_empty() {tx.send(lines.join("\n")).unwrap();}
+        tx.send(String::new()).unwrap();  // send quit
+        Self::store_rfen_thread(rx, &outdir, "generate", self.format);
+        Ok(())
+    }
+
+    /// "rfen,score"の局面を乱数で終局まで打って、
+    /// "rfen,score,mean,var,win,draw,loss"にして書き出す。
+    fn run_rollout(&mut self) -> Result<(), std::io::Error> {
+        // 固定長のレコードには統計の列を入れられない
+        if self.format == argument::Format::Binary {
+            return Err(std::io::Error::other(
+                "rollout does not support --format binary, the statistics columns would be lost"));
+        }
+        let show_path = self.verbose;
+        let mut boards = Vec::new();
+        for d in self.kifudir.iter() {
+            for fname in data_loader::find_mate_files(&format!("./{d}")) {
+                let path = format!("{d}/{fname}");
+                self.log.write_all(format!("{path}\n").as_bytes())?;
+                if show_path {print!("{path}\r");}
+                boards.extend(data_loader::load_mates_all(&path).map_err(
+                    |msg| std::io::Error::other(format!("{msg} @ {path}")))?);
+            }
+        }
+        let msg = format!("rollout: {} boards x {} game(s)\n", boards.len(), self.rollouts);
+        self.log.write_all(msg.as_bytes())?;
+        if show_path {print!("{msg}");}
+
+        let pb = if self.show_progressbar {
+            let pb = self.multibar.add(ProgressBar::new(boards.len() as u64));
+            pb.set_style(
+                ProgressStyle::with_template(
+                    "[{elapsed_precise}] {wide_bar} [{eta_precise}] {pos}/{len} {msg}").unwrap()
+                .progress_chars("🎲🎯🏁"));
+            Some(pb)
+        } else {
+            None
+        };
+        // 局面毎に種を変えるので並列に動かしても同じ結果になる
+        let indexed = boards.iter().enumerate().collect::<Vec<_>>();
+        let pool = ruversirunner::WorkerPool::new(self.jobs).unwrap();
+        let lines = pool.map(&indexed, |(i, (ban, _, _, score, _))| {
+            let stats = rollout::rollout(ban, self.rollouts,
+                self.seed.wrapping_add(*i as u64), self.rollout_policy);
+            if let Some(pb) = &pb {pb.inc(1);}
+            format!("{ban},{score},{stats}")
+        });
+        if let Some(pb) = &pb {
+            pb.finish();
+            self.multibar.remove(pb);
+        }
+
+        let mut outdir = std::env::current_dir().unwrap().clone();
+        outdir.push(&self.outdir);
+        let (tx, rx) = mpsc::channel::<String>();
+        if !lines.is_empty() {tx.send(lines.join("\n")).unwrap();}
+        tx.send(String::new()).unwrap();  // send quit
+        Self::store_rfen_thread(rx, &outdir, "rollout", self.format);
+        Ok(())
+    }
+
+    /// 棋譜から指定の範囲の局面を取り出して終局の石の差を値にする。
+    /// 残りのマス毎にファイルに分けて書き出す。
+    fn run_midgame(&mut self) -> Result<(), std::io::Error> {
+        let show_path = self.verbose;
+        let min = self.min_empties.unwrap_or(0);
+        let max = self.max_empties.unwrap_or(60);
+        if min > max {
+            return Err(std::io::Error::other(
+                format!("--min-empties {min} > --max-empties {max}")));
+        }
+
+        let keep = |ban : &bitboard::BitBoard| {
+            (min..=max).contains(&ban.nblank())
+                && self.progress.is_none_or(|p| ban.is_progress(p))
+        };
+
+        let mut outdir = std::env::current_dir().unwrap().clone();
+        outdir.push(&self.outdir);
+        let (tx, rx) = mpsc::channel::<String>();
+        let format = self.format;
+        let store_thread = std::thread::spawn(move || {
+            Self::store_rfen_thread(rx, &outdir, "midgame", format);
+        });
+
+        let mut nkifu = 0;
+        let mut nskip = 0;
+        let mut nboard = 0;
+        let mut report = String::new();
+        for d in self.kifudir.iter() {
+            let (kifus, _) = match data_loader::iter_kifus(d) {
+                Ok(kifus) => kifus,
+                Err(msg) => {panic!("{msg}");},
+            };
+            for (path, content) in kifus {
+                nkifu += 1;
+                let boards = content.and_then(|content| {
+                    data_loader::extract_midgame(&content, keep, self.discount)
+                });
+                let boards = match boards {
+                    Ok(boards) => boards,
+                    Err(msg) => {
+                        // 結果の無い棋譜は飛ばす
+                        nskip += 1;
+                        report += &format!("{path}: {msg}\n");
+                        continue;
+                    },
+                };
+                report += &format!("{path}\n");
+                if show_path {print!("{path}\r");}
+                nboard += boards.len();
+                let data = boards.iter().map(|(ban, value)| {
+                    format_sample(ban, self.rounding.apply(*value), *value, self.value_column)
+                }).collect::<Vec<_>>().join("\n");
+                if !data.is_empty() {tx.send(data).unwrap();}
+            }
+        }
+        tx.send(String::new()).unwrap();  // send quit
+        store_thread.join().unwrap();
+
+        let msg = format!("midgame: {nboard} boards from {} kifu(s), {nskip} skipped\n",
+            nkifu - nskip);
+        self.log.write_all((report + &msg).as_bytes())?;
+        if show_path {print!("{msg}");}
+        Ok(())
+    }
+
     fn putlog(&mut self, msg : &str) {
         let msg = if msg.ends_with("\n") {
             msg
@@ -49,9 +49,12 @@ impl Te {
             x = 0;
             y = 0;
         } else {
-            let c = elem[2].chars().nth(0).unwrap();
-            x = STR_POSX.find(c)?;
-            y = elem[2].chars().nth(1).unwrap().to_digit(10).unwrap() as usize;
+            let mut chars = elem[2].chars();
+            x = STR_POSX.find(chars.next()?)?;
+            y = chars.next()?.to_digit(10)? as usize;
+            if x == 0 || y == 0 || y > bitboard::NUMCELL || chars.next().is_some() {
+                return None;
+            }
         }
         let rfen = format!("{} {}", elem[3], elem[4]);
         Some(Te {x, y, teban, rfen})
@@ -104,6 +107,15 @@ fn testte() {
     let te = Te::from("1  @@ a1 rfenb");
     assert!(te.is_none());
 
+    let te = Te::from("1 @@ a9 rfen b");
+    assert!(te.is_none());
+
+    let te = Te::from("1 @@ z1 rfen b");
+    assert!(te.is_none());
+
+    let te = Te::from("1 @@ a rfen b");
+    assert!(te.is_none());
+
     let te = Te::from("1 @@ a1 rfen b");
     assert!(te.is_some());
     let te = te.unwrap();
@@ -150,19 +162,12 @@ impl Kifu {
             }
             ret.list.push(te.unwrap());
         }
-        // score?
-        let result = lines.last().unwrap();
-        // println!("{:?}", result);
-        let score = result.split(' ').collect::<Vec<&str>>();
-        let score = score.last().unwrap().parse::<i8>().unwrap_or(0);
-        ret.winneris(score);
-        // if result.find("SENTE won.").is_some() {
-        //     ret.score = Some(1);
-        // } else if result.find("GOTE won.").is_some() {
-        //     ret.score = Some(-1);
-        // } else if result.find("DRAW.").is_some() {
-        //     ret.score = Some(0);
-        // }
+        // 最後の空でない行が結果。改行で終わるファイルは最後が空行になる。
+        // 結果の行が無ければ対局中。
+        let result = lines.iter().rev().map(|l| l.trim_end()).find(|l| !l.is_empty());
+        if let Some(score) = result.and_then(|l| parse_result(l).ok()) {
+            ret.winneris(score);
+        }
         ret
     }
 
@@ -287,4 +292,235 @@ fn testkifu() {
     for ((i, a), b) in kifu.list.iter().enumerate().zip(kifu2.list.iter()) {
         assert_eq!(a.to_str(i), b.to_str(i));
     }
+
+    // 改行で終わっていても結果を読む
+    let txt = format!("{lines}\n");
+    let kifu = Kifu::from(&txt.split("\n").collect::<Vec<&str>>());
+    assert_eq!(kifu.score, Some(4));
+    let txt = lines.replace("SENTE won. 4", "GOTE won. -2\r\n\n");
+    let kifu = Kifu::from(&txt.split("\n").collect::<Vec<&str>>());
+    assert_eq!(kifu.score, Some(-2));
+    let kifu = Kifu::from(&lines.replace("SENTE won. 4", "DRAW.").split("\n").collect());
+    assert_eq!(kifu.score, Some(0));
+    // 結果が無い
+    let txt = lines.replace("SENTE won. 4", "");
+    let kifu = Kifu::from(&txt.split("\n").collect::<Vec<&str>>());
+    assert_eq!(kifu.list.len(), 6);
+    assert_eq!(kifu.score, None);
+}
+
+/// 棋譜のチェックで見つかった問題
+#[derive(Debug, PartialEq)]
+pub struct LintError {
+    /// 行番号(1~)
+    pub line : usize,
+    pub msg : String,
+}
+
+impl std::fmt::Display for LintError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+/// 結果の行を読む。
+///
+/// # Returns
+/// - Ok(score)
+/// - Err(msg) 読めなかった
+fn parse_result(line : &str) -> Result<i8, String> {
+    if line == "DRAW." {return Ok(0);}
+
+    let (sign, num) = if let Some(num) = line.strip_prefix("SENTE won. ") {
+        (1, num)
+    } else if let Some(num) = line.strip_prefix("GOTE won. ") {
+        (-1, num)
+    } else {
+        return Err(format!("unknown result \"{line}\""));
+    };
+    let score = num.parse::<i8>().map_err(
+        |e| format!("invalid score \"{num}\" : {e}"))?;
+    if score == 0 || score.signum() != sign {
+        return Err(format!("score {score} does not match \"{line}\""));
+    }
+    Ok(score)
+}
+
+/// 棋譜を初期局面から並べ直して矛盾が無いか調べる。
+///
+/// - 読めない行
+/// - 記録されているrfenと並べた局面の違い
+/// - 手番の違い
+/// - 打てない手、打てる手があるのにPS
+/// - 結果の行と最終局面の石の差の違い
+///
+/// # Returns
+/// 見つかった問題の配列。問題が無ければ空。
+pub fn lint(lines : &[&str]) -> Vec<LintError> {
+    let mut errors = Vec::new();
+    let mut ban = bitboard::BitBoard::new();
+    let mut result = None;
+    let mut nmove = 0;
+    for (i, &l) in lines.iter().enumerate() {
+        let line = i + 1;
+        let l = l.trim_end();
+        if l.is_empty() || l.starts_with('#') {continue;}
+
+        if let Some((resline, _)) = result {
+            errors.push(LintError {line : resline,
+                msg : String::from("result is not the last line")});
+            result = None;
+        }
+        let te = match Te::from(l) {
+            Some(te) => te,
+            None => {
+                match parse_result(l) {
+                    Ok(score) => {result = Some((line, score));},
+                    Err(msg) => {
+                        errors.push(LintError {line,
+                            msg : format!("cannot parse \"{l}\". {msg}")});
+                    },
+                }
+                continue;
+            },
+        };
+        nmove += 1;
+
+        match bitboard::BitBoard::try_from(te.rfen.as_str()) {
+            Ok(recorded) => {
+                if recorded.black != ban.black || recorded.white != ban.white
+                        || recorded.teban != ban.teban {
+                    errors.push(LintError {line, msg : format!(
+                        "rfen \"{}\" != replayed \"{ban}\"", te.rfen)});
+                    // 以降は記録に合わせて続ける
+                    ban = recorded;
+                }
+            },
+            Err(msg) => {
+                errors.push(LintError {line,
+                    msg : format!("invalid rfen \"{}\". {msg}", te.rfen)});
+            },
+        }
+        if te.teban != ban.teban {
+            errors.push(LintError {line,
+                msg : format!("teban {} != {}", te.teban, ban.teban)});
+        }
+
+        let moves = ban.mobility();
+        let xy = bitboard::cell(te.x as u8, te.y as u8);
+        if xy == bitboard::PASS {
+            if moves != 0 {
+                errors.push(LintError {line, msg : format!(
+                    "PS while {} moves available", moves.count_ones())});
+            }
+        } else if moves & (bitboard::LSB_CELL << xy) == 0 {
+            errors.push(LintError {line,
+                msg : format!("illegal move {}", te.pos())});
+            // 石だけは置いて続ける
+            let mut newban = ban.clone();
+            newban.set(te.x as u8 - 1, te.y as u8 - 1);
+            newban.flipturn();
+            ban = newban;
+            continue;
+        }
+        ban = ban.r#move(xy).unwrap();
+    }
+
+    if nmove == 0 {
+        errors.push(LintError {line : lines.len(), msg : String::from("no moves")});
+    }
+    match result {
+        None => {
+            errors.push(LintError {line : lines.len(),
+                msg : String::from("no result line")});
+        },
+        Some((line, score)) => {
+            let mine = ban.mobility();
+            let oppo = ban.r#move(bitboard::PASS).unwrap().mobility();
+            if mine != 0 || oppo != 0 {
+                errors.push(LintError {line, msg : format!(
+                    "game is not finished \"{ban}\"")});
+            }
+            if score != ban.count() {
+                errors.push(LintError {line, msg : format!(
+                    "score {score} != count {} \"{ban}\"", ban.count())});
+            }
+        },
+    }
+    errors
+}
+
+#[cfg(test)]
+/// 乱数で1局並べた棋譜を作る。
+pub fn random_kifu(seed : u64) -> Kifu {
+    use rand::{Rng, SeedableRng};
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    let mut kifu = Kifu::new();
+    let mut ban = bitboard::BitBoard::new();
+    loop {
+        let moves = ban.genmove();
+        let Some(moves) = moves else {break};
+        let passed = moves[0] == bitboard::PASS;
+        if passed && ban.r#move(bitboard::PASS).unwrap().mobility() == 0 {
+            break;
+        }
+        let mv = moves[rng.gen_range(0..moves.len())];
+        let (x, y) = if passed {(0, 0)} else {bitboard::cell2xy(mv)};
+        kifu.append(x as usize, y as usize, ban.teban, ban.to_string());
+        ban = ban.r#move(mv).unwrap();
+    }
+    kifu.winneris(ban.count());
+    kifu
+}
+
+#[test]
+fn testlint() {
+    for seed in 0..20 {
+        let txt = random_kifu(seed).to_str();
+        let lines = txt.split('\n').collect::<Vec<_>>();
+        assert_eq!(lint(&lines), vec![], "{txt}");
+    }
+
+    let txt = random_kifu(1).to_str();
+    let lines = txt.split('\n').collect::<Vec<_>>();
+
+    // 結果の改ざん
+    let mut bad = lines.clone();
+    let last = bad.len() - 1;
+    bad[last] = "SENTE won. 64";
+    let errors = lint(&bad);
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].msg.starts_with("score 64 != count"), "{}", errors[0]);
+    bad[last] = "SENTE won. -3";
+    let errors = lint(&bad);
+    assert!(errors[0].msg.ends_with("score -3 does not match \"SENTE won. -3\""),
+        "{}", errors[0]);
+    bad[last] = "";
+    assert_eq!(lint(&bad), vec![LintError {line : bad.len(),
+        msg : String::from("no result line")}]);
+
+    // 読めない行
+    let mut bad = lines.clone();
+    bad[3] = "4 @@ z9 8/8/8/3Aa3/3aA3/8/8/8 b";
+    let errors = lint(&bad);
+    assert_eq!(errors[0].line, 4);
+    assert!(errors[0].msg.starts_with("cannot parse"), "{}", errors[0]);
+
+    // 打てない手
+    let mut bad = lines.clone();
+    let l0 = format!("1 @@ a1 {}", bitboard::BitBoard::new());
+    bad[0] = &l0;
+    let errors = lint(&bad);
+    assert_eq!(errors[0], LintError {line : 1, msg : String::from("illegal move a1")});
+    assert_eq!(errors[1].line, 2);
+    assert!(errors[1].msg.starts_with("rfen"), "{}", errors[1]);
+
+    // 打てる手があるのにPS
+    let mut bad = lines.clone();
+    let l0 = format!("1 @@ PS {}", bitboard::BitBoard::new());
+    bad[0] = &l0;
+    let errors = lint(&bad);
+    assert_eq!(errors[0], LintError {line : 1,
+        msg : String::from("PS while 4 moves available")});
 }
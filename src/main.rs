@@ -5,9 +5,20 @@ mod kifu;
 mod bitboard;
 // mod weight;
 mod argument;
+mod consistency;
 mod data_loader;
+mod edaxrunner;
+mod engine;
+mod engineconfig;
+mod generator;
 mod incubator;
+mod policy;
+mod resultcache;
+mod rollout;
+mod ruversioutput;
 mod ruversirunner;
+mod solver;
+mod tarball;
 
 fn main() -> Result<(), std::io::Error> {
     let arg = argument::Arg::parse();
@@ -0,0 +1,110 @@
+//! 方策を学習するための出力。
+//!
+//! 1局面を"rfen,64マス+パスの評価値[,64マス+パスの確率]"の1行にする。
+//! マスの順番はbitの番号(x + 8y)で、最後がパス。打てない手の評価値は空にする。
+
+use crate::bitboard::{self, BitBoard, Sample};
+
+/// 64マスとパス
+pub const N_SLOT : usize = bitboard::CELL_2D + 1;
+/// パスの欄
+pub const SLOT_PASS : usize = bitboard::CELL_2D;
+
+/// 子供の局面の評価値を手の欄に並べる。
+///
+/// # Arguments
+/// - parent 親の局面
+/// - children 子供の局面の情報。評価値は黒から見た値。
+///
+/// # Returns
+/// 手毎の評価値。打てない手はNone。子供の局面が親から1手で行けない時はErr。
+pub fn slots(parent : &BitBoard, children : &[Sample])
+        -> Result<[Option<f32> ; N_SLOT], String> {
+    let moves = parent.genmove().unwrap_or_default();
+    let mut ret = [None ; N_SLOT];
+    for (child, _, _, _, value) in children.iter() {
+        let mv = moves.iter().find(|&&mv| {
+            parent.r#move(mv).is_ok_and(|b| b == *child)
+        }).ok_or_else(|| format!("\"{child}\" is not a child of \"{parent}\""))?;
+        let slot = if *mv == bitboard::PASS {SLOT_PASS} else {*mv as usize};
+        ret[slot] = Some(*value);
+    }
+    Ok(ret)
+}
+
+/// 手番から見た評価値のsoftmax。打てない手は0。
+///
+/// # Arguments
+/// - teban 親の局面の手番
+/// - slots `slots()`の結果
+/// - temperature 温度。小さいほど最善手に集まる。
+pub fn softmax(teban : i8, slots : &[Option<f32> ; N_SLOT], temperature : f32)
+        -> [f32 ; N_SLOT] {
+    let logits = slots.map(|v| v.map(|v| v * teban as f32 / temperature));
+    let max = logits.iter().flatten().fold(f32::NEG_INFINITY, |a, &b| a.max(b));
+    let exps = logits.map(|l| l.map_or(0.0, |l| (l - max).exp()));
+    let sum = exps.iter().sum::<f32>();
+    if sum <= 0.0 {return exps;}
+
+    exps.map(|e| e / sum)
+}
+
+/// 1局面分の行を作る。
+///
+/// # Arguments
+/// - parent 親の局面
+/// - children 子供の局面の情報
+/// - temperature Someならsoftmaxの確率も付ける。
+pub fn format(parent : &BitBoard, children : &[Sample], temperature : Option<f32>)
+        -> Result<String, String> {
+    let slots = slots(parent, children)?;
+    let mut ret = parent.to_string();
+    for v in slots.iter() {
+        ret += ",";
+        if let Some(v) = v {ret += &v.to_string();}
+    }
+    if let Some(t) = temperature {
+        for p in softmax(parent.teban, &slots, t).iter() {
+            ret += &format!(",{p:.4}");
+        }
+    }
+    Ok(ret)
+}
+
+#[test]
+fn test_policy() {
+    let ban = BitBoard::try_from("4A3/2AaB2/3aAa2/2Ca2/2Ad1/1BaAa2/2aBa2/1f1 b").unwrap();
+    let moves = ban.genmove().unwrap();
+    let children = moves.iter().enumerate().map(|(i, &mv)| {
+        (ban.r#move(mv).unwrap(), 0, 0, 0, i as f32 - 1.5)
+    }).collect::<Vec<_>>();
+    let s = slots(&ban, &children).unwrap();
+    assert_eq!(s.iter().flatten().count(), moves.len());
+    for (i, &mv) in moves.iter().enumerate() {
+        assert_eq!(s[mv as usize], Some(i as f32 - 1.5));
+    }
+    // 黒番なので評価値が大きい手ほど確率が高い
+    let p = softmax(ban.teban, &s, 1.0);
+    assert!((p.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+    let last = *moves.last().unwrap() as usize;
+    assert!(p.iter().all(|&x| x <= p[last]));
+    let p = softmax(bitboard::GOTE, &s, 1.0);
+    assert!(p.iter().enumerate().all(|(i, &x)| x >= 0.0 && (s[i].is_some() || x == 0.0)));
+    assert!(p[moves[0] as usize] > p[last]);
+    // 温度が低いと最善手に集まる
+    assert!(softmax(ban.teban, &s, 0.01)[last] > 0.99);
+
+    let line = format(&ban, &children, Some(1.0)).unwrap();
+    assert_eq!(line.split(',').count(), 1 + N_SLOT * 2);
+    assert_eq!(format(&ban, &children, None).unwrap().split(',').count(), 1 + N_SLOT);
+
+    // パスしかできない局面
+    let ban = BitBoard::try_from("H/H/H/H/H/H/Fa1/F2 w").unwrap();
+    let passed = ban.r#move(bitboard::PASS).unwrap();
+    let s = slots(&ban, &[(passed, 0, 0, 0, 64.0)]).unwrap();
+    assert_eq!(s[SLOT_PASS], Some(64.0));
+    assert_eq!(softmax(ban.teban, &s, 1.0)[SLOT_PASS], 1.0);
+
+    // 子供じゃない局面
+    assert!(slots(&ban, &[(BitBoard::new(), 0, 0, 0, 0.0)]).is_err());
+}
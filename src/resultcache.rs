@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::Mutex;
+
+use crate::bitboard::{self, BitBoard, Transform};
+use crate::ruversirunner::ChildPv;
+
+/// エンジンの結果をファイルに残しておいて同じ局面を2回読まないようにする。
+///
+/// 局面は対称な局面をまとめた代表(`BitBoard::canonical()`)で覚える。
+/// エンジンの設定毎に別のファイル(`<dir>/<fingerprint>.txt`)を使う。
+/// 1行1局面で"kind\trfen\t結果"の形式。
+pub struct ResultCache {
+    path : std::path::PathBuf,
+    entries : Mutex<HashMap<(String, String), String>>,
+    file : Mutex<std::fs::File>,
+}
+
+/// 設定の指紋。FNV-1aなのでRustのバージョンが変わっても同じ値になる。
+pub fn fingerprint(parts : &[&str]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for part in parts {
+        // 区切りを入れて["ab", "c"]と["a", "bc"]を区別する
+        for b in part.bytes().chain(std::iter::once(0)) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+/// "d3"や"ps"の手を変換する。
+fn transform_pos(pos : &str, t : &Transform) -> Result<String, String> {
+    if pos.eq_ignore_ascii_case("ps") {return Ok(String::from("ps"));}
+
+    let mut chars = pos.chars();
+    let x = chars.next().and_then(|c| "abcdefgh".find(c.to_ascii_lowercase()));
+    let y = chars.next().and_then(|c| c.to_digit(10)).filter(|y| (1..=8).contains(y));
+    let (Some(x), Some(y), None) = (x, y, chars.next()) else {
+        return Err(format!("invalid move \"{pos}\""));
+    };
+    let cell = t.apply_cell(bitboard::cell(x as u8 + 1, y as u8));
+    let (x, y) = bitboard::cell2xy(cell);
+    Ok(format!("{}{y}", "abcdefgh".chars().nth(x as usize - 1).unwrap()))
+}
+
+/// 黒から見た評価値の文字列を変換する。
+fn transform_score(score : &str, t : &Transform) -> String {
+    if !t.colorflip || score.parse::<f32>().is_ok_and(|v| v == 0.0) {
+        return score.to_string();
+    }
+    match score.strip_prefix('-') {
+        Some(s) => s.to_string(),
+        None => format!("-{score}"),
+    }
+}
+
+/// "rfen,score"を変換する。
+fn transform_line(line : &str, t : &Transform) -> Result<String, String> {
+    let (rfen, score) = line.rsplit_once(',')
+        .ok_or_else(|| format!("invalid line \"{line}\""))?;
+    let ban = BitBoard::try_from(rfen)?;
+    Ok(format!("{},{}", t.apply(&ban), transform_score(score, t)))
+}
+
+impl ResultCache {
+    /// dirの中の設定毎のファイルを開く。無ければ作る。
+    pub fn open(dir : &std::path::Path, fingerprint : u64)
+            -> Result<ResultCache, String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{e} : {dir:?}"))?;
+        let path = dir.join(format!("{fingerprint:016x}.txt"));
+        let mut file = std::fs::OpenOptions::new()
+            .create(true).read(true).append(true).open(&path)
+            .map_err(|e| format!("{e} : {path:?}"))?;
+
+        let mut txt = Vec::new();
+        file.read_to_end(&mut txt).map_err(|e| format!("{e} : {path:?}"))?;
+        // 改行で終わった行だけ信じる。書きかけで終わった行は区切りの良い所で
+        // 切れていても結果が足りないかもしれないので、捨てて次に求め直す。
+        let complete = txt.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        if complete < txt.len() {
+            file.set_len(complete as u64).map_err(|e| format!("{e} : {path:?}"))?;
+        }
+        let mut entries = HashMap::new();
+        for line in txt[..complete].split(|&b| b == b'\n') {
+            let line = String::from_utf8_lossy(line);
+            let mut cols = line.splitn(3, '\t');
+            if let (Some(kind), Some(rfen), Some(payload)) =
+                    (cols.next(), cols.next(), cols.next()) {
+                entries.insert(
+                    (kind.to_string(), rfen.to_string()), payload.to_string());
+            }
+        }
+        Ok(ResultCache {path, entries : Mutex::new(entries), file : Mutex::new(file)})
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// 代表の局面の結果を探す。無ければfで求めて覚えておく。
+    fn get_or_insert(&self, kind : &str, canon : &BitBoard,
+            f : impl FnOnce(&str) -> Result<String, String>) -> Result<String, String> {
+        let rfen = canon.to_string();
+        let key = (kind.to_string(), rfen);
+        if let Some(payload) = self.entries.lock().unwrap().get(&key) {
+            return Ok(payload.clone());
+        }
+
+        let payload = f(&key.1)?;
+        self.file.lock().unwrap().write_all(
+            format!("{kind}\t{}\t{payload}\n", key.1).as_bytes())
+            .map_err(|e| format!("{e} : {:?}", self.path))?;
+        self.entries.lock().unwrap().insert(key, payload.clone());
+        Ok(payload)
+    }
+
+    /// `RuversiRunner::run()`の結果
+    ///
+    /// # Arguments
+    /// - rfen 局面
+    /// - f 代表の局面のrfenを受け取ってエンジンを動かす
+    pub fn run(&self, rfen : &str,
+            f : impl FnOnce(&str) -> Result<(String, String), String>)
+            -> Result<(String, String), String> {
+        let ban = BitBoard::try_from(rfen)?;
+        let (_, t) = ban.canonical();
+        let payload = self.get_or_insert("run", &t.apply(&ban), |canon| {
+            let (pos, score) = f(canon)?;
+            Ok(format!("{pos},{score}"))
+        })?;
+        let (pos, score) = payload.split_once(',')
+            .ok_or_else(|| format!("broken cache \"{payload}\" in {:?}", self.path))?;
+        let inv = t.inverse();
+        Ok((transform_pos(pos, &inv)?, transform_score(score, &inv)))
+    }
+
+    /// `RuversiRunner::run_children_pv()`の結果
+    ///
+    /// 1局面を"rfen,score,読み筋"で覚える。読み筋は無くても良い。
+    pub fn children(&self, rfen : &str,
+            f : impl FnOnce(&str) -> Result<Vec<ChildPv>, String>)
+            -> Result<Vec<ChildPv>, String> {
+        let ban = BitBoard::try_from(rfen)?;
+        let (_, t) = ban.canonical();
+        let payload = self.get_or_insert("children", &t.apply(&ban), |canon| {
+            Ok(f(canon)?.iter().map(|(child, value, pv)| {
+                format!("{child},{value},{}", pv.concat())
+            }).collect::<Vec<_>>().join(";"))
+        })?;
+        let inv = t.inverse();
+        payload.split(';').filter(|l| !l.is_empty()).map(|line| {
+            let broken = || format!("broken cache \"{line}\" in {:?}", self.path);
+            let mut cols = line.split(',');
+            let (Some(rfen), Some(score)) = (cols.next(), cols.next()) else {
+                return Err(broken());
+            };
+            let value = score.parse::<f32>().map_err(|e| format!("{e} : \"{line}\""))?;
+            let pv = cols.next().unwrap_or("");
+            if !pv.is_ascii() || !pv.len().is_multiple_of(2) {return Err(broken());}
+            let pv = (0..pv.len()).step_by(2)
+                .map(|i| transform_pos(&pv[i..i + 2], &inv))
+                .collect::<Result<Vec<_>, String>>()?;
+            let value = if inv.colorflip {-value} else {value};
+            Ok((inv.apply(&BitBoard::try_from(rfen)?), value, pv))
+        }).collect()
+    }
+
+    /// `RuversiRunner::run_all_children()`の結果
+    pub fn all_children(&self, rfen : &str,
+            f : impl FnOnce(&str) -> Result<Vec<String>, String>)
+            -> Result<Vec<String>, String> {
+        let ban = BitBoard::try_from(rfen)?;
+        let (_, t) = ban.canonical();
+        let payload = self.get_or_insert("all", &t.apply(&ban),
+            |canon| Ok(f(canon)?.join(";")))?;
+        let inv = t.inverse();
+        payload.split(';').filter(|l| !l.is_empty())
+            .map(|line| transform_line(line, &inv)).collect()
+    }
+}
+
+#[test]
+fn test_resultcache() {
+    let dir = std::env::temp_dir().join(
+        format!("test_resultcache_{}", std::process::id()));
+    let fp = fingerprint(&["ruversi", "eval.txt", "--depth,7"]);
+    assert_ne!(fp, fingerprint(&["ruversi", "eval.txt", "--depth,8"]));
+    assert_ne!(fingerprint(&["ab", "c"]), fingerprint(&["a", "bc"]));
+
+    let tostr = |mv : u8| {
+        let (x, y) = bitboard::cell2xy(mv);
+        format!("{}{y}", "abcdefgh".chars().nth(x as usize - 1).unwrap())
+    };
+    // 読み筋は子供の局面で最初に見つかった手
+    let engine = |rfen : &str| -> Result<Vec<ChildPv>, String> {
+        let ban = BitBoard::try_from(rfen)?;
+        Ok(ban.genmove().unwrap_or_default().iter().map(|&mv| {
+            let child = ban.r#move(mv).unwrap();
+            // 小数も残る
+            let score = child.count() as f32 * 1.25;
+            let pv = child.genmove().unwrap_or_default().iter().take(1)
+                .map(|&m| tostr(m)).collect();
+            (child, score, pv)
+        }).collect())
+    };
+    let ban = BitBoard::try_from("4A3/2AaB2/3aAa2/2Ca2/2Ad1/1BaAa2/2aBa2/1f1 b").unwrap();
+    let expected = engine(&ban.to_string()).unwrap();
+    let calls = std::sync::atomic::AtomicUsize::new(0);
+    let counted = |rfen : &str| {
+        calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        engine(rfen)
+    };
+    {
+        let cache = ResultCache::open(&dir, fp).unwrap();
+        // 対称な局面は全部1回の呼び出しで済む
+        for t in Transform::all() {
+            let b = t.apply(&ban);
+            let children = cache.children(&b.to_string(), counted).unwrap();
+            // 読み筋も一緒に変換される
+            for (c, _, pv) in children.iter() {
+                if pv.is_empty() {continue;}
+                assert!(c.genmove().unwrap().iter().any(|&m| tostr(m) == pv[0]));
+            }
+            let mut children = children.iter()
+                .map(|(c, s, _)| format!("{c},{s}")).collect::<Vec<_>>();
+            let mut expected = engine(&b.to_string()).unwrap()
+                .iter().map(|(c, s, _)| format!("{c},{s}")).collect::<Vec<_>>();
+            children.sort();
+            expected.sort();
+            assert_eq!(children, expected);
+        }
+        assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+        let lines = cache.all_children(&ban.to_string(), |rfen| {
+            Ok(engine(rfen)?.iter().map(|(c, s, _)| format!("{c},{s}")).collect())
+        }).unwrap();
+        assert_eq!(lines.len(), expected.len());
+        for t in Transform::all() {
+            let b = t.apply(&ban);
+            // 代表の局面で最初に見つかった手とその後の石の差
+            let (pos, score) = cache.run(&b.to_string(), |canon| {
+                let c = BitBoard::try_from(canon)?;
+                let mv = c.genmove().unwrap()[0];
+                Ok((tostr(mv), c.r#move(mv).unwrap().count().to_string()))
+            }).unwrap();
+            let mv = b.genmove().unwrap().into_iter()
+                .find(|&mv| tostr(mv) == pos).unwrap();
+            assert_eq!(b.r#move(mv).unwrap().count().to_string(), score);
+        }
+        assert_eq!(cache.len(), 3);
+    }
+    // 開き直しても覚えている。書きかけの行は無視する。
+    let path = dir.join(format!("{fp:016x}.txt"));
+    {
+        let mut f = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+        f.write_all(b"children\tbroken").unwrap();
+    }
+    let cache = ResultCache::open(&dir, fp).unwrap();
+    assert_eq!(cache.len(), 3);
+    drop(cache);
+    // 子供の区切りで切れた行も使わずに求め直す。開き直しても残らない。
+    let ban3 = BitBoard::new().r#move(bitboard::cell(5, 3)).unwrap();
+    let (_, t3) = ban3.canonical();
+    let full = engine(&t3.apply(&ban3).to_string()).unwrap();
+    assert!(full.len() > 1);
+    {
+        let (child, value, _) = &full[0];
+        let mut f = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+        f.write_all(format!("children\t{}\t{child},{value},;", t3.apply(&ban3)).as_bytes())
+            .unwrap();
+    }
+    let size = std::fs::metadata(&path).unwrap().len();
+    let cache = ResultCache::open(&dir, fp).unwrap();
+    assert_eq!(cache.len(), 3);
+    assert!(std::fs::metadata(&path).unwrap().len() < size);
+    drop(cache);
+    let cache = ResultCache::open(&dir, fp).unwrap();
+    assert_eq!(cache.len(), 3);
+    assert_eq!(cache.children(&ban3.to_string(), engine).unwrap().len(), full.len());
+    drop(cache);
+    let cache = ResultCache::open(&dir, fp).unwrap();
+    assert_eq!(cache.len(), 4);
+    let children = cache.children(&ban.to_string(),
+        |_| Err(String::from("should not be called"))).unwrap();
+    assert_eq!(children.len(), expected.len());
+    // 失敗は覚えない
+    let ban2 = BitBoard::new();
+    assert!(cache.children(&ban2.to_string(), |_| Err(String::from("failed"))).is_err());
+    assert_eq!(cache.len(), 4);
+    assert_eq!(cache.children(&ban2.to_string(), engine).unwrap().len(), 4);
+    drop(cache);
+    assert_eq!(ResultCache::open(&dir, fp).unwrap().len(), 5);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(transform_pos("PS", &Transform::IDENTITY).unwrap(), "ps");
+    assert!(transform_pos("z9", &Transform::IDENTITY).is_err());
+    assert!(transform_pos("a10", &Transform::IDENTITY).is_err());
+    let flip = Transform {sym : 0, colorflip : true};
+    assert_eq!(transform_score("0.00", &flip), "0.00");
+    assert_eq!(transform_score("-3", &flip), "3");
+    assert_eq!(transform_score("3", &flip), "-3");
+}
@@ -0,0 +1,121 @@
+//! 乱数で終局まで打ってみて局面の値を見積もる。
+//!
+//! 終盤から遠くて読み切れない局面の値の参考にする。値は全て黒から見た値。
+
+use crate::argument::RolloutPolicy;
+use crate::bitboard::{self, BitBoard};
+use rand::{Rng, SeedableRng};
+
+/// K局打った結果
+#[derive(Debug, PartialEq)]
+pub struct Stats {
+    /// 最終的な石の差の平均
+    pub mean : f32,
+    /// 最終的な石の差の分散
+    pub var : f32,
+    /// 黒が勝った割合
+    pub win : f32,
+    pub draw : f32,
+    /// 黒が負けた割合
+    pub loss : f32,
+}
+
+impl std::fmt::Display for Stats {
+    /// "mean,var,win,draw,loss"
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:.2},{:.2},{:.3},{:.3},{:.3}",
+            self.mean, self.var, self.win, self.draw, self.loss)
+    }
+}
+
+/// 手を1つ選ぶ。
+fn pick(ban : &BitBoard, moves : &[u8], policy : RolloutPolicy,
+        rng : &mut rand::rngs::StdRng) -> u8 {
+    match policy {
+        RolloutPolicy::Random => moves[rng.gen_range(0..moves.len())],
+        RolloutPolicy::Mobility => {
+            // 相手の打てる手が少なくなる手ほど選びやすくする
+            let weights = moves.iter().map(|&mv| {
+                let next = ban.r#move(mv).unwrap();
+                1.0 / (1 + next.mobility().count_ones()) as f32
+            }).collect::<Vec<_>>();
+            let mut r = rng.gen_range(0.0..weights.iter().sum::<f32>());
+            for (mv, w) in moves.iter().zip(weights.iter()) {
+                if r < *w {return *mv;}
+
+                r -= w;
+            }
+            *moves.last().unwrap()
+        },
+    }
+}
+
+/// 終局まで1局打つ。
+///
+/// # Returns
+/// 最終的な石の差
+pub fn playout(ban : &BitBoard, policy : RolloutPolicy,
+        rng : &mut rand::rngs::StdRng) -> i8 {
+    let mut ban = ban.clone();
+    while let Some(moves) = ban.genmove() {
+        let mv = if moves[0] == bitboard::PASS {
+            // 両者パスなら終局
+            if ban.r#move(bitboard::PASS).unwrap().mobility() == 0 {break;}
+
+            bitboard::PASS
+        } else {
+            pick(&ban, &moves, policy, rng)
+        };
+        ban = ban.r#move(mv).unwrap();
+    }
+    ban.count()
+}
+
+/// k局打って集計する。
+///
+/// # Arguments
+/// - ban 局面
+/// - k 打つ数
+/// - seed 乱数の種。同じ種なら同じ結果になる。
+/// - policy 手の選び方
+pub fn rollout(ban : &BitBoard, k : usize, seed : u64, policy : RolloutPolicy) -> Stats {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    let scores = (0..k).map(|_| playout(ban, policy, &mut rng) as f32)
+        .collect::<Vec<_>>();
+    let n = scores.len().max(1) as f32;
+    let mean = scores.iter().sum::<f32>() / n;
+    let var = scores.iter().map(|s| (s - mean) * (s - mean)).sum::<f32>() / n;
+    let ratio = |f : fn(&f32) -> bool| scores.iter().filter(|s| f(s)).count() as f32 / n;
+    Stats {
+        mean, var,
+        win : ratio(|s| *s > 0.0),
+        draw : ratio(|s| *s == 0.0),
+        loss : ratio(|s| *s < 0.0),
+    }
+}
+
+#[test]
+fn test_rollout() {
+    // 終局している局面は石の差そのまま
+    let ban = BitBoard::try_from("H/H/H/H/H/H/H/G1 b").unwrap();
+    let stats = rollout(&ban, 10, 0, RolloutPolicy::Random);
+    assert_eq!(stats, Stats {mean : 63.0, var : 0.0, win : 1.0, draw : 0.0, loss : 0.0});
+    assert_eq!(stats.to_string(), "63.00,0.00,1.000,0.000,0.000");
+
+    // 残り1マスなら読み切りと同じ
+    let ban = BitBoard::try_from("dD/AdC/BcC/BaAbAa/Af1/AaAaAbA/BcC/H b").unwrap();
+    let stats = rollout(&ban, 5, 0, RolloutPolicy::Mobility);
+    assert_eq!(stats.mean, crate::solver::solve(&ban) as f32);
+    assert_eq!(stats.var, 0.0);
+
+    for policy in [RolloutPolicy::Random, RolloutPolicy::Mobility] {
+        let ban = BitBoard::new();
+        let stats = rollout(&ban, 50, 1, policy);
+        assert!((-64.0..=64.0).contains(&stats.mean));
+        assert!(stats.var > 0.0);
+        assert!((stats.win + stats.draw + stats.loss - 1.0).abs() < 1e-5);
+        // 同じ種なら同じ結果
+        assert_eq!(rollout(&ban, 50, 1, policy), stats);
+        assert_ne!(rollout(&ban, 50, 2, policy), stats);
+    }
+}
@@ -0,0 +1,203 @@
+//! ruversiの標準出力を読む。
+//!
+//! 出力の例は tests/fixtures/ruversi/ にある。
+//! 分からない行があったら読み飛ばさずにエラーにする。
+
+/// 出力の種類
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variant {
+    /// 盤面を表示してから結果を出力する。
+    Board,
+    /// 盤面を表示しないで結果だけ出力する。
+    Silent,
+}
+
+/// "val:-0.3012 2185 nodes. D3c5D6e3F4c6F5 7msec"
+#[derive(Clone, Debug, PartialEq)]
+pub struct Summary {
+    /// 評価値。ruversiが出力したままの文字列。
+    pub value : String,
+    pub nodes : u64,
+    /// 読み筋。"d3"や"ps"の形式。
+    pub pv : Vec<String>,
+    pub msec : u64,
+}
+
+impl Summary {
+    /// 最善手。読み筋の最初の手。
+    pub fn best(&self) -> &str {
+        &self.pv[0]
+    }
+}
+
+/// "val,-1.62,8/8/3A4/3B3/3Aa3/8/8/8 w,1769 nodes. c3C4c5B4d2C2a3"
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChildValue {
+    pub value : String,
+    pub rfen : String,
+    pub nodes : u64,
+    pub pv : Vec<String>,
+}
+
+/// "8/8/8/2C3/3Aa3/8/8/8 w,50"
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChildScore {
+    pub rfen : String,
+    pub score : String,
+}
+
+/// ruversiの出力を読んだ結果
+#[derive(Clone, Debug, PartialEq)]
+pub struct Output {
+    pub variant : Variant,
+    pub summary : Summary,
+    /// `--children`の時の子供の局面の評価値
+    pub children : Vec<ChildValue>,
+    /// `--children`の時の子供の局面の最終結果
+    pub scores : Vec<ChildScore>,
+}
+
+/// 読み筋を2文字ずつに分ける。
+fn parse_pv(txt : &str) -> Option<Vec<String>> {
+    if !txt.len().is_multiple_of(2) || !txt.is_ascii() {return None;}
+
+    let ptn = regex::Regex::new("^([a-h][1-8]|ps)$").unwrap();
+    (0..txt.len()).step_by(2).map(|i| {
+        let mv = txt[i..i + 2].to_ascii_lowercase();
+        if ptn.is_match(&mv) {Some(mv)} else {None}
+    }).collect()
+}
+
+struct Patterns {
+    obf : regex::Regex,
+    header : regex::Regex,
+    row : regex::Regex,
+    separator : regex::Regex,
+    turn : regex::Regex,
+    summary : regex::Regex,
+    child : regex::Regex,
+    score : regex::Regex,
+}
+
+impl Patterns {
+    fn new() -> Patterns {
+        let rfen = "([0-8A-Ha-h/]+ [bw])";
+        let val = "(-?\\d+(?:\\.\\d+)?)";
+        Patterns {
+            obf : regex::Regex::new("^[-XOxo*]{64}\\s+[XOxo]$").unwrap(),
+            header : regex::Regex::new("^\\s+\\|a\\s*\\|b").unwrap(),
+            row : regex::Regex::new("^[1-8] \\|(?:[^|]{2}\\|){8}$").unwrap(),
+            separator : regex::Regex::new("^\\s+\\+(?:--\\+){8}$").unwrap(),
+            turn : regex::Regex::new("^(?:@@|\\[\\])'s turn\\.$").unwrap(),
+            summary : regex::Regex::new(&format!(
+                "^val:{val} (\\d+) nodes\\. (\\S+) (\\d+)msec$")).unwrap(),
+            child : regex::Regex::new(&format!(
+                "^val,{val},{rfen},(\\d+) nodes\\. ?(\\S*)$")).unwrap(),
+            score : regex::Regex::new(&format!("^{rfen},{val}$")).unwrap(),
+        }
+    }
+}
+
+/// ruversiの標準出力を読む。
+///
+/// # Returns
+/// 読めない行や結果の行が無い時はErr。
+pub fn parse(txt : &str) -> Result<Output, String> {
+    let ptn = Patterns::new();
+    let mut nboard = 0;
+    let mut summary = None;
+    let mut children = Vec::new();
+    let mut scores = Vec::new();
+    for (i, line) in txt.lines().enumerate() {
+        let lineno = i + 1;
+        let line = line.trim_end();
+        let err = |msg : &str| format!("line {lineno}: {msg} \"{line}\"");
+        if line.is_empty() {continue;}
+
+        if ptn.obf.is_match(line) || ptn.header.is_match(line)
+                || ptn.row.is_match(line) || ptn.separator.is_match(line)
+                || ptn.turn.is_match(line) {
+            if summary.is_some() || !scores.is_empty() {
+                return Err(err("board after results"));
+            }
+            nboard += 1;
+        } else if let Some(cap) = ptn.summary.captures(line) {
+            if summary.is_some() {return Err(err("second summary"));}
+
+            let pv = parse_pv(&cap[3]).ok_or_else(|| err("invalid moves"))?;
+            summary = Some(Summary {
+                value : cap[1].to_string(),
+                nodes : cap[2].parse().map_err(|_| err("invalid nodes"))?,
+                pv,
+                msec : cap[4].parse().map_err(|_| err("invalid time"))?,
+            });
+        } else if let Some(cap) = ptn.child.captures(line) {
+            if summary.is_none() {return Err(err("child before summary"));}
+
+            children.push(ChildValue {
+                value : cap[1].to_string(),
+                rfen : cap[2].to_string(),
+                nodes : cap[3].parse().map_err(|_| err("invalid nodes"))?,
+                pv : parse_pv(&cap[4]).ok_or_else(|| err("invalid moves"))?,
+            });
+        } else if let Some(cap) = ptn.score.captures(line) {
+            if summary.is_some() {return Err(err("score after summary"));}
+
+            scores.push(ChildScore {rfen : cap[1].to_string(), score : cap[2].to_string()});
+        } else {
+            return Err(err("can not understand"));
+        }
+    }
+
+    let Some(summary) = summary else {
+        return Err(String::from("no result in the output"));
+    };
+    let variant = match nboard {
+        0 => Variant::Silent,
+        12 => Variant::Board,
+        n => {return Err(format!("unknown board printout of {n} lines"));},
+    };
+    Ok(Output {variant, summary, children, scores})
+}
+
+#[test]
+fn test_parse_golden() {
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/ruversi");
+    let mut names = std::fs::read_dir(&dir).unwrap().filter_map(|e| {
+        let path = e.unwrap().path();
+        if path.extension()? != "txt" {return None;}
+        Some(path)
+    }).collect::<Vec<_>>();
+    names.sort();
+    assert!(names.len() >= 5);
+    for path in names {
+        let txt = std::fs::read_to_string(&path).unwrap();
+        let actual = format!("{:#?}\n", parse(&txt));
+        let golden = path.with_extension("parsed");
+        if std::env::var_os("INCUVERSI_BLESS").is_some() {
+            std::fs::write(&golden, &actual).unwrap();
+            continue;
+        }
+        let expected = std::fs::read_to_string(&golden)
+            .unwrap_or_else(|e| panic!("{e} : {golden:?}"));
+        assert_eq!(actual, expected, "{path:?}");
+    }
+}
+
+#[test]
+fn test_parse() {
+    let out = parse("val:3.0000 4 nodes. d6 0msec\n").unwrap();
+    assert_eq!(out.variant, Variant::Silent);
+    assert_eq!(out.summary.best(), "d6");
+    assert!(out.children.is_empty());
+    assert!(parse("").is_err());
+    assert!(parse("val:3.0000 4 nodes. d9 0msec\n").is_err());
+    assert!(parse("val:3.0000 4 nodes. d6 0msec\nval:3.0000 4 nodes. d6 0msec\n").is_err());
+    assert!(parse("val,6.00,8/8/8/8/8/8/8/8 b,1 nodes. e7\nval:3.0000 4 nodes. d6 0msec\n")
+        .is_err());
+    assert!(parse("  +--+--+--+--+--+--+--+--+\nval:3.0000 4 nodes. d6 0msec\n").is_err());
+    assert_eq!(parse_pv("D3c5PS"),
+        Some(vec![String::from("d3"), String::from("c5"), String::from("ps")]));
+    assert_eq!(parse_pv("d3c"), None);
+}
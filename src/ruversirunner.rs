@@ -1,10 +1,18 @@
-use std::io::{BufReader, BufRead, Write};
+use std::io::{BufRead, BufReader, Read};
 use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use rayon::prelude::*;
 
+use crate::argument::Rounding;
 use crate::bitboard;
+use crate::engineconfig::{self, EngineConfig};
+use crate::resultcache::{self, ResultCache};
+use crate::ruversioutput;
 
 /**
  * configファイルのargsタグを処理する。
+ * カンマで区切る。引用符の中のカンマは区切らない。
+ * 各引数は`engineconfig::parse_value()`で引用符を外して`~`や`$VAR`を展開する。
  * # Argumemts  
  * - txt args:を含まない"arg:"の後ろに書いてある文字列。
  * # Returns  
@@ -13,8 +21,8 @@ use crate::bitboard;
  * - Err(String>)  
  *   処理エラーの内容
  */
-fn parse_args_tag(txt : &str) -> Result<Vec<String>, String> {
-    let args = txt.trim().split(",")
+pub fn parse_args_tag(txt : &str) -> Result<Vec<String>, String> {
+    let args = engineconfig::split_commas(txt.trim())?.iter()
         .map(|s| s.trim().to_string()).collect::<Vec<_>>();
     if args.len() > 1 || !args[0].is_empty() {
         for (i, a) in args.iter().enumerate() {
@@ -23,12 +31,117 @@ fn parse_args_tag(txt : &str) -> Result<Vec<String>, String> {
                     format!("\"{txt}\" contains empty part @{i}! {args:?}"));
             }
         }
-        Ok(args)
+        args.iter().map(|a| engineconfig::parse_value(a)).collect()
     } else  {
         Ok(Vec::new())
     }
 }
 
+/// 子プロセスの終了を待って標準出力と標準エラー出力を返す。
+///
+/// # Arguments
+/// - child stdoutとstderrをpipeにしたプロセス
+/// - timeout これを過ぎたらプロセスを止めてErrを返す。Noneなら待ち続ける。
+///
+/// # Returns
+/// Ok((stdout, stderr)) or Err(String)。
+/// 途中まで出力していても、正常に終了しなかった時(シグナルで死んだ時も)はErr。
+pub fn wait_output(child : Child, timeout : Option<Duration>)
+        -> Result<(String, String), String> {
+    wait_output_lines(child, timeout, |_| false)
+}
+
+/// `wait_output()`と同じだが、標準出力を1行読む毎に`on_line`に渡す。
+///
+/// `on_line`がtrueを返したら1局面読み終わったとみなしてtimeoutを数え直す。
+/// 何局面も続けて読むプロセスではtimeoutが1局面毎の制限時間になる。
+pub fn wait_output_lines(mut child : Child, timeout : Option<Duration>,
+        mut on_line : impl FnMut(&str) -> bool) -> Result<(String, String), String> {
+    fn read_thread(pipe : Option<impl Read + Send + 'static>)
+            -> std::thread::JoinHandle<String> {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            String::from_utf8_lossy(&buf).to_string()
+        })
+    }
+    let (tx, rx) = std::sync::mpsc::channel::<String>();
+    let pipe = child.stdout.take();
+    let stdout_thread = std::thread::spawn(move || {
+        let Some(pipe) = pipe else {return;};
+        let mut reader = BufReader::new(pipe);
+        let mut buf = Vec::new();
+        while reader.read_until(b'\n', &mut buf).is_ok_and(|n| n > 0) {
+            if tx.send(String::from_utf8_lossy(&buf).to_string()).is_err() {return;}
+            buf.clear();
+        }
+    });
+    let stderr = read_thread(child.stderr.take());
+    let mut stdout = String::new();
+    let mut start = Instant::now();
+    let mut take_lines = |stdout : &mut String, start : &mut Instant| {
+        for line in rx.try_iter() {
+            if on_line(line.trim_end_matches(['\n', '\r'])) {*start = Instant::now();}
+            *stdout += &line;
+        }
+    };
+
+    let mut timedout = false;
+    let mut status = None;
+    loop {
+        take_lines(&mut stdout, &mut start);
+        match child.try_wait() {
+            Ok(Some(st)) => {
+                status = Some(st);
+                break;
+            },
+            Ok(None) => {},
+            Err(e) => {return Err(format!("{e}"));},
+        }
+        if timeout.is_some_and(|t| start.elapsed() > t) {
+            let _ = child.kill();
+            let _ = child.wait();
+            timedout = true;
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    if timedout {
+        // 孫プロセスがpipeを開いたままだと読み終わらないので少しだけ待つ
+        let start = Instant::now();
+        while !stderr.is_finished() && start.elapsed() < Duration::from_millis(100) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let msg = format!("timeout after {:?}.", timeout.unwrap());
+        return Err(if stderr.is_finished() {
+            with_stderr(msg, &stderr.join().unwrap_or_default())
+        } else {
+            msg
+        });
+    }
+    let _ = stdout_thread.join();
+    take_lines(&mut stdout, &mut start);
+    let stderr = stderr.join().unwrap_or_default();
+    if let Some(status) = status.filter(|st| !st.success()) {
+        return Err(with_stderr(format!("engine failed, {status}."), &stderr));
+    }
+    Ok((stdout, stderr))
+}
+
+/// エラーメッセージに標準エラー出力を付ける。
+pub fn with_stderr(msg : String, stderr : &str) -> String {
+    if stderr.trim().is_empty() {
+        msg
+    } else {
+        format!("{msg} stderr:\"{}\"", stderr.trim())
+    }
+}
+
+/// 子供の局面, 評価値, 子供の局面からの読み筋("d3"や"ps")
+pub type ChildPv = (bitboard::BitBoard, f32, Vec<String>);
+
 /// run ruversi
 pub struct RuversiRunner {
     curdir : String,
@@ -36,6 +149,10 @@ pub struct RuversiRunner {
     evfile : String,
     verbose : bool,
     args : Vec<String>,
+    depth : Option<u32>,
+    timeout : Option<Duration>,
+    rounding : Rounding,
+    cache : Option<ResultCache>,
 }
 
 impl std::fmt::Display for RuversiRunner {
@@ -53,6 +170,10 @@ impl RuversiRunner {
             evfile : String::from("data/evaltable.txt"),
             verbose : false,
             args : vec!["--thinkall".to_string()],
+            depth : None,
+            timeout : None,
+            rounding : Rounding::Truncate,
+            cache : None,
         }
     }
 
@@ -60,7 +181,21 @@ impl RuversiRunner {
         self.verbose = verbose;
     }
 
-    pub fn from_config(path : &std::path::PathBuf)
+    pub fn set_timeout(&mut self, timeout : Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub fn set_rounding(&mut self, rounding : Rounding) {
+        self.rounding = rounding;
+    }
+
+    /// 評価値を石の差にする。
+    pub fn label(&self, value : f32) -> i8 {
+        self.rounding.apply(value)
+    }
+
+    #[allow(dead_code)]
+    pub fn from_config(path : &std::path::Path)
             -> Result<RuversiRunner, String> {
         let mut rr = RuversiRunner::new();
         if path.as_os_str().is_empty() {
@@ -79,56 +214,80 @@ impl RuversiRunner {
     /// curdir: ~/ruversi/
     /// path: ./bin/ruversi
     /// evfile: ./data/eval.dat
-    /// args: --depth,7,--silent
+    /// args: --silent,"--name=a,b"
+    /// cache: ./cache
     /// 
-    /// note:
-    /// COMMAs between `"`s are not be ignored yet...
-    /// "a,b" will be `"a` and `b"`...
-    pub fn read(&mut self, path : &std::path::PathBuf) -> Result<(), String> {
-        let file = std::fs::File::open(path);
-        if file.is_err() {return Err(file.err().unwrap().to_string());}
-
-        let file = file.unwrap();
-        let lines = BufReader::new(file);
-        for line in lines.lines() {
-            match line {
-                Ok(l) => {
-                    if let Some(cd) = l.strip_prefix("curdir:") {
-                        self.curdir = String::from(cd.trim());
-                    } else if let Some(ed) = l.strip_prefix("path:") {
-                        self.path = String::from(ed.trim());
-                    } else if let Some(evf) = l.strip_prefix("evfile:") {
-                        self.evfile = String::from(evf.trim());
-                    } else if let Some(args_txt) = l.strip_prefix("args:") {
-                        self.args = parse_args_tag(args_txt)?;
-                    }
-                },
-                Err(err) => {return Err(err.to_string())}
-            }
+    /// cacheを書くとエンジンの結果をそのディレクトリに覚えておく。
+    /// 書式は`EngineConfig`を参照。
+    #[allow(dead_code)]
+    pub fn read(&mut self, path : &std::path::Path) -> Result<(), String> {
+        self.configure(&EngineConfig::load(path, None)?)
+    }
+
+    /// 設定ファイルの内容を反映する。書いていない項目はそのまま。
+    pub fn configure(&mut self, cfg : &EngineConfig) -> Result<(), String> {
+        if let Some(cd) = &cfg.curdir {self.curdir = cd.clone();}
+        if let Some(path) = &cfg.path {self.path = path.clone();}
+        if let Some(evf) = &cfg.evfile {self.evfile = evf.clone();}
+        if let Some(args) = &cfg.args {self.args = args.clone();}
+        if cfg.depth.is_some() {self.depth = cfg.depth;}
+        if cfg.timeout.is_some() {self.timeout = cfg.timeout;}
+        if let Some(dir) = &cfg.cache {
+            self.cache = Some(ResultCache::open(
+                std::path::Path::new(dir), self.fingerprint())?);
         }
         Ok(())
     }
 
+    /// 結果が変わる設定の指紋。pathやevfileはcurdirからの相対パスなのでcurdirも入れる。
+    fn fingerprint(&self) -> u64 {
+        let depth = self.depth.map(|d| d.to_string()).unwrap_or_default();
+        resultcache::fingerprint(&[&self.curdir,
+            &self.program().to_string_lossy(), &self.evfile, &self.args.join(","), &depth])
+    }
+
+    /// 実行ファイルのパス。
+    /// 相対パスはcurdirからの相対パスとして扱う。名前だけならPATHから探す。
+    fn program(&self) -> std::path::PathBuf {
+        let path = std::path::Path::new(&self.path);
+        if path.is_relative() && path.components().count() > 1 {
+            std::path::Path::new(&self.curdir).join(path)
+        } else {
+            path.to_path_buf()
+        }
+    }
+
     #[allow(dead_code)]
     fn spawn(&self, rfen : &str) -> std::io::Result<Child> {
-        std::env::set_current_dir(&self.curdir).unwrap();
-        let mut cmd = Command::new(&self.path);
-        cmd.arg("--rfen").arg(rfen).arg("--ev1").arg(&self.evfile)
-            .args(&self.args)
+        let mut cmd = Command::new(self.program());
+        cmd.current_dir(&self.curdir)
+            .arg("--rfen").arg(rfen).arg("--ev1").arg(&self.evfile);
+        if let Some(depth) = self.depth {
+            cmd.arg("--depth").arg(format!("{depth}"));
+        }
+        cmd.args(&self.args)
             .stdout(Stdio::piped())
-            .stderr(Stdio::null());
+            .stderr(Stdio::piped());
         if self.verbose {eprintln!("cmd:{cmd:?}");}
         cmd.spawn()
     }
 
-    fn spawn_children(&self, rfen : &str, depth : u32) -> std::io::Result<Child> {
-        std::env::set_current_dir(&self.curdir).unwrap();
-        let mut cmd = Command::new(&self.path);
-        cmd.arg("--rfen").arg(rfen).arg("--ev1").arg(&self.evfile)
+    /// 子供の局面を読み切るコマンド。
+    ///
+    /// 読み切りなので設定ファイルのdepthは使わない。depthは`spawn()`だけに効く。
+    fn children_command(&self, rfen : &str, depth : u32) -> Command {
+        let mut cmd = Command::new(self.program());
+        cmd.current_dir(&self.curdir)
+            .arg("--rfen").arg(rfen).arg("--ev1").arg(&self.evfile)
             .arg("--children").args(vec!["--depth".to_string(), format!("{depth}")])
             .args(&self.args)
             .stdout(Stdio::piped())
-            .stderr(Stdio::null());
+            .stderr(Stdio::piped());
+        cmd
+    }
+
+    fn spawn_children(&self, rfen : &str, depth : u32) -> std::io::Result<Child> {
+        let mut cmd = self.children_command(rfen, depth);
         if self.verbose {eprintln!("cmd:{cmd:?}");}
         cmd.spawn()
     }
@@ -137,49 +296,29 @@ impl RuversiRunner {
     /// Ok((rfen, score)) or Err(String)
     #[allow(dead_code)]
     pub fn run(&self, rfen : &str) -> Result<(String, String), String> {
-        // launch another
-        let curdir = std::env::current_dir().unwrap();
-        let cmd = match self.spawn(rfen) {
-            Err(msg) => {
-                std::env::set_current_dir(curdir).unwrap();
-                return Err(format!(
-                    "error running ruversi... [{msg}], config:[{self}]"))
-            },
-            Ok(prcs) => prcs,
-        };
-        // read stdout and get moves
-        let w = cmd.wait_with_output().unwrap();
-        std::env::set_current_dir(curdir).unwrap();
-        let txt = String::from_utf8(w.stdout).unwrap();
-        // println!("txt:{txt}");
-        let lines : Vec<_> = txt.split("\n").collect();
-        if lines.len() < 13 {
-            return Err(format!("invalid input {lines:?}"));
+        match &self.cache {
+            Some(cache) => cache.run(rfen, |canon| self.run_uncached(canon)),
+            None => self.run_uncached(rfen),
         }
+    }
 
-        let res = lines[12].to_ascii_lowercase();
-        if self.verbose {println!("opp:{}", &res);}
-        let posptn = regex::Regex::new("nodes\\. ([A-Ha-h][1-8]|[pP][sS])").unwrap();
-        let xtxt = match posptn.captures(&res) {
-            Some(cap) => {
-                String::from(&cap[1].to_lowercase())
-            },
-            _ => {
-                return Err(
-                    format!("invalid input from ruversi. \"{}\"", &res));
-            }
-        };
+    fn run_uncached(&self, rfen : &str) -> Result<(String, String), String> {
+        let out = self.execute(self.spawn(rfen))?;
+        if self.verbose {println!("opp:{:?}", out.summary);}
+        Ok((out.summary.best().to_string(), out.summary.value))
+    }
 
-        let scoreptn = regex::Regex::new("val:(-?\\d+\\.\\d+) ").unwrap();
-        match scoreptn.captures(&res) {
-            Some(cap) => {
-                Ok((xtxt, String::from(&cap[1])))
-            },
-            _ => {
-                Err(format!("invalid input from ruversi. \"{}\" pos{xtxt}",
-                    lines[2]))
-            }
-        }
+    /// ruversiの終了を待って出力を読む。
+    /// 読めなかった時はエラーにruversiの標準エラー出力を付ける。
+    fn execute(&self, cmd : std::io::Result<Child>)
+            -> Result<ruversioutput::Output, String> {
+        let cmd = cmd.map_err(
+            |msg| format!("error running ruversi... [{msg}], config:[{self}]"))?;
+        let (txt, stderr) = wait_output(cmd, self.timeout)
+            .map_err(|e| format!("{e}, config:[{self}]"))?;
+        if self.verbose {println!("txt:{txt}");}
+        ruversioutput::parse(&txt).map_err(|e| with_stderr(
+            format!("invalid output from ruversi. {e}, config:[{self}]"), &stderr))
     }
 
     /// `--rfen`と`--children`をつけて実行。
@@ -189,103 +328,105 @@ impl RuversiRunner {
     /// - rfen 開始局面。この局面の子供の局面の情報が返る。
     /// 
     /// # Returns
-    /// 指定した局面の子供の局面の情報(Bitboardと最終結果)が返る。
+    /// 指定した局面の子供の局面の情報(Bitboard, 石の差, 評価値)が返る。
+    /// 石の差は評価値を`set_rounding()`の方法で整数にしたもの。
     /// ダブり解消の処理のために確定石の欄にゼロを入れている。
     pub fn run_children(&self, rfen : &str)
-            -> Result<Vec<(bitboard::BitBoard, i8, i8, i8)>, String> {
-        let depth = bitboard::count_empty_cells(rfen)? * 2;  // PASSが入って2倍に伸びても大丈夫
-        // launch ruversi
-        let curdir = std::env::current_dir().unwrap();
-        let cmd = match self.spawn_children(rfen, depth as u32) {
-            Err(msg) => {
-                std::env::set_current_dir(curdir).unwrap();
-                return Err(format!(
-                    "error running ruversi... [{msg}], config:[{self}]"))
-            },
-            Ok(prcs) => prcs,
-        };
-        // read stdout and get moves
-        let w = cmd.wait_with_output().unwrap();
-        std::env::set_current_dir(curdir).unwrap();
-        let txt = String::from_utf8(w.stdout).unwrap();
-        if self.verbose {println!("txt:{txt}");}
-        let lines : Vec<_> = txt.split("\n").collect();
-        if lines.len() < 10 {
-            return Err(format!("invalid input {lines:?}"));
-        }
+            -> Result<Vec<bitboard::Sample>, String> {
+        Ok(self.run_children_pv(rfen)?.into_iter().map(|(child, value, _)| {
+            (child, 0, 0, self.label(value), value)
+        }).collect())
+    }
 
-        // "val,{val:.2},{newban},{node}";
-        // ex.
-        // |__|__|__|__|__|__|__|__|  <-- 不要
-        // @@'s turn.  <-- 不要
-        // val:-0.3012 2185 nodes. D3c5D6e3F4c6F5 7msec  <-- 不要
-        // val,-1.62,8/8/3A4/3B3/3Aa3/8/8/8 w,1769 nodes. c3C4c5B4d2C2a3
-        // val,-1.62,8/8/8/2C3/3Aa3/8/8/8 w,507 nodes. c3D3
-        // val,-1.50,8/8/8/3aA3/3C2/8/8/8 w,2357 nodes. d6C4f4C5f6G5d3
-        // val,-1.13,8/8/8/3aA3/3B3/4A3/8/8 w,1262 nodes. f4D3e7F3e3F6d6
-        let scoreptn = regex::Regex::new("val,([-0-9.]+),([^ ]+ [bw])").unwrap();
-        let ret = lines.iter().filter_map(|line| {
-            match scoreptn.captures(line) {
-                Some(cap) => {
-                    // cap[1] : val, cap[2] : rfen
-                    Some((
-                        bitboard::BitBoard::try_from(&cap[2]).ok()?,
-                        0, 0,
-                        cap[1].parse::<f32>().ok()? as i8
-                    ))
-                },
-                _ => {None}
-            }
-        }).collect::<Vec<_>>();
+    /// `run_children()`と同じだが子供の局面からの読み筋も返す。
+    pub fn run_children_pv(&self, rfen : &str) -> Result<Vec<ChildPv>, String> {
+        match &self.cache {
+            Some(cache) => cache.children(rfen, |canon| self.run_children_uncached(canon)),
+            None => self.run_children_uncached(rfen),
+        }
+    }
 
-        Ok(ret)
+    fn run_children_uncached(&self, rfen : &str) -> Result<Vec<ChildPv>, String> {
+        let depth = bitboard::count_empty_cells(rfen)? * 2;  // PASSが入って2倍に伸びても大丈夫
+        let out = self.execute(self.spawn_children(rfen, depth as u32))?;
+        out.children.into_iter().map(|child| {
+            let value = child.value.parse::<f32>()
+                .map_err(|e| format!("invalid value \"{}\" : {e}", child.value))?;
+            Ok((bitboard::BitBoard::try_from(child.rfen.as_str())?, value, child.pv))
+        }).collect()
     }
 
+    /// `--rfen`と`--children`をつけて実行。
+    /// 
+    /// # Returns
+    /// 子供の局面の"rfen,score"
+    #[allow(dead_code)]
     pub fn run_all_children(&self, rfen : &str)
                 -> Result<Vec<String>, String> {
-        let depth = bitboard::count_empty_cells(rfen)? * 2;  // PASSが入って2倍に伸びても大丈夫
-        // launch ruversi
-        let curdir = std::env::current_dir().unwrap();
-        let cmd = match self.spawn_children(rfen, depth as u32) {
-            Err(msg) => {
-                std::env::set_current_dir(curdir).unwrap();
-                return Err(format!(
-                    "error running ruversi... [{msg}], config:[{self}]"))
-            },
-            Ok(prcs) => prcs,
-        };
-        // read stdout and get moves
-        let w = cmd.wait_with_output().unwrap();
-        std::env::set_current_dir(curdir).unwrap();
-        let txt = String::from_utf8(w.stdout).unwrap();
-        if self.verbose {println!("txt:{txt}");}
-        let lines : Vec<_> = txt.split("\n").collect();
-        if lines.len() < 10 {
-            return Err(format!("invalid input {lines:?}"));
+        match &self.cache {
+            Some(cache) => cache.all_children(rfen, |canon| self.run_all_children_uncached(canon)),
+            None => self.run_all_children_uncached(rfen),
         }
+    }
 
-        // "val,{val:.2},{newban},{node}";
-        // ex.
-        // |__|__|__|__|__|__|__|__|  <-- 不要
-        // @@'s turn.  <-- 不要
-        // 8/8/8/2C3/3Aa3/8/8/8 w,50
-        // 8/8/3A4/3B3/3Aa3/8/8/8 w,17
-        // 8/8/8/3aA3/3C2/8/8/8 w,23
-        // 8/8/8/3aA3/3B3/4A3/8/8 w,12
-        // val:-0.3012 2185 nodes. D3c5D6e3F4c6F5 7msec  <-- 不要
-        // val,-1.62,8/8/3A4/3B3/3Aa3/8/8/8 w,1769 nodes. c3C4c5B4d2C2a3  <-- 不要
-        // val,-1.62,8/8/8/2C3/3Aa3/8/8/8 w,507 nodes. c3D3  <-- 不要
-        // val,-1.50,8/8/8/3aA3/3C2/8/8/8 w,2357 nodes. d6C4f4C5f6G5d3  <-- 不要
-        // val,-1.13,8/8/8/3aA3/3B3/4A3/8/8 w,1262 nodes. f4D3e7F3e3F6d6  <-- 不要
-        let scoreptn = regex::Regex::new("^(([0-8A-Ha-h\\/]+ [bw]),[-0-9.]+)").unwrap();
-        let ret = lines.iter().filter_map(|line| {
-            scoreptn.captures(line).map(|cap| cap[0].to_string())
-        }).collect::<Vec<_>>();
-
-        Ok(ret)
+    fn run_all_children_uncached(&self, rfen : &str)
+                -> Result<Vec<String>, String> {
+        let depth = bitboard::count_empty_cells(rfen)? * 2;  // PASSが入って2倍に伸びても大丈夫
+        let out = self.execute(self.spawn_children(rfen, depth as u32))?;
+        Ok(out.scores.iter().map(|s| format!("{},{}", s.rfen, s.score)).collect())
     }
 }
 
+/// エンジンの起動を最大jobs個までに抑えて並列に走らせる。
+///
+/// ruversiは1局面を読むと終了するので`map()`や`stream()`で局面毎に起動する。
+/// Edaxのように1つのプロセスで何局面も読めるエンジンは`split()`で局面をjobs個に分け、
+/// 1つのワーカーが1つのプロセスを起動したまま分けた局面を全部読ませる。
+pub struct WorkerPool {
+    pool : rayon::ThreadPool,
+}
+
+impl WorkerPool {
+    pub fn new(jobs : usize) -> Result<WorkerPool, String> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs.max(1))
+            .thread_name(|i| format!("engine{i}"))
+            .build().map_err(|e| format!("{e}"))?;
+        Ok(WorkerPool {pool})
+    }
+
+    /// itemsを並列に処理して結果を返す。結果の順番はitemsと同じ。
+    pub fn map<T, R, F>(&self, items : &[T], f : F) -> Vec<R>
+            where T : Sync, R : Send, F : Fn(&T) -> R + Sync {
+        self.pool.install(|| items.par_iter().map(&f).collect())
+    }
+
+    /// itemsを最大jobs個のひと続きに分けて並列に処理する。
+    ///
+    /// fは(分けた最初の要素の番号, 分けた要素)を受け取る。結果の順番は分けた順。
+    pub fn split<T, R, F>(&self, items : &[T], f : F) -> Vec<R>
+            where T : Sync, R : Send, F : Fn(usize, &[T]) -> R + Sync {
+        let size = items.len().div_ceil(self.pool.current_num_threads()).max(1);
+        let chunks = items.chunks(size).enumerate()
+            .map(|(i, c)| (i * size, c)).collect::<Vec<_>>();
+        self.pool.install(|| chunks.par_iter().map(|(offset, c)| f(*offset, c)).collect())
+    }
+
+    /// itemsを並列に処理して、終わった順に結果をtxに送る。Noneは送らない。
+    pub fn stream<T, R, F>(&self, items : &[T], f : F,
+            tx : &std::sync::mpsc::Sender<R>)
+            where T : Sync, R : Send, F : Fn(&T) -> Option<R> + Sync {
+        self.pool.install(|| {
+            items.par_iter().for_each_with(tx.clone(), |tx, item| {
+                if let Some(res) = f(item) {tx.send(res).unwrap();}
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+use std::io::Write;
+
 #[test]
 fn test_ruversirunner_default_values() {
     // RuversiRunner::new() で各フィールドがデフォルト値になっていることを確認
@@ -369,7 +510,8 @@ args:a,b,c,
     }
     let mut rr = RuversiRunner::new();
     let result = rr.read(&config_path);
-    assert_eq!(result, Err("\"a,b,c,\" contains empty part @3! [\"a\", \"b\", \"c\", \"\"]".to_string()));
+    assert_eq!(result, Err(format!("{}:4: \"a,b,c,\" contains empty part @3! [\"a\", \"b\", \"c\", \"\"]",
+        config_path.display())));
     std::fs::remove_file(config_path).unwrap();
 }
 
@@ -401,7 +543,8 @@ args:a,b,,c
     }
     let mut er = RuversiRunner::new();
     let result = er.read(&config_path);
-    assert_eq!(result, Err("\"a,b,,c\" contains empty part @2! [\"a\", \"b\", \"\", \"c\"]".to_string()));
+    assert_eq!(result, Err(format!("{}:4: \"a,b,,c\" contains empty part @2! [\"a\", \"b\", \"\", \"c\"]",
+        config_path.display())));
     std::fs::remove_file(config_path).unwrap();
 }
 
@@ -510,3 +653,121 @@ fn test_ruversirunner_from_config_file() {
     assert_eq!(rr.curdir, "/tmp/abc");
     std::fs::remove_file(config_path).unwrap();
 }
+
+#[test]
+fn test_ruversirunner_configure() {
+    // 引用符の中のカンマで区切らない。depthやcurdirで指紋が変わる。
+    let mut rr = RuversiRunner::new();
+    let fp = rr.fingerprint();
+    let cfg = EngineConfig {
+        args : Some(parse_args_tag("--silent, \"--name=a,b\"").unwrap()),
+        depth : Some(5),
+        timeout : Some(Duration::from_secs(3)),
+        ..Default::default()
+    };
+    rr.configure(&cfg).unwrap();
+    assert_eq!(rr.args, vec!["--silent", "--name=a,b"]);
+    assert_eq!(rr.depth, Some(5));
+    assert_eq!(rr.timeout, Some(Duration::from_secs(3)));
+    assert_eq!(rr.curdir, "../ruversi");
+    assert_ne!(rr.fingerprint(), fp);
+    // 相対パスの基準が違えば別のエンジン
+    let fp = rr.fingerprint();
+    rr.curdir = String::from("../other");
+    assert_ne!(rr.fingerprint(), fp);
+    assert!(parse_args_tag("a,\"b").is_err());
+    // 読み切りのdepthは設定のdepthで浅くならない
+    let cmd = rr.children_command("8/8/8/8/8/8/8/8 b", 120);
+    let args = cmd.get_args().map(|a| a.to_string_lossy().into_owned()).collect::<Vec<_>>();
+    let i = args.iter().position(|a| a == "--depth").unwrap();
+    assert_eq!(args[i + 1], "120");
+    assert_eq!(args.iter().filter(|a| *a == "--depth").count(), 1);
+}
+
+#[test]
+fn test_ruversirunner_program() {
+    // 相対パスはcurdirから、名前だけならそのまま
+    let mut rr = RuversiRunner::new();
+    assert_eq!(rr.program(),
+        std::path::PathBuf::from("../ruversi/./target/release/ruversi"));
+    rr.path = String::from("ruversi");
+    assert_eq!(rr.program(), std::path::PathBuf::from("ruversi"));
+    rr.path = String::from("/usr/bin/ruversi");
+    assert_eq!(rr.program(), std::path::PathBuf::from("/usr/bin/ruversi"));
+}
+
+#[test]
+fn test_workerpool() {
+    let pool = WorkerPool::new(4).unwrap();
+    let items = (0..100).collect::<Vec<i32>>();
+    assert_eq!(pool.map(&items, |i| i * 2),
+        items.iter().map(|i| i * 2).collect::<Vec<_>>());
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    pool.stream(&items, |i| if i % 2 == 0 {Some(i * 3)} else {None}, &tx);
+    drop(tx);
+    let mut res = rx.iter().collect::<Vec<_>>();
+    res.sort();
+    assert_eq!(res, items.iter().filter(|i| *i % 2 == 0)
+        .map(|i| i * 3).collect::<Vec<_>>());
+
+    // jobs個までのひと続きに分ける
+    let parts = pool.split(&items, |offset, c| (offset, c.to_vec()));
+    assert_eq!(parts.len(), 4);
+    assert_eq!(parts.iter().flat_map(|(_, c)| c.clone()).collect::<Vec<_>>(), items);
+    assert!(parts.iter().all(|(offset, c)| items[*offset] == c[0]));
+    assert_eq!(pool.split(&items[0..3], |_, c| c.len()), vec![1, 1, 1]);
+    assert!(pool.split(&items[0..0], |_, c| c.len()).is_empty());
+
+    // curdirが変わらない
+    let curdir = std::env::current_dir().unwrap();
+    let rr = RuversiRunner::new();
+    let _ = pool.map(&items[0..4], |_| rr.run_children("8/8/8/3Aa3/3aA3/8/8/8 b"));
+    assert_eq!(std::env::current_dir().unwrap(), curdir);
+}
+
+#[cfg(not(target_os="windows"))]
+#[test]
+fn test_wait_output() {
+    let child = Command::new("sh").arg("-c").arg("echo out; echo err 1>&2")
+        .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    assert_eq!(wait_output(child, Some(Duration::from_secs(10))),
+        Ok((String::from("out\n"), String::from("err\n"))));
+
+    let child = Command::new("sh").arg("-c").arg("echo slow 1>&2; sleep 10")
+        .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    let start = Instant::now();
+    let res = wait_output(child, Some(Duration::from_millis(200)));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    let err = res.unwrap_err();
+    assert!(err.starts_with("timeout after"), "{err}");
+
+    // 途中まで出力して失敗した
+    let child = Command::new("sh").arg("-c").arg("echo val,1; echo oops 1>&2; exit 3")
+        .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    let err = wait_output(child, None).unwrap_err();
+    assert!(err.starts_with("engine failed, exit status: 3.") && err.contains("oops"), "{err}");
+    // シグナルで死んだ
+    let child = Command::new("sh").arg("-c").arg("echo val,1; kill -9 $$")
+        .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    let err = wait_output(child, None).unwrap_err();
+    assert!(err.contains("signal"), "{err}");
+
+    // 1行毎に受け取る。読み終わる毎に制限時間を数え直す。
+    let script = "for i in 1 2 3 4; do sleep 0.15; echo line$i; done";
+    let mut lines = Vec::new();
+    let res = wait_output_lines(
+        Command::new("sh").arg("-c").arg(script)
+            .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap(),
+        Some(Duration::from_millis(400)), |l| {lines.push(l.to_string()); true});
+    assert_eq!(res.unwrap().0, "line1\nline2\nline3\nline4\n");
+    assert_eq!(lines, vec!["line1", "line2", "line3", "line4"]);
+    let res = wait_output_lines(
+        Command::new("sh").arg("-c").arg(script)
+            .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap(),
+        Some(Duration::from_millis(400)), |_| false);
+    assert!(res.unwrap_err().starts_with("timeout after"));
+
+    assert_eq!(with_stderr(String::from("a"), " \n"), "a");
+    assert_eq!(with_stderr(String::from("a"), "b\n"), "a stderr:\"b\"");
+}
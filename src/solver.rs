@@ -0,0 +1,195 @@
+use crate::bitboard::{self, BitBoard};
+
+/// 完全読みで扱う空きマスの上限
+pub const MAX_EMPTIES : u32 = 20;
+/// これ以下の空きマスでは手の並べ替えをしない
+const NO_ORDERING_EMPTIES : u32 = 6;
+const SCORE_MAX : i8 = 65;
+
+/// 終局時の石の差を手番側から見た値で返す。
+fn final_score(ban : &BitBoard) -> i8 {
+    ban.count() * ban.teban
+}
+
+/// 相手の着手可能数が少ない順に並べる。(fastest first)
+fn order_moves(ban : &BitBoard, moves : u64) -> Vec<(u32, BitBoard)> {
+    let mut children = bitboard::cells2vec(moves).into_iter().map(|mv| {
+        let child = ban.r#move(mv).unwrap();
+        (child.mobility().count_ones(), child)
+    }).collect::<Vec<_>>();
+    children.sort_by_key(|(n, _)| *n);
+    children
+}
+
+/// alpha-beta付きnegamax
+///
+/// # Returns
+/// 手番側から見た最終的な石の差
+fn negamax(ban : &BitBoard, alpha : i8, beta : i8) -> i8 {
+    if ban.is_last1_or_full() {
+        if ban.is_full() {return final_score(ban);}
+
+        return ban.move_mate1().0 as i8 * ban.teban;
+    }
+
+    let moves = ban.mobility();
+    if moves == 0 {
+        let passed = ban.r#move(bitboard::PASS).unwrap();
+        if passed.mobility() == 0 {
+            // 両者打てないので終局
+            return final_score(ban);
+        }
+        return -negamax(&passed, -beta, -alpha);
+    }
+
+    let mut alpha = alpha;
+    let mut best = -SCORE_MAX;
+    let mut search = |child : &BitBoard| -> bool {
+        let val = -negamax(child, -beta, -alpha);
+        if val > best {
+            best = val;
+            if val > alpha {
+                alpha = val;
+                if alpha >= beta {return true;}
+            }
+        }
+        false
+    };
+    if ban.nblank() > NO_ORDERING_EMPTIES {
+        for (_, child) in order_moves(ban, moves) {
+            if search(&child) {break;}
+        }
+    } else {
+        let mut remain = moves;
+        while remain != 0 {
+            let mv = remain.trailing_zeros() as u8;
+            remain &= remain - 1;
+            if search(&ban.r#move(mv).unwrap()) {break;}
+        }
+    }
+    best
+}
+
+/// 完全読みをする。
+///
+/// # Arguments
+/// - ban
+///   読む局面。空きマスは`MAX_EMPTIES`以下であること。
+///
+/// # Returns
+/// 最善を尽くした場合の最終的な石の差(黒 - 白)
+pub fn solve(ban : &BitBoard) -> i8 {
+    if ban.teban == bitboard::BLANK {return ban.count();}
+
+    negamax(ban, -SCORE_MAX, SCORE_MAX) * ban.teban
+}
+
+fn check_empties(ban : &BitBoard) -> Result<(), String> {
+    if ban.nblank() > MAX_EMPTIES {
+        return Err(format!(
+            "too many empty cells to solve. {} > {MAX_EMPTIES} \"{ban}\"",
+            ban.nblank()));
+    }
+    Ok(())
+}
+
+/// 子供の局面を全部完全読みする。
+/// `RuversiRunner::run_children()`の代わり。
+///
+/// # Arguments
+/// - ban 開始局面。この局面の子供の局面の情報が返る。
+///
+/// # Returns
+/// 指定した局面の子供の局面の情報(Bitboard, 最終結果, 評価値)が返る。
+/// ダブり解消の処理のために確定石の欄にゼロを入れている。
+pub fn solve_children(ban : &BitBoard)
+        -> Result<Vec<bitboard::Sample>, String> {
+    check_empties(ban)?;
+
+    let moves = match ban.genmove() {
+        None => {return Ok(Vec::new());},
+        Some(mv) => mv,
+    };
+    Ok(moves.iter().map(|&mv| {
+        let child = ban.r#move(mv).unwrap();
+        let score = solve(&child);
+        (child, 0, 0, score, score as f32)
+    }).collect())
+}
+
+#[cfg(test)]
+/// 枝刈り無しのminimax。テスト用。
+fn minimax(ban : &BitBoard) -> i8 {
+    let moves = match ban.genmove() {
+        None => {return ban.count();},
+        Some(mv) => mv,
+    };
+    if moves[0] == bitboard::PASS {
+        let passed = ban.r#move(bitboard::PASS).unwrap();
+        if passed.genmove().is_none_or(|m| m[0] == bitboard::PASS) {
+            return ban.count();
+        }
+        return minimax(&passed);
+    }
+    let vals = moves.iter().map(|&mv| minimax(&ban.r#move(mv).unwrap()));
+    if ban.teban == bitboard::SENTE {
+        vals.max().unwrap()
+    } else {
+        vals.min().unwrap()
+    }
+}
+
+#[test]
+fn test_solve_last1() {
+    let tbl = [
+        "dD/AdC/BcC/BdB/DcA/AaAe/BcC/G1 w",
+        "dD/AdC/BcC/BdB/DcA/AaAe/BcC/G1 b",
+        "H/H/H/H/H/H/H/G1 w",
+        "h/h/h/h/h/h/h/g1 w",
+    ];
+    for rfen in tbl {
+        let ban = BitBoard::try_from(rfen).unwrap();
+        assert_eq!(solve(&ban), ban.move_mate1().0 as i8, "{rfen}");
+    }
+}
+
+#[test]
+fn test_solve_full() {
+    let ban = BitBoard::try_from("H/H/H/H/H/h/h/h b").unwrap();
+    assert_eq!(solve(&ban), 16);
+    assert!(solve_children(&ban).unwrap().is_empty());
+}
+
+#[test]
+fn test_solve_vs_minimax() {
+    let tbl = [
+        "dD/AdC/BcC/BaAbAa/Af1/AaAaA1a1/BcC/G1 b",
+        "dD/AdC/BcC/BaAbB/H/AaAaA1A1/BcC/G1 w",
+        "dD/AdC/BcC/BdB/DbB/AaAcA1/BcC/G1 b",
+    ];
+    for rfen in tbl {
+        let ban = BitBoard::try_from(rfen).unwrap();
+        assert_eq!(solve(&ban), minimax(&ban), "{rfen}");
+    }
+}
+
+#[test]
+fn test_solve_children() {
+    let ban = BitBoard::try_from(
+        "dD/AdC/BcC/BaAbAa/Af1/AaAaA1a1/BcC/G1 b").unwrap();
+    let children = solve_children(&ban).unwrap();
+    let moves = ban.genmove().unwrap();
+    assert_eq!(children.len(), moves.len());
+    for ((child, fsb, fsw, score, value), mv) in children.iter().zip(moves) {
+        assert!(*child == ban.r#move(mv).unwrap());
+        assert_eq!((*fsb, *fsw), (0, 0));
+        assert_eq!(*score, minimax(child));
+        assert_eq!(*value, *score as f32);
+    }
+    // 黒番なので子供の最大値が親の値
+    let best = children.iter().map(|(_, _, _, s, _)| *s).max().unwrap();
+    assert_eq!(best, solve(&ban));
+
+    let ban = BitBoard::new();
+    assert!(solve_children(&ban).is_err());
+}
@@ -0,0 +1,192 @@
+use std::io::Read;
+
+const BLOCK_SIZE : usize = 512;
+
+/// tar(ustar)のファイルを先頭から順に読む。
+/// 通常のファイルだけを(名前, 中身)として返す。
+pub struct TarReader<R : Read> {
+    reader : R,
+    /// GNUの長い名前やpaxのpathで次のファイル名を上書きする
+    longname : Option<String>,
+    finished : bool,
+}
+
+/// 8進数の欄を読む。大きな値のbase-256表記にも対応する。
+fn parse_octal(field : &[u8]) -> Result<u64, String> {
+    if field.first().is_some_and(|b| b & 0x80 != 0) {
+        // base-256
+        return Ok(field[1..].iter().fold(0u64, |acc, &b| (acc << 8) | b as u64));
+    }
+    let txt = field.iter().take_while(|&&b| b != 0)
+        .map(|&b| b as char).collect::<String>();
+    let txt = txt.trim();
+    if txt.is_empty() {return Ok(0);}
+
+    u64::from_str_radix(txt, 8).map_err(|e| format!("invalid octal \"{txt}\" : {e}"))
+}
+
+/// NULで終わる文字列の欄を読む。
+fn parse_str(field : &[u8]) -> String {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..len]).to_string()
+}
+
+/// paxの拡張ヘッダからpathを探す。"<len> path=<name>\n"
+fn pax_path(data : &[u8]) -> Option<String> {
+    let txt = String::from_utf8_lossy(data);
+    txt.lines().find_map(|l| {
+        let (_, kv) = l.split_once(' ')?;
+        kv.strip_prefix("path=").map(|p| p.to_string())
+    })
+}
+
+impl<R : Read> TarReader<R> {
+    pub fn new(reader : R) -> Self {
+        Self {reader, longname : None, finished : false}
+    }
+
+    fn read_block(&mut self, buf : &mut [u8]) -> Result<bool, String> {
+        let mut pos = 0;
+        while pos < buf.len() {
+            match self.reader.read(&mut buf[pos..]) {
+                Ok(0) => {
+                    if pos == 0 {return Ok(false);}
+                    return Err(String::from("unexpected end of tar"));
+                },
+                Ok(n) => {pos += n;},
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {},
+                Err(e) => {return Err(format!("{e}"));},
+            }
+        }
+        Ok(true)
+    }
+
+    /// 中身を読む。512byte単位の余りも読み飛ばす。
+    fn read_data(&mut self, size : u64) -> Result<Vec<u8>, String> {
+        let padded = size.div_ceil(BLOCK_SIZE as u64) * BLOCK_SIZE as u64;
+        let mut data = vec![0u8 ; padded as usize];
+        if !self.read_block(&mut data)? && padded > 0 {
+            return Err(String::from("unexpected end of tar"));
+        }
+        data.truncate(size as usize);
+        Ok(data)
+    }
+
+    fn next_entry(&mut self) -> Result<Option<(String, Vec<u8>)>, String> {
+        let mut header = [0u8 ; BLOCK_SIZE];
+        loop {
+            if !self.read_block(&mut header)? {return Ok(None);}
+            // 空のブロックで終わり
+            if header.iter().all(|&b| b == 0) {return Ok(None);}
+
+            let chksum = parse_octal(&header[148..156])?;
+            let sum = header.iter().enumerate().map(|(i, &b)| {
+                if (148..156).contains(&i) {b' ' as u64} else {b as u64}
+            }).sum::<u64>();
+            if chksum != sum {
+                return Err(format!("tar header checksum mismatch {chksum} != {sum}"));
+            }
+
+            let size = parse_octal(&header[124..136])?;
+            let typeflag = header[156];
+            let data = self.read_data(size)?;
+            match typeflag {
+                b'0' | 0 | b'7' => {
+                    let name = match self.longname.take() {
+                        Some(name) => name,
+                        None => {
+                            let name = parse_str(&header[0..100]);
+                            let prefix = if &header[257..262] == b"ustar" {
+                                parse_str(&header[345..500])
+                            } else {
+                                String::new()
+                            };
+                            if prefix.is_empty() {name} else {format!("{prefix}/{name}")}
+                        },
+                    };
+                    return Ok(Some((name, data)));
+                },
+                b'L' => {
+                    self.longname = Some(parse_str(&data));
+                },
+                b'x' => {
+                    if let Some(path) = pax_path(&data) {
+                        self.longname = Some(path);
+                    }
+                },
+                _ => {
+                    // ディレクトリ、リンク等は読み飛ばす
+                    self.longname = None;
+                },
+            }
+        }
+    }
+}
+
+impl<R : Read> Iterator for TarReader<R> {
+    type Item = Result<(String, Vec<u8>), String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {return None;}
+
+        match self.next_entry() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            },
+            Err(msg) => {
+                self.finished = true;
+                Some(Err(msg))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+/// テスト用にtarを作る。
+pub fn build_tar(entries : &[(&str, &[u8])]) -> Vec<u8> {
+    let mut tar = Vec::new();
+    for (name, data) in entries {
+        let mut header = [0u8 ; BLOCK_SIZE];
+        header[0..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[148..156].copy_from_slice(b"        ");
+        let sum = header.iter().map(|&b| b as u32).sum::<u32>();
+        header[148..155].copy_from_slice(format!("{sum:06o}\0").as_bytes());
+        tar.extend_from_slice(&header);
+        tar.extend_from_slice(data);
+        tar.resize(tar.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+    }
+    tar.resize(tar.len() + BLOCK_SIZE * 2, 0);
+    tar
+}
+
+#[test]
+fn test_tarreader() {
+    let tar = build_tar(&[("a/kifu1.txt", b"hello"), ("a/kifu2.txt", &[b'x' ; 1000])]);
+    let entries = TarReader::new(tar.as_slice()).collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0], (String::from("a/kifu1.txt"), b"hello".to_vec()));
+    assert_eq!(entries[1].0, "a/kifu2.txt");
+    assert_eq!(entries[1].1.len(), 1000);
+
+    // 壊れたヘッダ
+    let mut broken = tar.clone();
+    broken[0] = b'b';
+    let mut reader = TarReader::new(broken.as_slice());
+    assert!(reader.next().unwrap().is_err());
+    assert!(reader.next().is_none());
+
+    // 途中で切れている
+    let truncated = &tar[..BLOCK_SIZE + 100];
+    let mut reader = TarReader::new(truncated);
+    assert!(reader.next().unwrap().is_err());
+
+    assert_eq!(parse_octal(b"0000644\0").unwrap(), 0o644);
+    assert_eq!(parse_octal(&[0x80, 0, 0, 1, 0]).unwrap(), 256);
+}
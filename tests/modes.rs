@@ -0,0 +1,536 @@
+//! 偽ruversiを使って各モードを最初から最後まで動かすテスト。
+//!
+//! 出力は tests/fixtures/expected/ 以下と比較する。
+//! 出力が変わるのが正しい時は`INCUVERSI_BLESS=1 cargo test`で書き換える。
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const FIXTURES : &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+/// 作業用のディレクトリを作って棋譜と設定ファイルを置く。
+fn workspace(name : &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(
+        format!("incuversi_it_{name}_{}", std::process::id()));
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    std::fs::create_dir_all(dir.join("kifu")).unwrap();
+    for entry in std::fs::read_dir(Path::new(FIXTURES).join("kifu")).unwrap() {
+        let path = entry.unwrap().path();
+        std::fs::copy(&path, dir.join("kifu").join(path.file_name().unwrap())).unwrap();
+    }
+    std::fs::write(dir.join("engine.cfg"), format!(
+        "curdir:{}\npath:{}\nevfile:none\n",
+        dir.display(), env!("CARGO_BIN_EXE_mock_ruversi"))).unwrap();
+    dir
+}
+
+/// incuversiを動かす。失敗したらpanicする。
+fn run(dir : &Path, args : &[&str]) -> String {
+    run_env(dir, args, &[])
+}
+
+/// 環境変数を足してincuversiを動かす。
+fn run_env(dir : &Path, args : &[&str], envs : &[(&str, &str)]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_incuversi"))
+        .current_dir(dir)
+        .envs(envs.iter().copied())
+        .args(args)
+        .args(["--no-progressbar", "--ru-config", "engine.cfg"])
+        .output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    assert!(output.status.success(), "{args:?}\nstdout:{stdout}\nstderr:{}",
+        String::from_utf8_lossy(&output.stderr));
+    stdout
+}
+
+/// incuversiを動かして失敗するのを確かめる。
+///
+/// # Returns
+/// 標準エラー出力
+fn run_err(dir : &Path, args : &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_incuversi"))
+        .current_dir(dir)
+        .args(args)
+        .args(["--no-progressbar", "--ru-config", "engine.cfg"])
+        .output().unwrap();
+    assert!(!output.status.success(), "{args:?}");
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+/// 期待する出力と比べる。
+fn assert_golden(actual : &Path, golden : &str) {
+    let path = Path::new(FIXTURES).join("expected").join(golden);
+    let txt = std::fs::read_to_string(actual)
+        .unwrap_or_else(|e| panic!("{e} : {actual:?}"));
+    if std::env::var_os("INCUVERSI_BLESS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, &txt).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("{e} : {path:?}"));
+    assert_eq!(txt, expected, "{actual:?} != {path:?}");
+}
+
+/// ディレクトリにあるファイルを全部期待する出力と比べる。
+fn assert_golden_dir(actual : &Path, golden : &str) {
+    let mut files = std::fs::read_dir(actual).unwrap().map(|e| {
+        e.unwrap().file_name().to_string_lossy().to_string()
+    }).collect::<Vec<_>>();
+    files.sort();
+    assert!(!files.is_empty(), "no output in {actual:?}");
+    for fname in files.iter() {
+        assert_golden(&actual.join(fname), &format!("{golden}/{fname}"));
+    }
+    if std::env::var_os("INCUVERSI_BLESS").is_some() {return;}
+
+    let mut expected = std::fs::read_dir(Path::new(FIXTURES).join("expected").join(golden))
+        .unwrap().map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(files, expected);
+}
+
+/// 期待する出力を入力としてディレクトリに置く。
+fn put_golden(dir : &Path, golden : &str) {
+    let src = Path::new(FIXTURES).join("expected").join(golden);
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::copy(&src, dir.join(src.file_name().unwrap())).unwrap();
+}
+
+#[test]
+fn test_mode_kifu() {
+    let dir = workspace("kifu");
+    run(&dir, &["kifu", "-m", "6", "--kifudir", "kifu"]);
+    assert_golden(&dir.join("mate5.txt"), "kifu/mate5.txt");
+
+    // 並列に動かしても同じ
+    std::fs::remove_file(dir.join("mate5.txt")).unwrap();
+    run(&dir, &["kifu", "-m", "6", "--kifudir", "kifu", "--jobs", "3"]);
+    assert_golden(&dir.join("mate5.txt"), "kifu/mate5.txt");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_edax_workers() {
+    let dir = workspace("edax");
+    std::fs::write(dir.join("engine.cfg"), format!(
+        "engine:edax\ncurdir:{}\npath:{}\nevfile:none\n",
+        dir.display(), env!("CARGO_BIN_EXE_mock_edax"))).unwrap();
+    let log = dir.join("edax.log");
+    let log_env = log.to_str().unwrap();
+    run_env(&dir, &["kifu", "-m", "6", "--kifudir", "kifu", "--jobs", "2"],
+        &[("MOCK_EDAX_LOG", log_env)]);
+    assert_golden(&dir.join("mate5.txt"), "kifu/mate5.txt");
+    // 局面毎ではなくワーカー毎に1回だけ起動する
+    let launches = std::fs::read_to_string(&log).unwrap().lines().count();
+    assert!((1..=2).contains(&launches), "{launches}");
+
+    // まとめて読むのに失敗したら残りを1局面ずつ読み直す。
+    // 2局面分の子供の局面をまとめたワーカーだけが途中で落ちる。
+    std::fs::remove_file(dir.join("mate5.txt")).unwrap();
+    std::fs::remove_file(&log).unwrap();
+    run_env(&dir, &["kifu", "-m", "6", "--kifudir", "kifu", "--jobs", "2"],
+        &[("MOCK_EDAX_LOG", log_env), ("MOCK_EDAX_CRASH_AFTER", "6")]);
+    assert_golden(&dir.join("mate5.txt"), "kifu/mate5.txt");
+    let launches = std::fs::read_to_string(&log).unwrap().lines().count();
+    assert_eq!(launches, 3);
+    assert!(!dir.join("quarantine.txt").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_pv_samples() {
+    let dir = workspace("pv");
+    run(&dir, &["kifu", "-m", "6", "--kifudir", "kifu", "--pv-samples", "-o", "out"]);
+    // 読み筋を出しても子供の局面は変わらない
+    assert_golden(&dir.join("mate5.txt"), "kifu/mate5.txt");
+    assert_golden_dir(&dir.join("out"), "pv");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mode_mate() {
+    let dir = workspace("mate");
+    put_golden(&dir.join("mates"), "kifu/mate5.txt");
+    run(&dir, &["mate", "-m", "5", "--kifudir", "mates"]);
+    assert_golden(&dir.join("mate4.txt"), "mate/mate4.txt");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mode_spread() {
+    let dir = workspace("spread");
+    run(&dir, &["spread", "-m", "6", "--kifudir", "kifu", "-o", "out"]);
+    assert_golden_dir(&dir.join("out"), "spread");
+
+    // 親の数は局面のファイルに入れない
+    let lines = std::fs::read_to_string(dir.join("out/mate5_spread.txt")).unwrap();
+    let counts = std::fs::read_to_string(dir.join("out/spread_count.csv")).unwrap();
+    assert_eq!(lines.lines().count(), counts.lines().count());
+    for (line, count) in lines.lines().zip(counts.lines()) {
+        let (rfen, _) = line.split_once(',').unwrap();
+        assert_eq!(line.split(',').count(), 2, "{line}");
+        assert!(count.starts_with(&format!("{rfen},")), "{count}");
+    }
+
+    // valueの列はエンジンの評価値
+    let cfg = std::fs::read_to_string(dir.join("engine.cfg")).unwrap();
+    std::fs::write(dir.join("engine.cfg"), cfg + "args:--mock-offset,0.25\n").unwrap();
+    run(&dir, &["spread", "-m", "6", "--kifudir", "kifu", "-o", "value", "--value-column"]);
+    let valued = std::fs::read_to_string(dir.join("value/mate5_spread.txt")).unwrap();
+    for (line, exp) in valued.lines().zip(lines.lines()) {
+        let elem = line.split(',').collect::<Vec<_>>();
+        let (rfen, score) = exp.split_once(',').unwrap();
+        assert_eq!(elem.len(), 3, "{line}");
+        assert_eq!(elem[0], rfen);
+        assert_eq!(elem[2].parse::<f32>().unwrap(), score.parse::<f32>().unwrap() + 0.25);
+    }
+
+    // バイナリでも親の数は別のファイルに出る
+    run(&dir, &["spread", "-m", "6", "--kifudir", "kifu", "-o", "bin", "--format", "binary"]);
+    assert!(dir.join("bin/mate5_spread.bin").is_file());
+    assert_eq!(std::fs::read_to_string(dir.join("bin/spread_count.csv")).unwrap(), counts);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_spread_depth() {
+    let dir = workspace("spread_depth");
+    run(&dir, &["spread", "-m", "6", "--kifudir", "kifu", "-o", "out",
+        "--spread-depth", "2", "--jobs", "3"]);
+    assert_golden_dir(&dir.join("out"), "spread_depth");
+    let mut keys = std::collections::HashSet::new();
+    for fname in ["mate5_spread.txt", "mate4_spread.txt"] {
+        let txt = std::fs::read_to_string(dir.join("out").join(fname)).unwrap();
+        for line in txt.lines() {
+            let elem = line.split(',').collect::<Vec<_>>();
+            assert_eq!(elem.len(), 2, "{line}");
+            assert!(keys.insert(elem[0].to_string()), "{line}");
+        }
+    }
+    let counts = std::fs::read_to_string(dir.join("out/spread_count.csv")).unwrap();
+    assert_eq!(counts.lines().count(), keys.len());
+
+    // 同じ棋譜を2回読んでも同じ親は1回しか展開しないので親の数は増えない
+    run(&dir, &["spread", "-m", "6", "--kifudir", "kifu,kifu", "-o", "twice",
+        "--spread-depth", "2", "--jobs", "3"]);
+    assert_golden_dir(&dir.join("twice"), "spread_depth");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mode_dedup() {
+    let dir = workspace("dedup");
+    put_golden(&dir.join("mates"), "kifu/mate5.txt");
+    run(&dir, &["dedup", "--kifudir", "mates"]);
+    assert_golden_dir(&dir.join("mates"), "dedup");
+    let stderr = run_err(&dir, &["dedup", "--kifudir", "mates", "--format", "binary"]);
+    assert!(stderr.contains("--format binary"), "{stderr}");
+    assert_golden_dir(&dir.join("mates"), "dedup");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mode_shorten() {
+    let dir = workspace("shorten");
+    put_golden(&dir.join("mates"), "kifu/mate5.txt");
+    run(&dir, &["shorten", "-m", "6", "--kifudir", "mates", "-o", "out"]);
+    assert_golden_dir(&dir.join("out"), "shorten");
+    let stderr = run_err(&dir, &["shorten", "-m", "6", "--kifudir", "mates", "-o", "bin",
+        "--format", "binary"]);
+    assert!(stderr.contains("--format binary"), "{stderr}");
+    assert!(!dir.join("bin").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mode_validate() {
+    let dir = workspace("validate");
+    put_golden(&dir.join("mates"), "kifu/mate5.txt");
+    run(&dir, &["validate", "-m", "6", "--kifudir", "mates", "-o", "out"]);
+    assert_golden_dir(&dir.join("out"), "validate");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mode_lint() {
+    let dir = workspace("lint");
+    let stdout = run(&dir, &["lint", "--kifudir", "kifu"]);
+    assert!(stdout.ends_with("0 / 3 file(s) have errors.\n"), "{stdout}");
+
+    std::fs::write(dir.join("kifu/broken.txt"), "1 @@ a1 8/8/8/3Aa3/3aA3/8/8/8 b\n").unwrap();
+    let stdout = run(&dir, &["lint", "--kifudir", "kifu"]);
+    assert!(stdout.contains("kifu/broken.txt: 2 error(s)\n  line 1: illegal move a1\n"),
+        "{stdout}");
+    assert!(stdout.ends_with("1 / 4 file(s) have errors.\n"), "{stdout}");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_policy() {
+    let dir = workspace("policy");
+    run(&dir, &["kifu", "-m", "6", "--kifudir", "kifu", "-o", "out",
+        "--policy", "--policy-temperature", "2"]);
+    assert_golden(&dir.join("mate5.txt"), "kifu/mate5.txt");
+    assert_golden_dir(&dir.join("out"), "policy");
+    // 親の局面毎に64マス+パスの評価値と確率
+    let txt = std::fs::read_to_string(dir.join("out/policy6.csv")).unwrap();
+    for line in txt.lines() {
+        let elem = line.split(',').collect::<Vec<_>>();
+        assert_eq!(elem.len(), 1 + 65 * 2, "{line}");
+        let sum = elem[66..].iter().map(|p| p.parse::<f32>().unwrap()).sum::<f32>();
+        assert!((sum - 1.0).abs() < 1e-2, "{line}");
+    }
+
+    // 方策のファイルと同じディレクトリの局面を読んでも方策は局面として読まない
+    std::fs::rename(dir.join("out"), dir.join("mates")).unwrap();
+    std::fs::copy(dir.join("mate5.txt"), dir.join("mates/mate5.txt")).unwrap();
+    run(&dir, &["mate", "-m", "5", "--kifudir", "mates"]);
+    assert_golden(&dir.join("mate4.txt"), "mate/mate4.txt");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_value_column() {
+    // 評価値を0.4ずらして丸め方の違いを見る
+    let dir = workspace("value");
+    put_golden(&dir.join("mates"), "kifu/mate5.txt");
+    let cfg = std::fs::read_to_string(dir.join("engine.cfg")).unwrap();
+    std::fs::write(dir.join("engine.cfg"), cfg + "args:--mock-offset,-0.4\n").unwrap();
+    for (rounding, golden) in [
+            ("truncate", "value/mate4_truncate.txt"),
+            ("round", "mate/mate4.txt"),
+            ("floor-by-sign", "value/mate4_floor-by-sign.txt")] {
+        run(&dir, &["mate", "-m", "5", "--kifudir", "mates", "--value-column",
+            "--rounding", rounding]);
+        let txt = std::fs::read_to_string(dir.join("mate4.txt")).unwrap();
+        for line in txt.lines().filter(|l| l.contains(',')) {
+            let elem = line.split(',').collect::<Vec<_>>();
+            assert_eq!(elem.len(), 3, "{line}");
+            let value = elem[2].parse::<f32>().unwrap();
+            // 色を入れ替えた局面ではずれの符号も変わる
+            assert!(((value - value.round()).abs() - 0.4).abs() < 1e-4, "{line}");
+        }
+        // 評価値の列を外すと丸めた値だけが残る
+        let stripped = txt.lines().map(|l| {
+            if l.contains(',') {&l[..l.rfind(',').unwrap()]} else {l}
+        }).collect::<Vec<_>>().join("\n") + "\n";
+        std::fs::write(dir.join("mate4.txt"), stripped).unwrap();
+        assert_golden(&dir.join("mate4.txt"), golden);
+        std::fs::remove_file(dir.join("mate4.txt")).unwrap();
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mode_consistency() {
+    let dir = workspace("consistency");
+    put_golden(&dir.join("mates"), "kifu/mate5.txt");
+    put_golden(&dir.join("mates"), "mate/mate4.txt");
+    let stdout = run(&dir, &["consistency", "--kifudir", "mates"]);
+    assert!(stdout.contains(
+        "mate5 vs mate4 in mates: 0 contradiction(s), 0 with missing children / 17 position(s).\n"),
+        "{stdout}");
+    assert!(stdout.ends_with("0 / 1 pair(s) of mate files have problems.\n"), "{stdout}");
+
+    // 値を1つ変えて子供を1つ消す
+    let mate5 = std::fs::read_to_string(dir.join("mates/mate5.txt")).unwrap();
+    let (rfen, score) = mate5.lines().nth(1).unwrap().split_once(',').unwrap();
+    let broken = mate5.replacen(&format!("{rfen},{score}\n"), &format!("{rfen},{}\n",
+        score.parse::<i8>().unwrap() + 2), 1);
+    std::fs::write(dir.join("mates/mate5.txt"), broken).unwrap();
+    let mate4 = std::fs::read_to_string(dir.join("mates/mate4.txt")).unwrap();
+    let removed = mate4.lines().last().unwrap().split_once(',').unwrap().0.to_string();
+    let mate4 = mate4.lines().filter(|l| !l.starts_with(&removed)).collect::<Vec<_>>()
+        .join("\n") + "\n";
+    std::fs::write(dir.join("mates/mate4.txt"), mate4).unwrap();
+    let stdout = run(&dir, &["consistency", "--kifudir", "mates"]);
+    assert!(stdout.contains(&format!("mates/mate5.txt: {rfen},{} : children say {score}\n",
+        score.parse::<i8>().unwrap() + 2)), "{stdout}");
+    assert!(stdout.contains(&format!("  {removed}\n")), "{stdout}");
+    assert!(stdout.contains("mate5 vs mate4 in mates: 1 contradiction(s), 1 with missing children"),
+        "{stdout}");
+    assert!(stdout.ends_with("1 / 1 pair(s) of mate files have problems.\n"), "{stdout}");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mode_generate() {
+    let dir = workspace("generate");
+    run(&dir, &["generate", "-m", "54", "--playouts", "40", "--seed", "3", "-o", "out"]);
+    assert_golden_dir(&dir.join("out"), "generate");
+
+    // 並列に動かしても同じ
+    std::fs::remove_dir_all(dir.join("out")).unwrap();
+    run(&dir, &["generate", "-m", "54", "--playouts", "40", "--seed", "3", "-o", "out",
+        "--jobs", "3"]);
+    assert_golden_dir(&dir.join("out"), "generate");
+
+    // 棋譜の局面からepsilon-greedyで進めてmateモードで値を付ける
+    run(&dir, &["generate", "-m", "30", "--playouts", "10", "--epsilon", "0.5",
+        "--kifudir", "kifu", "-o", "greedy"]);
+    let txt = std::fs::read_to_string(dir.join("greedy/mate30_generate.txt")).unwrap();
+    assert!(!txt.is_empty());
+    for line in txt.lines() {
+        let (rfen, _) = line.split_once(',').unwrap();
+        assert_eq!(rfen.chars().filter_map(|c| c.to_digit(10)).sum::<u32>(), 30, "{line}");
+    }
+    run(&dir, &["mate", "-m", "30", "--kifudir", "greedy"]);
+    let mate29 = std::fs::read_to_string(dir.join("mate29.txt")).unwrap();
+    assert!(mate29.lines().any(|l| l.contains(',')));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mode_rollout() {
+    let dir = workspace("rollout");
+    put_golden(&dir.join("mates"), "kifu/mate5.txt");
+    run(&dir, &["rollout", "--kifudir", "mates", "--rollouts", "20", "--seed", "5",
+        "-o", "out"]);
+    assert_golden_dir(&dir.join("out"), "rollout");
+
+    // 並列に動かしても同じ
+    std::fs::remove_dir_all(dir.join("out")).unwrap();
+    run(&dir, &["rollout", "--kifudir", "mates", "--rollouts", "20", "--seed", "5",
+        "-o", "out", "--jobs", "3"]);
+    assert_golden_dir(&dir.join("out"), "rollout");
+
+    run(&dir, &["rollout", "--kifudir", "mates", "--rollouts", "20",
+        "--rollout-policy", "mobility", "-o", "mobility"]);
+    let txt = std::fs::read_to_string(dir.join("mobility/mate5_rollout.txt")).unwrap();
+    for line in txt.lines() {
+        let elem = line.split(',').collect::<Vec<_>>();
+        assert_eq!(elem.len(), 7, "{line}");
+        let wdl = elem[4..].iter().map(|e| e.parse::<f32>().unwrap()).sum::<f32>();
+        assert!((wdl - 1.0).abs() < 0.01, "{line}");
+    }
+
+    // 固定長のレコードでは統計の列が消えるので断る。0局も断る。
+    let stderr = run_err(&dir, &["rollout", "--kifudir", "mates", "-o", "bin",
+        "--format", "binary"]);
+    assert!(stderr.contains("--format binary"), "{stderr}");
+    assert!(!dir.join("bin").exists());
+    let stderr = run_err(&dir, &["rollout", "--kifudir", "mates", "--rollouts", "0",
+        "-o", "zero"]);
+    assert!(stderr.contains("--rollouts"), "{stderr}");
+    assert!(!dir.join("zero").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mode_midgame() {
+    let dir = workspace("midgame");
+    run(&dir, &["midgame", "--kifudir", "kifu", "--min-empties", "20", "--max-empties", "22",
+        "-o", "out"]);
+    assert_golden_dir(&dir.join("out"), "midgame");
+
+    // 終局までの手数で割り引く
+    run(&dir, &["midgame", "--kifudir", "kifu", "--min-empties", "20", "--max-empties", "22",
+        "--discount", "0.9", "--value-column", "--rounding", "round", "-o", "discount"]);
+    assert_golden_dir(&dir.join("discount"), "midgame_discount");
+
+    // 進行度で選ぶ
+    run(&dir, &["midgame", "--kifudir", "kifu", "--progress", "2", "-o", "progress"]);
+    let files = std::fs::read_dir(dir.join("progress")).unwrap().map(|e| {
+        e.unwrap().file_name().to_string_lossy().to_string()
+    }).collect::<Vec<_>>();
+    assert!(!files.is_empty());
+    for fname in files.iter() {
+        let n = fname.trim_start_matches("mate").trim_end_matches("_midgame.txt")
+            .parse::<u32>().unwrap();
+        assert!(n <= 20, "{fname}");
+    }
+
+    // 範囲が逆ならpanicせずにエラーで終わる
+    let stderr = run_err(&dir, &["midgame", "--kifudir", "kifu", "--min-empties", "30",
+        "--max-empties", "20", "-o", "reversed"]);
+    assert!(stderr.contains("--min-empties 30 > --max-empties 20"), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_engine_failure() {
+    // 全部失敗しても最後まで動いて失敗した局面を書き出す
+    let dir = workspace("failure");
+    put_golden(&dir.join("mates"), "kifu/mate5.txt");
+    let cfg = std::fs::read_to_string(dir.join("engine.cfg")).unwrap();
+    std::fs::write(dir.join("engine.cfg"), cfg + "args:--mock-fail\n").unwrap();
+    run(&dir, &["validate", "-m", "6", "--kifudir", "mates", "-o", "out",
+        "--retries", "1", "--log", "log.txt"]);
+    let quarantine = std::fs::read_to_string(dir.join("out/quarantine.txt")).unwrap();
+    let expected = std::fs::read_to_string(dir.join("mates/mate5.txt")).unwrap();
+    assert_eq!(quarantine.lines().count(),
+        expected.lines().filter(|l| !l.starts_with('#')).count());
+    let log = std::fs::read_to_string(dir.join("log.txt")).unwrap();
+    assert!(log.contains("try 2/2 failed"), "{log}");
+    assert!(log.contains("stderr:\"mock failure.\""), "{log}");
+    assert!(log.contains("position(s) failed, see"), "{log}");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_engine_crash() {
+    // 途中まで出力して落ちたら結果を使わずにやり直して、諦めたら書き出す
+    let dir = workspace("crash");
+    put_golden(&dir.join("mates"), "kifu/mate5.txt");
+    let cfg = std::fs::read_to_string(dir.join("engine.cfg")).unwrap();
+    std::fs::write(dir.join("engine.cfg"), cfg + "args:--mock-crash\n").unwrap();
+    run(&dir, &["mate", "-m", "5", "--kifudir", "mates", "--retries", "1",
+        "--log", "log.txt"]);
+    let log = std::fs::read_to_string(dir.join("log.txt")).unwrap();
+    assert!(log.contains("try 2/2 failed"), "{log}");
+    assert!(log.contains("engine failed, exit status: 2."), "{log}");
+    assert!(log.contains("stderr:\"mock crash.\""), "{log}");
+    // 対称な局面をまとめて呼んでも入力の向きのまま書き出す
+    let quarantine = std::fs::read_to_string(dir.join("quarantine.txt")).unwrap();
+    let input = std::fs::read_to_string(dir.join("mates/mate5.txt")).unwrap();
+    let input = input.lines().filter_map(|l| l.split_once(',')).map(|(rfen, _)| rfen)
+        .collect::<Vec<_>>();
+    assert_eq!(quarantine.lines().count(), input.len());
+    for rfen in quarantine.lines() {
+        assert!(input.contains(&rfen), "{rfen}");
+    }
+    // 子供の局面を1つも書かない
+    let mate4 = std::fs::read_to_string(dir.join("mate4.txt")).unwrap_or_default();
+    assert!(!mate4.lines().any(|l| l.contains(',')), "{mate4}");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_engine_timeout() {
+    let dir = workspace("timeout");
+    put_golden(&dir.join("mates"), "kifu/mate5.txt");
+    let cfg = std::fs::read_to_string(dir.join("engine.cfg")).unwrap();
+    std::fs::write(dir.join("engine.cfg"), cfg + "args:--mock-sleep,10000\n").unwrap();
+    let start = std::time::Instant::now();
+    run(&dir, &["mate", "-m", "5", "--kifudir", "mates",
+        "--retries", "0", "--timeout", "0.1", "--jobs", "4", "--log", "log.txt"]);
+    assert!(start.elapsed() < std::time::Duration::from_secs(60));
+    let log = std::fs::read_to_string(dir.join("log.txt")).unwrap();
+    assert!(log.contains("timeout after"), "{log}");
+    assert!(dir.join("quarantine.txt").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_engine_cache() {
+    let dir = workspace("cache");
+    let cfg = std::fs::read_to_string(dir.join("engine.cfg")).unwrap();
+    std::fs::write(dir.join("engine.cfg"), cfg + "cache:cache\n").unwrap();
+    run(&dir, &["kifu", "-m", "6", "--kifudir", "kifu"]);
+    assert_golden(&dir.join("mate5.txt"), "kifu/mate5.txt");
+    let nfiles = std::fs::read_dir(dir.join("cache")).unwrap().count();
+    assert_eq!(nfiles, 1);
+
+    // 2回目はエンジンを呼ばない
+    std::fs::remove_file(dir.join("mate5.txt")).unwrap();
+    run_env(&dir, &["kifu", "-m", "6", "--kifudir", "kifu", "--retries", "0"],
+        &[("MOCK_RUVERSI_FAIL", "1")]);
+    assert_golden(&dir.join("mate5.txt"), "kifu/mate5.txt");
+    assert!(!dir.join("quarantine.txt").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}